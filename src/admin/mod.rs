@@ -0,0 +1,544 @@
+use crate::{
+    app::{self, AppState},
+    attachment,
+    config::Config,
+    device::{register, Device},
+    logging::LogTarget,
+    net::ListenAddress,
+    password::Password,
+    snapshot,
+    tor::client_auth::{self, PAIRING_CLIENT_NAME},
+};
+use anyhow::{anyhow, bail, Result};
+use keybear_core::{crypto::StaticSecretExt, types::NeedsVerificationDevice};
+use std::{
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::Path,
+};
+use x25519_dalek::StaticSecret;
+
+/// Get the devices awaiting verification with their verification codes.
+pub async fn pending_devices(state: &AppState) -> Result<Vec<NeedsVerificationDevice>> {
+    Ok(state
+        .verification_devices()
+        .await?
+        .to_needs_verification_vec())
+}
+
+/// Register a device that is awaiting verification.
+///
+/// When a verification code is passed it must match the code of the device.
+pub async fn approve_device(
+    state: &AppState,
+    id: &str,
+    verification_code: Option<&str>,
+) -> Result<Device> {
+    if let Some(verification_code) = verification_code {
+        let verification_devices = state.verification_devices().await?;
+        match verification_devices.find(id) {
+            Some((code, _)) if code == verification_code => (),
+            Some(_) => bail!("Device verification code mismatch"),
+            None => bail!("Device with ID \"{}\" is not awaiting verification", id),
+        }
+    }
+
+    register::approve(state, id).await
+}
+
+/// Remove a device that is awaiting verification without registering it.
+pub async fn reject_device(state: &AppState, id: &str) -> Result<Device> {
+    let mut verification_devices = state.verification_devices().await?;
+    let device = verification_devices
+        .remove(id)
+        .ok_or_else(|| anyhow!("Device with ID \"{}\" is not awaiting verification", id))?;
+    state
+        .set_verification_devices(verification_devices)
+        .await
+        .map_err(|err| anyhow!("Error setting verification devices on database: {}", err))?;
+
+    Ok(device)
+}
+
+/// Remove a registered device so it can't access the server anymore.
+pub async fn revoke_device(state: &AppState, id: &str) -> Result<Device> {
+    let mut devices = state.devices().await?;
+    let device = devices
+        .remove(id)
+        .ok_or_else(|| anyhow!("Device with ID \"{}\" is not registered", id))?;
+    state.set_devices(devices).await?;
+
+    client_auth::revoke(&state.config, id)?;
+
+    Ok(device)
+}
+
+/// Change the name of a registered device.
+pub async fn rename_device(state: &AppState, id: &str, name: &str) -> Result<Device> {
+    let mut device = state.device(id).await?;
+    device.set_name(name);
+    state.set_device(&device).await?;
+
+    Ok(device)
+}
+
+/// Remove a password with its attachments.
+pub async fn delete_password(state: &AppState, id: &str) -> Result<Password> {
+    let mut passwords = state.passwords().await?;
+    let password = passwords
+        .remove(id)
+        .ok_or_else(|| anyhow!("Password with ID \"{}\" does not exist", id))?;
+    state.set_passwords(&passwords).await?;
+
+    attachment::delete_entry_attachments(&*state.storage.lock().await, id).await?;
+
+    Ok(password)
+}
+
+/// Replace the secret key of the server with a newly generated one.
+///
+/// The devices can't communicate with the new key so all devices are removed and have to register
+/// again. Returns the amount of removed devices.
+pub async fn rotate_key(state: &AppState) -> Result<usize> {
+    let devices = state.devices().await?;
+    let removed = devices.iter().count();
+
+    StaticSecret::new_with_os_rand().save(state.config.key_path())?;
+    app::restrict_key_permissions(state.config.key_path())?;
+
+    for device in devices.iter() {
+        client_auth::revoke(&state.config, device.id())?;
+    }
+    // The pairing URI contains the old public key
+    client_auth::revoke(&state.config, PAIRING_CLIENT_NAME)?;
+
+    state.set_devices(Default::default()).await?;
+    state
+        .set_verification_devices(Default::default())
+        .await
+        .map_err(|err| anyhow!("Error setting verification devices on database: {}", err))?;
+
+    Ok(removed)
+}
+
+/// Permissions other users must not have on files with secrets.
+const PRIVATE_MODE: u32 = 0o077;
+/// Permissions other users must not have on directories containing files with secrets.
+const SHARED_DIRECTORY_MODE: u32 = 0o022;
+
+/// Find the problems with the configuration that would prevent the server from running or are
+/// unsafe.
+pub fn check_config(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let key_path = config.key_path();
+    if key_path.exists() {
+        if let Err(err) = StaticSecret::from_file(key_path) {
+            problems.push(format!("Secret key {:?} is invalid: {}", key_path, err));
+        }
+        check_permissions(&mut problems, "Secret key", key_path, PRIVATE_MODE);
+    } else if !parent_exists(key_path) {
+        problems.push(format!(
+            "Directory of the secret key {:?} doesn't exist",
+            key_path
+        ));
+    }
+    check_permissions(
+        &mut problems,
+        "Directory of the secret key",
+        parent(key_path),
+        SHARED_DIRECTORY_MODE,
+    );
+
+    let database_path = config.database_path();
+    if database_path.exists() {
+        check_permissions(&mut problems, "Database", database_path, PRIVATE_MODE);
+    } else if !parent_exists(database_path) {
+        problems.push(format!(
+            "Directory of the database {:?} doesn't exist",
+            database_path
+        ));
+    }
+    check_permissions(
+        &mut problems,
+        "Directory of the database",
+        parent(database_path),
+        SHARED_DIRECTORY_MODE,
+    );
+
+    if config.lan_address().is_some() {
+        check_permissions(
+            &mut problems,
+            "LAN private key",
+            &config.lan_private_key_path(),
+            PRIVATE_MODE,
+        );
+    }
+
+    for address in config.listen_addresses() {
+        match address {
+            ListenAddress::Tcp(addr) if addr.port() == 0 => {
+                problems.push(format!("Listen address {} can't use port 0", addr));
+            }
+            ListenAddress::Unix(path) if !parent_exists(&path) => {
+                problems.push(format!(
+                    "Directory of the unix socket {:?} doesn't exist",
+                    path
+                ));
+            }
+            _ => (),
+        }
+    }
+
+    if config.log_target() == LogTarget::File && !parent_exists(config.log_path()) {
+        problems.push(format!(
+            "Directory of the log file {:?} doesn't exist",
+            config.log_path()
+        ));
+    }
+
+    if let Some(address) = config.metrics_address() {
+        if !address.ip().is_loopback() {
+            problems.push(format!(
+                "Metrics address {} must be a loopback address",
+                address
+            ));
+        }
+    }
+
+    if let Some(hibp_path) = config.hibp_path() {
+        if !hibp_path.exists() {
+            problems.push(format!(
+                "Have I Been Pwned dataset {:?} doesn't exist",
+                hibp_path
+            ));
+        }
+    }
+
+    if let Some(directory) = config.snapshot_directory() {
+        if directory.exists() && !directory.is_dir() {
+            problems.push(format!(
+                "Snapshot directory {:?} is not a directory",
+                directory
+            ));
+        }
+        // The snapshots contain all secrets
+        check_permissions(&mut problems, "Snapshot directory", directory, PRIVATE_MODE);
+        for (_, path) in snapshot::list(directory).unwrap_or_default() {
+            check_permissions(&mut problems, "Snapshot", &path, PRIVATE_MODE);
+        }
+    }
+
+    problems
+}
+
+/// Whether the directory a file would be created in exists.
+fn parent_exists(path: &Path) -> bool {
+    path.parent()
+        // A relative path without directories is created in the working directory
+        .map(|parent| parent.as_os_str().is_empty() || parent.is_dir())
+        .unwrap_or(true)
+}
+
+/// The directory a file is created in.
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Add a problem when an existing path has any of the forbidden permissions or is owned by
+/// another user than the current one or root.
+fn check_permissions(
+    problems: &mut Vec<String>,
+    description: &str,
+    path: &Path,
+    forbidden_mode: u32,
+) {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return,
+    };
+
+    let mode = metadata.permissions().mode() & 0o777;
+    if mode & forbidden_mode != 0 {
+        problems.push(format!(
+            "{} {:?} has unsafe permissions {:03o}, remove {:03o}",
+            description,
+            path,
+            mode,
+            mode & forbidden_mode
+        ));
+    }
+
+    // The owner of the process directory is the user the process runs as
+    if let Ok(process) = fs::metadata("/proc/self") {
+        if metadata.uid() != process.uid() && metadata.uid() != 0 {
+            problems.push(format!(
+                "{} {:?} is owned by another user with ID {}",
+                description,
+                path,
+                metadata.uid()
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        admin, app,
+        attachment::{self, Attachment, Attachments},
+        config::Config,
+        device::register::VerificationDevices,
+        lan,
+        password::Password,
+        store::StorageBuilder,
+        test,
+        tor::client_auth::{self, ClientAuthKey, PAIRING_CLIENT_NAME},
+    };
+    use anyhow::{anyhow, Result};
+    use keybear_core::crypto::StaticSecretExt;
+    use std::{
+        fs::{self, Permissions},
+        os::unix::fs::PermissionsExt,
+    };
+    use x25519_dalek::StaticSecret;
+
+    #[actix_rt::test]
+    async fn devices() -> Result<()> {
+        let state = test::app_state();
+        test::register_device(&state, "first").await?;
+        let id = test::register_device(&state, "second")
+            .await?
+            .id()
+            .to_string();
+
+        let pending = admin::pending_devices(&state).await?;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id(), id);
+        assert!(admin::approve_device(&state, &id, Some("wrong code"))
+            .await
+            .is_err());
+
+        let device =
+            admin::approve_device(&state, &id, Some(pending[0].verification_code())).await?;
+        assert_eq!(device.name(), "second");
+        assert_eq!(state.devices().await?.iter().count(), 2);
+        assert_eq!(
+            state.verification_devices().await?,
+            VerificationDevices::default()
+        );
+        // It can't be approved twice
+        assert!(admin::approve_device(&state, &id, None).await.is_err());
+
+        admin::rename_device(&state, &id, "renamed").await?;
+        assert_eq!(state.device(&id).await?.name(), "renamed");
+
+        admin::revoke_device(&state, &id).await?;
+        assert!(state.device(&id).await.is_err());
+        assert!(admin::revoke_device(&state, &id).await.is_err());
+
+        // Rejected devices are never registered
+        let id = test::register_device(&state, "third")
+            .await?
+            .id()
+            .to_string();
+        assert_eq!(admin::reject_device(&state, &id).await?.name(), "third");
+        assert!(admin::pending_devices(&state).await?.is_empty());
+        assert!(admin::approve_device(&state, &id, None).await.is_err());
+        assert!(state.device(&id).await.is_err());
+
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn rotate_key() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let key_path = dir.path().join("key");
+        let state = test::app_state_with_config(Config::from_raw_str(&format!(
+            r#"
+            key_path = {:?}
+
+            [tor]
+            hidden_service_dir = {:?}
+            client_authorization = true
+            "#,
+            key_path,
+            dir.path()
+        ))?);
+        state.secret_key.save(&key_path)?;
+        test::register_device(&state, "first").await?;
+        test::register_device(&state, "second").await?;
+        client_auth::authorize(
+            &state.config,
+            PAIRING_CLIENT_NAME,
+            &ClientAuthKey::generate(),
+        )?;
+        assert_eq!(client_auth::authorized_public_keys(&state.config)?.len(), 2);
+
+        assert_eq!(admin::rotate_key(&state).await?, 1);
+        assert_ne!(
+            StaticSecret::from_file(&key_path)?.to_bytes(),
+            state.secret_key.to_bytes()
+        );
+        assert!(state.devices().await?.is_empty());
+        assert_eq!(
+            state.verification_devices().await?,
+            VerificationDevices::default()
+        );
+        // Neither the devices nor the pairing URI can connect to the onion service anymore
+        assert!(client_auth::authorized_public_keys(&state.config)?.is_empty());
+
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn delete_password() -> Result<()> {
+        let state = test::app_state();
+        let mut passwords = state.passwords().await?;
+        let password = Password::new("test", "test_password");
+        passwords.register(password.clone());
+        state.set_passwords(&passwords).await?;
+
+        // Attach a file to the password
+        let file = Attachment {
+            id: "file".to_string(),
+            entry_id: password.id.clone(),
+            name: "file.txt".to_string(),
+            size: 4,
+            chunks: 1,
+        };
+        let mut attachments = state.attachments().await?;
+        attachments.register(file.clone());
+        state.set_attachments(&attachments).await?;
+        state
+            .storage
+            .lock()
+            .await
+            .set_bytes(attachment::chunk_key(&file.id, 0), b"file".to_vec())
+            .await
+            .map_err(|err| anyhow!("{}", err))?;
+        assert!(attachment::chunks(&*state.storage.lock().await, &file)
+            .await
+            .is_ok());
+
+        assert_eq!(
+            admin::delete_password(&state, &password.id).await?,
+            password
+        );
+        assert_eq!(state.passwords().await?.iter().count(), 0);
+        // The attachments are deleted with it
+        assert_eq!(state.attachments().await?, Attachments::default());
+        assert!(attachment::chunks(&*state.storage.lock().await, &file)
+            .await
+            .is_err());
+        assert!(admin::delete_password(&state, &password.id).await.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_config() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let key_path = dir.path().join("key");
+
+        let config = Config::from_raw_str(&format!(
+            r#"
+            key_path = {:?}
+            database_path = {:?}
+            "#,
+            key_path,
+            dir.path().join("db")
+        ))?;
+        assert!(admin::check_config(&config).is_empty());
+
+        // The database keybear creates itself is safe
+        StorageBuilder::new(dir.path().join("db")).build()?;
+        assert!(admin::check_config(&config).is_empty());
+
+        // An invalid key
+        fs::write(&key_path, "invalid")?;
+        fs::set_permissions(&key_path, Permissions::from_mode(0o600))?;
+        assert_eq!(admin::check_config(&config).len(), 1);
+        StaticSecret::new_with_os_rand().save(&key_path)?;
+        assert!(admin::check_config(&config).is_empty());
+
+        // Other users can read the key or replace files in its directory
+        fs::set_permissions(&key_path, Permissions::from_mode(0o644))?;
+        assert_eq!(admin::check_config(&config).len(), 1);
+        app::restrict_key_permissions(&key_path)?;
+        fs::set_permissions(dir.path(), Permissions::from_mode(0o777))?;
+        // Both the key and the database directory
+        assert_eq!(admin::check_config(&config).len(), 2);
+        fs::set_permissions(dir.path(), Permissions::from_mode(0o700))?;
+        assert!(admin::check_config(&config).is_empty());
+
+        // Other users can read the snapshots
+        let snapshots = dir.path().join("snapshots");
+        let config = Config::from_raw_str(&format!(
+            r#"
+            key_path = {:?}
+            database_path = {:?}
+
+            [snapshots]
+            directory = {:?}
+            "#,
+            key_path,
+            dir.path().join("db"),
+            snapshots
+        ))?;
+        fs::create_dir(&snapshots)?;
+        fs::set_permissions(&snapshots, Permissions::from_mode(0o755))?;
+        assert_eq!(admin::check_config(&config).len(), 1);
+        fs::set_permissions(&snapshots, Permissions::from_mode(0o700))?;
+        assert!(admin::check_config(&config).is_empty());
+
+        // Other users can read the private key of the LAN certificate
+        let config = Config::from_raw_str(&format!(
+            r#"
+            key_path = {:?}
+            database_path = {:?}
+
+            [lan]
+            address = "192.168.1.10:52478"
+            "#,
+            key_path,
+            dir.path().join("db")
+        ))?;
+        lan::ensure_certificate(&config)?;
+        assert!(admin::check_config(&config).is_empty());
+        fs::set_permissions(config.lan_private_key_path(), Permissions::from_mode(0o644))?;
+        assert_eq!(admin::check_config(&config).len(), 1);
+
+        let missing = dir.path().join("missing");
+        let config = Config::from_raw_str(&format!(
+            r#"
+            key_path = {:?}
+            database_path = {:?}
+
+            [server]
+            listen = ["127.0.0.1:0", "unix:{}"]
+
+            [breach]
+            hibp_path = {:?}
+
+            [metrics]
+            address = "0.0.0.0:9477"
+
+            [log]
+            target = "file"
+            path = {:?}
+            "#,
+            missing.join("key"),
+            missing.join("db"),
+            missing.join("socket").display(),
+            missing.join("hibp"),
+            missing.join("keybear.log")
+        ))?;
+        assert_eq!(admin::check_config(&config).len(), 7);
+
+        Ok(())
+    }
+}
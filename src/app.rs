@@ -1,6 +1,11 @@
 use crate::{
+    attachment::Attachments,
     config::Config,
     device::{register::VerificationDevices, Device, Devices},
+    item::Items,
+    metrics::Metrics,
+    net::ListenerPolicy,
+    password::Passwords,
     route,
     store::StorageBuilder,
 };
@@ -13,37 +18,103 @@ use actix_web::{
     App, Error, Result as WebResult,
 };
 use anyhow::{anyhow, Result};
+use futures::lock::Mutex;
 use keybear_core::crypto::StaticSecretExt;
-use std::sync::Mutex;
+use sled::Db;
+use std::{
+    fs::{self, DirBuilder, OpenOptions, Permissions},
+    io::Write,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt},
+    path::Path,
+    sync::atomic::AtomicU64,
+};
 use x25519_dalek::StaticSecret;
 
+/// Only allow the owner to access the file of the secret key.
+pub fn restrict_key_permissions(path: &Path) -> Result<()> {
+    fs::set_permissions(path, Permissions::from_mode(0o600)).map_err(|err| {
+        anyhow!(
+            "Restricting permissions of secret key {:?} failed: {}",
+            path,
+            err
+        )
+    })
+}
+
+/// Write a file containing a secret that only the owner can access.
+///
+/// The permissions of an existing file are restricted before it's overwritten.
+pub fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
+    if path.exists() {
+        restrict_key_permissions(path)?;
+    }
+
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .map_err(|err| anyhow!("Writing secret file {:?} failed: {}", path, err))
+}
+
+/// Create a directory with the missing parents that only the owner can access.
+///
+/// The permissions of directories that already exist aren't changed.
+pub fn create_private_dir(path: &Path) -> Result<()> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(path)
+        .map_err(|err| anyhow!("Creating directory {:?} failed: {}", path, err))
+}
+
 /// The shareable state of the application.
 pub struct AppState {
     /// The database.
     pub storage: Mutex<Storage>,
+    /// Handle to the database behind the storage, `None` when the storage is kept in memory.
+    pub database: Option<Db>,
     /// The secret key to communicate with the clients.
     pub secret_key: StaticSecret,
+    /// The configuration the server is started with.
+    pub config: Config,
+    /// Counters of the handled requests.
+    pub metrics: Metrics,
+    /// Bytes reserved by attachment uploads in progress, only reserved while holding the storage
+    /// lock.
+    pub reserved_attachment_size: AtomicU64,
 }
 
 impl AppState {
     /// Construct the application state with the information from the config.
     pub fn from_config(config: &Config) -> Result<Self> {
         // Generate a static secret key if it doesn't exist
+        let generate_key = !config.key_path().exists();
         let secret_key = StaticSecret::from_file_or_generate(config.key_path())?;
+        if generate_key {
+            restrict_key_permissions(config.key_path())?;
+        }
 
         // Setup the database
-        let storage = Mutex::new(StorageBuilder::new(config.database_path()).build()?);
+        let (storage, database) =
+            StorageBuilder::new(config.database_path()).build_with_database()?;
 
         Ok(Self {
             secret_key,
-            storage,
+            storage: Mutex::new(storage),
+            database: Some(database),
+            metrics: Default::default(),
+            reserved_attachment_size: Default::default(),
+            config: config.clone(),
         })
     }
 
     /// Set the devices.
     pub async fn set_devices(&self, devices: Devices) -> Result<()> {
         // Get a mutex lock on the storage
-        let storage = self.storage.lock().unwrap();
+        let storage = self.storage.lock().await;
 
         // Persist the devices in the storage
         storage
@@ -57,7 +128,7 @@ impl AppState {
     /// Get the devices from the database.
     pub async fn devices(&self) -> Result<Devices> {
         // Get a mutex lock on the storage
-        let storage = self.storage.lock().unwrap();
+        let storage = self.storage.lock().await;
 
         // Get the devices from the database or use the default
         Ok(storage
@@ -96,7 +167,7 @@ impl AppState {
     /// Set the devices that are awaiting verification.
     pub async fn set_verification_devices(&self, devices: VerificationDevices) -> WebResult<()> {
         // Get a mutex lock on the storage
-        let storage = self.storage.lock().unwrap();
+        let storage = self.storage.lock().await;
 
         // Persist the devices in the storage
         storage.set("verification_devices", &devices).await?;
@@ -107,7 +178,7 @@ impl AppState {
     /// Get the devices that are awaiting verification from the database.
     pub async fn verification_devices(&self) -> Result<VerificationDevices> {
         // Get a mutex lock on the storage
-        let storage = self.storage.lock().unwrap();
+        let storage = self.storage.lock().await;
 
         // Get the devices from the database or use the default
         Ok(storage
@@ -116,10 +187,122 @@ impl AppState {
             .map_err(|err| anyhow!("Could not get verification devices from storage: {}", err))?
             .unwrap_or_else(VerificationDevices::default))
     }
+
+    /// Set the token the first device must register with.
+    pub async fn set_bootstrap_token(&self, token: Option<&str>) -> Result<()> {
+        // Get a mutex lock on the storage
+        let storage = self.storage.lock().await;
+
+        // Persist the token in the storage
+        storage
+            .set("bootstrap_token", &token)
+            .await
+            .map_err(|err| anyhow!("Error setting bootstrap token on database: {}", err))?;
+
+        Ok(())
+    }
+
+    /// Get the token the first device must register with from the database.
+    pub async fn bootstrap_token(&self) -> Result<Option<String>> {
+        // Get a mutex lock on the storage
+        let storage = self.storage.lock().await;
+
+        // Get the token from the database, it might not be set
+        Ok(storage
+            .get::<_, Option<String>>("bootstrap_token")
+            .await
+            .map_err(|err| anyhow!("Could not get bootstrap token from storage: {}", err))?
+            .flatten())
+    }
+
+    /// Set the passwords.
+    pub async fn set_passwords(&self, passwords: &Passwords) -> Result<()> {
+        // Get a mutex lock on the storage
+        let storage = self.storage.lock().await;
+
+        // Persist the passwords in the storage
+        storage
+            .set("passwords", passwords)
+            .await
+            .map_err(|err| anyhow!("Error setting passwords on database: {}", err))?;
+
+        Ok(())
+    }
+
+    /// Get the passwords from the database.
+    pub async fn passwords(&self) -> Result<Passwords> {
+        // Get a mutex lock on the storage
+        let storage = self.storage.lock().await;
+
+        // Get the passwords from the database or use the default
+        Ok(storage
+            .get("passwords")
+            .await
+            .map_err(|err| anyhow!("Could not get passwords from storage: {}", err))?
+            .unwrap_or_else(Passwords::default))
+    }
+
+    /// Set the metadata of the attachments.
+    pub async fn set_attachments(&self, attachments: &Attachments) -> Result<()> {
+        // Get a mutex lock on the storage
+        let storage = self.storage.lock().await;
+
+        // Persist the attachments in the storage
+        storage
+            .set("attachments", attachments)
+            .await
+            .map_err(|err| anyhow!("Error setting attachments on database: {}", err))?;
+
+        Ok(())
+    }
+
+    /// Get the metadata of the attachments from the database.
+    pub async fn attachments(&self) -> Result<Attachments> {
+        // Get a mutex lock on the storage
+        let storage = self.storage.lock().await;
+
+        // Get the attachments from the database or use the default
+        Ok(storage
+            .get("attachments")
+            .await
+            .map_err(|err| anyhow!("Could not get attachments from storage: {}", err))?
+            .unwrap_or_else(Attachments::default))
+    }
+
+    /// Set the vault items.
+    pub async fn set_items(&self, items: Items) -> Result<()> {
+        // Get a mutex lock on the storage
+        let storage = self.storage.lock().await;
+
+        // Persist the items in the storage
+        storage
+            .set("items", &items)
+            .await
+            .map_err(|err| anyhow!("Error setting items on database: {}", err))?;
+
+        Ok(())
+    }
+
+    /// Get the vault items from the database.
+    pub async fn items(&self) -> Result<Items> {
+        // Get a mutex lock on the storage
+        let storage = self.storage.lock().await;
+
+        // Get the items from the database or use the default
+        Ok(storage
+            .get("items")
+            .await
+            .map_err(|err| anyhow!("Could not get items from storage: {}", err))?
+            .unwrap_or_else(Items::default))
+    }
 }
 
-/// Create the server app.
-pub fn fill_app<T, B>(app: App<T, B>, app_state: &Data<AppState>) -> App<T, B>
+/// Create the server app, only accepting the clients allowed by the policy of the listener.
+pub fn fill_app<T, B>(
+    app: App<T, B>,
+    app_state: &Data<AppState>,
+    policy: ListenerPolicy,
+) -> App<T, B>
 where
     B: MessageBody,
     T: ServiceFactory<
@@ -134,5 +317,5 @@ where
         // Attach the database
         .app_data(app_state.clone())
         // Configure the routes and services
-        .configure(route::router)
+        .configure(|cfg| route::router(cfg, policy))
 }
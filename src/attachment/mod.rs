@@ -0,0 +1,571 @@
+use crate::{
+    app::AppState,
+    body::{ChunkCipher, EncryptedBody, EncryptedChunks},
+    item::Items,
+    password::Passwords,
+};
+use actix_storage::Storage;
+use actix_web::{
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound, ErrorPayloadTooLarge},
+    web::{Bytes, Data, Path},
+    HttpRequest, HttpResponse, Result as WebResult,
+};
+use anyhow::{anyhow, Result};
+use futures::stream;
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+use uuid::Uuid;
+
+/// The metadata of all attachments.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Attachments {
+    /// The attachments.
+    attachments: Vec<Attachment>,
+}
+
+impl Attachments {
+    /// Register a new attachment.
+    pub fn register(&mut self, attachment: Attachment) {
+        self.attachments.push(attachment);
+    }
+
+    /// Get an attachment by ID.
+    pub fn by_id(&self, id: &str) -> Option<&Attachment> {
+        self.attachments
+            .iter()
+            .find(|attachment| attachment.id == id)
+    }
+
+    /// Remove all attachments of a password or item, returns the removed attachments.
+    pub fn remove_entry(&mut self, entry_id: &str) -> Vec<Attachment> {
+        let (removed, kept) = self
+            .attachments
+            .drain(..)
+            .partition(|attachment| attachment.entry_id == entry_id);
+        self.attachments = kept;
+
+        removed
+    }
+
+    /// Remove an attachment, returns the removed attachment if it existed.
+    pub fn remove(&mut self, id: &str) -> Option<Attachment> {
+        let index = self
+            .attachments
+            .iter()
+            .position(|attachment| attachment.id == id)?;
+
+        Some(self.attachments.remove(index))
+    }
+
+    /// Iterate over all attachments.
+    pub fn iter(&self) -> impl Iterator<Item = &Attachment> {
+        self.attachments.iter()
+    }
+
+    /// The combined size in bytes of all attachments.
+    pub fn total_size(&self) -> u64 {
+        self.attachments
+            .iter()
+            .map(|attachment| attachment.size)
+            .sum()
+    }
+
+    /// Get a vector of attachments as allowed to be shown to the clients.
+    pub fn to_public_vec(&self) -> Vec<PublicAttachment> {
+        self.attachments
+            .iter()
+            .map(|attachment| attachment.to_public())
+            .collect()
+    }
+}
+
+/// The metadata of a file attached to a password or item.
+///
+/// The contents are stored separately in chunks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    /// Unique identifier.
+    pub id: String,
+    /// Identifier of the password or item this file is attached to.
+    pub entry_id: String,
+    /// The file name.
+    pub name: String,
+    /// Size of the contents in bytes.
+    pub size: u64,
+    /// Amount of chunks the contents are stored in.
+    pub chunks: u32,
+}
+
+impl Attachment {
+    /// Convert it to a public attachment, without the storage details.
+    pub fn to_public(&self) -> PublicAttachment {
+        PublicAttachment {
+            id: self.id.clone(),
+            entry_id: self.entry_id.clone(),
+            name: self.name.clone(),
+            size: self.size,
+        }
+    }
+
+    /// The key in the storage of a chunk of the contents.
+    fn chunk_key(&self, index: u32) -> String {
+        chunk_key(&self.id, index)
+    }
+}
+
+/// The first chunk of an attachment upload, describing the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisterAttachmentRequest {
+    /// Identifier of the password or item to attach the file to.
+    pub entry_id: String,
+    /// The file name.
+    pub name: String,
+}
+
+/// Attachment information as shown to the clients.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PublicAttachment {
+    /// Unique identifier.
+    pub id: String,
+    /// Identifier of the password or item this file is attached to.
+    pub entry_id: String,
+    /// The file name.
+    pub name: String,
+    /// Size of the contents in bytes.
+    pub size: u64,
+}
+
+/// Get the stored contents of an attachment, chunk by chunk.
+///
+/// Must be called while holding the lock on the storage.
+pub(crate) async fn chunks(storage: &Storage, attachment: &Attachment) -> Result<Vec<Vec<u8>>> {
+    let mut chunks = Vec::with_capacity(attachment.chunks as usize);
+    for index in 0..attachment.chunks {
+        chunks.push(
+            storage
+                .get_bytes(attachment.chunk_key(index))
+                .await
+                .map_err(|err| anyhow!("Could not get attachment chunk: {}", err))?
+                .ok_or_else(|| anyhow!("Attachment chunk {} is missing", index))?,
+        );
+    }
+
+    Ok(chunks)
+}
+
+/// Delete the stored contents of an attachment.
+pub(crate) async fn delete_chunks(storage: &Storage, id: &str, chunks: u32) -> Result<()> {
+    for index in 0..chunks {
+        storage
+            .delete(chunk_key(id, index))
+            .await
+            .map_err(|err| anyhow!("Could not delete attachment chunk: {}", err))?;
+    }
+
+    Ok(())
+}
+
+/// Delete all attachments of a password or item with their contents.
+///
+/// Must be called while holding the lock on the storage.
+pub(crate) async fn delete_entry_attachments(storage: &Storage, entry_id: &str) -> Result<()> {
+    let mut attachments = storage
+        .get::<_, Attachments>("attachments")
+        .await
+        .map_err(|err| anyhow!("Could not get attachments from storage: {}", err))?
+        .unwrap_or_else(Attachments::default);
+
+    let removed = attachments.remove_entry(entry_id);
+    if removed.is_empty() {
+        return Ok(());
+    }
+
+    for attachment in &removed {
+        delete_chunks(storage, &attachment.id, attachment.chunks).await?;
+    }
+    storage
+        .set("attachments", &attachments)
+        .await
+        .map_err(|err| anyhow!("Error setting attachments on database: {}", err))?;
+
+    Ok(())
+}
+
+/// The key in the storage of a chunk of the contents of an attachment.
+pub(crate) fn chunk_key(id: &str, index: u32) -> String {
+    format!("attachment_{}_{}", id, index)
+}
+
+/// Get a list of all attachments.
+pub async fn get_attachments(
+    state: Data<AppState>,
+) -> WebResult<EncryptedBody<Vec<PublicAttachment>>> {
+    Ok(EncryptedBody::new(
+        state
+            .attachments()
+            .await
+            // Convert the anyhow error to an internal server error
+            .map_err(ErrorInternalServerError)?
+            .to_public_vec(),
+    ))
+}
+
+/// Upload a new attachment.
+///
+/// The first chunk contains the JSON encoded description of the file, the rest of the chunks
+/// contain the file contents.
+pub async fn post_attachment(
+    mut chunks: EncryptedChunks,
+    state: Data<AppState>,
+) -> WebResult<EncryptedBody<PublicAttachment>> {
+    let request: RegisterAttachmentRequest = chunks.next_json().await.map_err(ErrorBadRequest)?;
+
+    // The entry the file is attached to must exist
+    check_entry_exists(&*state.storage.lock().await, &request.entry_id).await?;
+
+    let mut upload = Upload::new(
+        state.clone(),
+        Attachment {
+            id: Uuid::new_v4().to_simple().to_string(),
+            entry_id: request.entry_id,
+            name: request.name,
+            size: 0,
+            chunks: 0,
+        },
+    );
+
+    store_chunks(&mut chunks, &state, &mut upload.attachment).await?;
+
+    // Get a mutex lock on the storage, so the reservation is released together with registering
+    let storage = state.storage.lock().await;
+    let attachment = upload.commit(&storage).await?;
+
+    debug!(
+        "Stored attachment of {} bytes in {} chunks",
+        attachment.size, attachment.chunks
+    );
+
+    Ok(EncryptedBody::new(attachment.to_public()))
+}
+
+/// An attachment that's being uploaded.
+///
+/// When it's dropped the reservation of its size is released. The stored chunks are also deleted
+/// unless it's committed, so an upload that fails or is aborted by the client leaves nothing
+/// behind.
+struct Upload {
+    /// The state the chunks are stored in.
+    state: Data<AppState>,
+    /// The attachment, the size is reserved and the chunks are stored.
+    attachment: Attachment,
+    /// Whether the attachment is registered.
+    committed: bool,
+}
+
+impl Upload {
+    /// Start uploading an attachment without any contents.
+    fn new(state: Data<AppState>, attachment: Attachment) -> Self {
+        Self {
+            state,
+            attachment,
+            committed: false,
+        }
+    }
+
+    /// Register the uploaded attachment.
+    ///
+    /// Must be called while holding the lock on the storage.
+    async fn commit(mut self, storage: &Storage) -> WebResult<Attachment> {
+        register(storage, &self.attachment).await?;
+        self.committed = true;
+
+        Ok(self.attachment.clone())
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        self.state
+            .reserved_attachment_size
+            .fetch_sub(self.attachment.size, Ordering::SeqCst);
+
+        if self.committed {
+            return;
+        }
+
+        // The chunks are only known to this upload, so they can be deleted without the lock on
+        // the storage, which can't be awaited here
+        if let Some(database) = &self.state.database {
+            for index in 0..self.attachment.chunks {
+                if let Err(err) = database.remove(self.attachment.chunk_key(index)) {
+                    error!("Deleting chunk of unfinished attachment failed: {}", err);
+                }
+            }
+        }
+    }
+}
+
+/// Store the uploaded contents chunk by chunk.
+///
+/// The size of every chunk is reserved before it's stored, so concurrent uploads can't exceed the
+/// quota together. The reservation is kept in the size of the attachment.
+async fn store_chunks(
+    chunks: &mut EncryptedChunks,
+    state: &AppState,
+    attachment: &mut Attachment,
+) -> WebResult<()> {
+    let max_size = state.config.attachment_max_size();
+    let quota = state.config.attachment_quota();
+
+    while let Some(chunk) = chunks.next_chunk().await.map_err(ErrorBadRequest)? {
+        let chunk_size = chunk.len() as u64;
+        if attachment.size + chunk_size > max_size {
+            return Err(ErrorPayloadTooLarge(format!(
+                "Attachment exceeds the maximum size of {} bytes",
+                max_size
+            )));
+        }
+
+        // Get a mutex lock on the storage, so the stored size can't change while reserving
+        let storage = state.storage.lock().await;
+
+        let stored_size = storage
+            .get::<_, Attachments>("attachments")
+            .await?
+            .unwrap_or_else(Attachments::default)
+            .total_size();
+        let reserved_size = state.reserved_attachment_size.load(Ordering::SeqCst);
+        if stored_size + reserved_size + chunk_size > quota {
+            return Err(ErrorPayloadTooLarge("Attachment exceeds the storage quota"));
+        }
+        state
+            .reserved_attachment_size
+            .fetch_add(chunk_size, Ordering::SeqCst);
+        attachment.size += chunk_size;
+
+        // Count the chunk before storing it, so it's also deleted when storing is interrupted
+        attachment.chunks += 1;
+        storage
+            .set_bytes(attachment.chunk_key(attachment.chunks - 1), chunk)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Throw an error when the password or item doesn't exist.
+async fn check_entry_exists(storage: &Storage, entry_id: &str) -> WebResult<()> {
+    let password_exists = storage
+        .get::<_, Passwords>("passwords")
+        .await?
+        .unwrap_or_else(Passwords::default)
+        .by_id(entry_id)
+        .is_some();
+    let item_exists = storage
+        .get::<_, Items>("items")
+        .await?
+        .unwrap_or_else(Items::default)
+        .by_id(entry_id)
+        .is_some();
+    if !password_exists && !item_exists {
+        return Err(ErrorNotFound(format!(
+            "Entry with ID \"{}\" does not exist",
+            entry_id
+        )));
+    }
+
+    Ok(())
+}
+
+/// Add the metadata of a stored attachment.
+///
+/// Must be called while holding the lock on the storage.
+async fn register(storage: &Storage, attachment: &Attachment) -> WebResult<()> {
+    // The entry might have been deleted while uploading
+    check_entry_exists(storage, &attachment.entry_id).await?;
+
+    let mut attachments = storage
+        .get::<_, Attachments>("attachments")
+        .await?
+        .unwrap_or_else(Attachments::default);
+    attachments.register(attachment.clone());
+    storage.set("attachments", &attachments).await?;
+
+    Ok(())
+}
+
+/// Download the contents of an attachment, encrypted per chunk.
+pub async fn get_attachment(
+    req: HttpRequest,
+    Path((id,)): Path<(String,)>,
+    state: Data<AppState>,
+) -> WebResult<HttpResponse> {
+    let attachment = state
+        .attachments()
+        .await
+        .map_err(ErrorInternalServerError)?
+        .by_id(&id)
+        .cloned()
+        .ok_or_else(|| ErrorNotFound(format!("Attachment with ID \"{}\" does not exist", id)))?;
+
+    let cipher = ChunkCipher::for_response(&req)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    // Always send at least a single chunk so the client receives the last chunk marker
+    let frame_count = attachment.chunks.max(1);
+
+    // Load and encrypt the chunks one by one while sending them
+    let frames = stream::unfold((state, cipher, 0), move |(state, mut cipher, index)| {
+        let attachment = attachment.clone();
+
+        async move {
+            if index >= frame_count {
+                return None;
+            }
+
+            let frame = async {
+                let data = if index < attachment.chunks {
+                    state
+                        .storage
+                        .lock()
+                        .await
+                        .get_bytes(attachment.chunk_key(index))
+                        .await
+                        .map_err(|err| anyhow!("Could not get attachment chunk: {}", err))?
+                        .ok_or_else(|| anyhow!("Attachment chunk {} is missing", index))?
+                } else {
+                    Vec::new()
+                };
+
+                cipher.encrypt_chunk(&data, index + 1 == frame_count)
+            }
+            .await;
+
+            match frame {
+                Ok(frame) => Some((Ok(Bytes::from(frame)), (state, cipher, index + 1))),
+                // Stop the stream after an error
+                Err(err) => Some((
+                    Err(ErrorInternalServerError(err)),
+                    (state, cipher, frame_count),
+                )),
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok().streaming(Box::pin(frames)))
+}
+
+/// Delete an attachment with its contents.
+pub async fn delete_attachment(
+    Path((id,)): Path<(String,)>,
+    state: Data<AppState>,
+) -> WebResult<EncryptedBody<()>> {
+    // Get a mutex lock on the storage for the whole update
+    let storage = state.storage.lock().await;
+
+    let mut attachments = storage
+        .get::<_, Attachments>("attachments")
+        .await?
+        .unwrap_or_else(Attachments::default);
+
+    let attachment = attachments
+        .remove(&id)
+        .ok_or_else(|| ErrorNotFound(format!("Attachment with ID \"{}\" does not exist", id)))?;
+
+    delete_chunks(&storage, &attachment.id, attachment.chunks)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    storage.set("attachments", &attachments).await?;
+
+    Ok(EncryptedBody::new(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        app::AppState,
+        attachment::{chunk_key, Attachment, Upload},
+        config::Config,
+        password::Password,
+    };
+    use actix_web::web::Data;
+    use anyhow::{anyhow, Result};
+    use std::sync::atomic::Ordering;
+
+    /// Start an upload of a single stored chunk to the entry.
+    async fn upload_chunk(state: &Data<AppState>, entry_id: &str) -> Result<Upload> {
+        let upload = Upload::new(
+            state.clone(),
+            Attachment {
+                id: "file".to_string(),
+                entry_id: entry_id.to_string(),
+                name: "file.txt".to_string(),
+                size: 4,
+                chunks: 1,
+            },
+        );
+        state
+            .reserved_attachment_size
+            .fetch_add(4, Ordering::SeqCst);
+        state
+            .storage
+            .lock()
+            .await
+            .set_bytes(chunk_key("file", 0), b"file")
+            .await
+            .map_err(|err| anyhow!("{}", err))?;
+
+        Ok(upload)
+    }
+
+    /// Get the stored chunk of the uploaded file.
+    async fn stored_chunk(state: &AppState) -> Result<Option<Vec<u8>>> {
+        state
+            .storage
+            .lock()
+            .await
+            .get_bytes(chunk_key("file", 0))
+            .await
+            .map_err(|err| anyhow!("{}", err))
+    }
+
+    #[actix_rt::test]
+    async fn upload() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let state = Data::new(AppState::from_config(&Config::from_raw_str(&format!(
+            "key_path = {:?}\ndatabase_path = {:?}",
+            dir.path().join("key"),
+            dir.path().join("db")
+        ))?)?);
+
+        // An upload that's dropped, like when the client disconnects, leaves nothing behind
+        drop(upload_chunk(&state, "missing").await?);
+        assert_eq!(state.reserved_attachment_size.load(Ordering::SeqCst), 0);
+        assert_eq!(stored_chunk(&state).await?, None);
+
+        // Just like one that can't be registered because the entry doesn't exist
+        let upload = upload_chunk(&state, "missing").await?;
+        assert!(upload.commit(&*state.storage.lock().await).await.is_err());
+        assert_eq!(state.reserved_attachment_size.load(Ordering::SeqCst), 0);
+        assert_eq!(stored_chunk(&state).await?, None);
+
+        // A committed upload keeps the chunks but not the reservation
+        let password = Password::new("test", "test_password");
+        let mut passwords = state.passwords().await?;
+        passwords.register(password.clone());
+        state.set_passwords(&passwords).await?;
+
+        let upload = upload_chunk(&state, &password.id).await?;
+        let attachment = upload
+            .commit(&*state.storage.lock().await)
+            .await
+            .map_err(|err| anyhow!("{}", err))?;
+        assert_eq!(state.reserved_attachment_size.load(Ordering::SeqCst), 0);
+        assert_eq!(stored_chunk(&state).await?, Some(b"file".to_vec()));
+        assert_eq!(state.attachments().await?.by_id("file"), Some(&attachment));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,714 @@
+use crate::{
+    app::{self, AppState},
+    attachment::{self, Attachments},
+    body::EncryptedBody,
+    config::Config,
+    device::{register::VerificationDevices, Devices},
+    item::Items,
+    password::{self, Passwords},
+};
+use actix_storage::Storage;
+use actix_web::{
+    error::{BlockingError, ErrorBadRequest, ErrorInternalServerError},
+    web::{self, Data},
+    Result as WebResult,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::Variant;
+use chacha20poly1305::{
+    aead::{Aead, NewAead, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use log::info;
+use rand::{rngs::OsRng, RngCore};
+use serde::{
+    de::{DeserializeOwned, Error as _},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use sled::Batch;
+use std::{collections::BTreeMap, convert::TryInto, fs};
+
+/// The start of every backup file.
+const MAGIC: &[u8; 8] = b"KBBACKUP";
+/// The version of the file format written.
+pub const FORMAT_VERSION: u16 = 1;
+/// Size of the salt of the key derivation.
+const SALT_SIZE: usize = 16;
+/// Size of the nonce of the cipher.
+const NONCE_SIZE: usize = 12;
+/// Size of the unencrypted header, which is authenticated together with the contents.
+const HEADER_SIZE: usize = MAGIC.len() + 2 + SALT_SIZE + 3 * 4 + NONCE_SIZE;
+/// The shortest passphrase accepted for new backups.
+pub const MIN_PASSPHRASE_LENGTH: usize = 12;
+/// The most memory in KiB the key derivation of a backup may ask for when reading it.
+const MAX_KDF_MEMORY: u32 = 256 * 1024;
+/// The most iterations the key derivation of a backup may ask for when reading it.
+const MAX_KDF_ITERATIONS: u32 = 16;
+/// The most lanes the key derivation of a backup may ask for when reading it.
+const MAX_KDF_LANES: u32 = 8;
+
+/// Argon2id parameters of the key derivation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory in KiB.
+    pub memory: u32,
+    /// Amount of iterations.
+    pub iterations: u32,
+    /// Degree of parallelism.
+    pub lanes: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory: 64 * 1024,
+            iterations: 3,
+            lanes: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Derive the encryption key from the passphrase.
+    fn derive_key(&self, passphrase: &str, salt: &[u8]) -> Result<Vec<u8>> {
+        // The parameters are read from the backup, don't let it exhaust the server
+        if self.memory > MAX_KDF_MEMORY {
+            bail!("Key derivation asks for too much memory");
+        }
+        if self.iterations > MAX_KDF_ITERATIONS {
+            bail!("Key derivation asks for too many iterations");
+        }
+        if self.lanes > MAX_KDF_LANES {
+            bail!("Key derivation asks for too many lanes");
+        }
+
+        argon2::hash_raw(
+            passphrase.as_bytes(),
+            salt,
+            &argon2::Config {
+                variant: Variant::Argon2id,
+                mem_cost: self.memory,
+                time_cost: self.iterations,
+                lanes: self.lanes,
+                hash_length: 32,
+                ..argon2::Config::default()
+            },
+        )
+        .map_err(|err| anyhow!("Deriving backup key failed: {}", err))
+    }
+}
+
+/// Everything needed to restore the server.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Backup {
+    /// When the backup was created as a UNIX timestamp.
+    pub created: u64,
+    /// The version of keybear that created it.
+    pub keybear_version: String,
+    /// The base64 encoded secret key of the server, without it the devices can't connect.
+    ///
+    /// Left out of backups created through the API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    secret_key: Option<String>,
+    /// The configuration of the server, without the Tor control password when created through
+    /// the API.
+    settings: Config,
+    /// The registered devices.
+    devices: Devices,
+    /// The devices awaiting verification.
+    verification_devices: VerificationDevices,
+    /// The passwords.
+    passwords: Passwords,
+    /// The vault items.
+    items: Items,
+    /// The metadata of the attachments.
+    attachments: Attachments,
+    /// The chunks of the attachment contents by attachment ID, base64 encoded in the file.
+    #[serde(
+        serialize_with = "serialize_chunks",
+        deserialize_with = "deserialize_chunks"
+    )]
+    attachment_chunks: BTreeMap<String, Vec<Vec<u8>>>,
+}
+
+impl Backup {
+    /// Collect all data from the server.
+    ///
+    /// The storage is locked while collecting, so the backup can't contain half of a change.
+    pub async fn collect(state: &AppState) -> Result<Self> {
+        // Get a mutex lock on the storage for the whole backup
+        let storage = state.storage.lock().await;
+
+        let attachments: Attachments = stored_or_default(&storage, "attachments").await?;
+
+        let mut attachment_chunks = BTreeMap::new();
+        for attachment in attachments.iter() {
+            let chunks = attachment::chunks(&storage, attachment).await?;
+            attachment_chunks.insert(attachment.id.clone(), chunks);
+        }
+
+        Ok(Self {
+            created: password::current_timestamp(),
+            keybear_version: env!("CARGO_PKG_VERSION").to_string(),
+            secret_key: Some(base64::encode(state.secret_key.to_bytes())),
+            settings: state.config.clone(),
+            devices: stored_or_default(&storage, "devices").await?,
+            verification_devices: stored_or_default(&storage, "verification_devices").await?,
+            passwords: stored_or_default(&storage, "passwords").await?,
+            items: stored_or_default(&storage, "items").await?,
+            attachments,
+            attachment_chunks,
+        })
+    }
+
+    /// Collect all data from the server except for the secret key and the Tor control password.
+    ///
+    /// Used for backups leaving the server, restoring it keeps the current secret key.
+    pub async fn collect_without_secrets(state: &AppState) -> Result<Self> {
+        let backup = Self::collect(state).await?;
+
+        Ok(Self {
+            secret_key: None,
+            settings: backup.settings.without_secrets(),
+            ..backup
+        })
+    }
+
+    /// Whether the secret key and the Tor control password are part of the backup.
+    pub fn contains_secrets(&self) -> bool {
+        self.secret_key.is_some()
+    }
+
+    /// The configuration of the server at the time of the backup.
+    pub fn settings(&self) -> &Config {
+        &self.settings
+    }
+
+    /// Check that the backup is complete, so restoring it can't fail halfway.
+    pub fn validate(&self) -> Result<()> {
+        self.decode_secret_key()?;
+
+        for attachment in self.attachments.iter() {
+            let chunks = self.attachment_chunks.get(&attachment.id).ok_or_else(|| {
+                anyhow!("Contents of attachment \"{}\" are missing", attachment.id)
+            })?;
+            if chunks.len() != attachment.chunks as usize {
+                bail!("Attachment \"{}\" is missing chunks", attachment.id);
+            }
+
+            let size = chunks.iter().map(|chunk| chunk.len() as u64).sum::<u64>();
+            if size != attachment.size {
+                bail!("Attachment \"{}\" has the wrong size", attachment.id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replace all data of the server with the backup, including the secret key when it's part
+    /// of the backup.
+    ///
+    /// Everything is decoded before anything is changed. A database on disk is replaced in a
+    /// single batch, so a failure can't leave it half restored.
+    pub async fn restore(mut self, state: &AppState) -> Result<()> {
+        self.validate()?;
+        let secret_key = self.decode_secret_key()?;
+        let created = self.created;
+
+        // The values as they are stored
+        let mut values = vec![
+            (
+                "attachments".to_string(),
+                serde_json::to_vec(&self.attachments)?,
+            ),
+            (
+                "passwords".to_string(),
+                serde_json::to_vec(&self.passwords)?,
+            ),
+            ("items".to_string(), serde_json::to_vec(&self.items)?),
+            ("devices".to_string(), serde_json::to_vec(&self.devices)?),
+            (
+                "verification_devices".to_string(),
+                serde_json::to_vec(&self.verification_devices)?,
+            ),
+        ];
+        for (id, chunks) in std::mem::take(&mut self.attachment_chunks) {
+            for (index, chunk) in chunks.into_iter().enumerate() {
+                values.push((attachment::chunk_key(&id, index as u32), chunk));
+            }
+        }
+
+        // Write the secret key next to the current one, it's moved in place after the database
+        let key_path = state.config.key_path();
+        let partial_key_path = key_path.with_file_name(format!(
+            ".{}.partial",
+            key_path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        ));
+        if let Some(secret_key) = &secret_key {
+            app::write_private_file(&partial_key_path, secret_key)?;
+        }
+
+        let replaced = replace_values(state, values).await;
+        if secret_key.is_some() {
+            match replaced {
+                Ok(()) => fs::rename(&partial_key_path, key_path).with_context(|| {
+                    format!("Moving restored secret key to {:?} failed", key_path)
+                })?,
+                Err(_) => fs::remove_file(&partial_key_path)?,
+            }
+        }
+        replaced?;
+
+        info!("Restored backup created at {}", created);
+
+        Ok(())
+    }
+
+    /// Encrypt the backup with a passphrase into the contents of a backup file.
+    pub fn encrypt(&self, passphrase: &str) -> Result<Vec<u8>> {
+        self.encrypt_with_kdf(passphrase, KdfParams::default())
+    }
+
+    /// Encrypt the backup with custom key derivation parameters.
+    pub fn encrypt_with_kdf(&self, passphrase: &str, kdf: KdfParams) -> Result<Vec<u8>> {
+        if passphrase.chars().count() < MIN_PASSPHRASE_LENGTH {
+            bail!(
+                "Passphrase must be at least {} characters",
+                MIN_PASSPHRASE_LENGTH
+            );
+        }
+
+        let mut salt = [0; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = [0; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut file = Vec::with_capacity(HEADER_SIZE);
+        file.extend_from_slice(MAGIC);
+        file.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        file.extend_from_slice(&salt);
+        file.extend_from_slice(&kdf.memory.to_be_bytes());
+        file.extend_from_slice(&kdf.iterations.to_be_bytes());
+        file.extend_from_slice(&kdf.lanes.to_be_bytes());
+        file.extend_from_slice(&nonce);
+
+        let key = kdf.derive_key(passphrase, &salt)?;
+        let encrypted = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &serde_json::to_vec(self)?,
+                    aad: &file,
+                },
+            )
+            .map_err(|err| anyhow!("Encrypting backup failed: {}", err))?;
+        file.extend_from_slice(&encrypted);
+
+        Ok(file)
+    }
+
+    /// Decrypt and verify the contents of a backup file.
+    pub fn decrypt(file: &[u8], passphrase: &str) -> Result<Self> {
+        if file.len() < HEADER_SIZE || &file[..MAGIC.len()] != MAGIC {
+            bail!("File is not a keybear backup");
+        }
+        let (header, encrypted) = file.split_at(HEADER_SIZE);
+
+        let version = u16::from_be_bytes(header[8..10].try_into()?);
+        if version != FORMAT_VERSION {
+            bail!("Backup format version {} is not supported", version);
+        }
+
+        let salt = &header[10..10 + SALT_SIZE];
+        let number = |offset: usize| -> Result<u32> {
+            let start = 10 + SALT_SIZE + offset * 4;
+
+            Ok(u32::from_be_bytes(header[start..start + 4].try_into()?))
+        };
+        let kdf = KdfParams {
+            memory: number(0)?,
+            iterations: number(1)?,
+            lanes: number(2)?,
+        };
+        let nonce = &header[HEADER_SIZE - NONCE_SIZE..];
+
+        let key = kdf.derive_key(passphrase, salt)?;
+        let contents = ChaCha20Poly1305::new(Key::from_slice(&key))
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: encrypted,
+                    aad: header,
+                },
+            )
+            .map_err(|_| anyhow!("Wrong passphrase or the backup is corrupt"))?;
+
+        serde_json::from_slice(&contents).context("Backup contents are invalid")
+    }
+
+    /// Get the secret key of the server, `None` when it's not part of the backup.
+    fn decode_secret_key(&self) -> Result<Option<[u8; 32]>> {
+        self.secret_key
+            .as_ref()
+            .map(|secret_key| {
+                base64::decode(secret_key)?
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| anyhow!("Secret key in backup is invalid"))
+            })
+            .transpose()
+    }
+}
+
+/// Get a value from the storage, or the default when it isn't stored.
+///
+/// Must be called while holding the lock on the storage.
+async fn stored_or_default<T>(storage: &Storage, key: &str) -> Result<T>
+where
+    T: DeserializeOwned + Default,
+{
+    Ok(storage
+        .get::<_, T>(key)
+        .await
+        .map_err(|err| anyhow!("Could not get {} from storage: {}", key, err))?
+        .unwrap_or_default())
+}
+
+/// Replace the stored attachments and the values, the values are already serialized.
+async fn replace_values(state: &AppState, values: Vec<(String, Vec<u8>)>) -> Result<()> {
+    // Get a mutex lock on the storage for the whole restore
+    let storage = state.storage.lock().await;
+
+    // The contents of the current attachments are removed
+    let current_attachments: Attachments = stored_or_default(&storage, "attachments").await?;
+    let removed = current_attachments
+        .iter()
+        .flat_map(|attachment| {
+            (0..attachment.chunks).map(move |index| attachment::chunk_key(&attachment.id, index))
+        })
+        .collect::<Vec<_>>();
+
+    match &state.database {
+        Some(database) => {
+            // Apply everything at once
+            let mut batch = Batch::default();
+            for key in removed {
+                batch.remove(key.as_bytes());
+            }
+            for (key, value) in values {
+                batch.insert(key.as_bytes(), value);
+            }
+            database
+                .apply_batch(batch)
+                .and_then(|_| database.flush())
+                .map_err(|err| anyhow!("Could not restore backup: {}", err))?;
+        }
+        // Storage in memory can't fail halfway
+        None => {
+            for key in removed {
+                storage
+                    .delete(key)
+                    .await
+                    .map_err(|err| anyhow!("Could not delete attachment chunk: {}", err))?;
+            }
+            for (key, value) in values {
+                storage
+                    .set_bytes(key, value)
+                    .await
+                    .map_err(|err| anyhow!("Could not restore backup: {}", err))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A request to create a backup.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupRequest {
+    /// The passphrase to encrypt the backup with.
+    pub passphrase: String,
+}
+
+/// The created backup.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupResponse {
+    /// The base64 encoded contents of the backup file.
+    pub data: String,
+}
+
+/// Create an encrypted backup of all data.
+///
+/// The secret key of the server and the Tor control password are left out, a full backup can only
+/// be created on the server itself.
+pub async fn post_backup(
+    request: EncryptedBody<BackupRequest>,
+    state: Data<AppState>,
+) -> WebResult<EncryptedBody<BackupResponse>> {
+    if request.passphrase.chars().count() < MIN_PASSPHRASE_LENGTH {
+        return Err(ErrorBadRequest(format!(
+            "Passphrase must be at least {} characters",
+            MIN_PASSPHRASE_LENGTH
+        )));
+    }
+
+    let backup = Backup::collect_without_secrets(&state)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    // Deriving the key and encoding all attachments is slow, so don't do it on the thread
+    // handling requests
+    let passphrase = request.into_inner().passphrase;
+    let data =
+        web::block(move || -> Result<String> { Ok(base64::encode(backup.encrypt(&passphrase)?)) })
+            .await
+            .map_err(|err| match err {
+                BlockingError::Error(err) => ErrorInternalServerError(err),
+                BlockingError::Canceled => ErrorInternalServerError("Creating backup was canceled"),
+            })?;
+
+    Ok(EncryptedBody::new(BackupResponse { data }))
+}
+
+/// Write the chunks of the attachment contents as base64 strings.
+fn serialize_chunks<S>(
+    chunks: &BTreeMap<String, Vec<Vec<u8>>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    chunks
+        .iter()
+        .map(|(id, chunks)| (id, chunks.iter().map(base64::encode).collect::<Vec<_>>()))
+        .collect::<BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+/// Read the chunks of the attachment contents from base64 strings.
+fn deserialize_chunks<'de, D>(deserializer: D) -> Result<BTreeMap<String, Vec<Vec<u8>>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    BTreeMap::<String, Vec<String>>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(id, chunks)| {
+            let chunks = chunks
+                .iter()
+                .map(base64::decode)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| {
+                    D::Error::custom(format!("Attachment \"{}\" is corrupt: {}", id, err))
+                })?;
+
+            Ok((id, chunks))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        app::AppState,
+        attachment::{self, Attachment},
+        backup::{self, Backup, BackupRequest, KdfParams},
+        body::EncryptedBody,
+        config::Config,
+        password::Password,
+        test,
+    };
+    use anyhow::{anyhow, Result};
+    use keybear_core::crypto::StaticSecretExt;
+    use x25519_dalek::StaticSecret;
+
+    /// Light key derivation parameters to keep the tests fast.
+    const TEST_KDF: KdfParams = KdfParams {
+        memory: 1024,
+        iterations: 1,
+        lanes: 1,
+    };
+
+    #[actix_rt::test]
+    async fn round_trip() -> Result<()> {
+        let state = test::app_state();
+
+        let mut passwords = state.passwords().await?;
+        passwords.register(Password::new("test", "test_password"));
+        state.set_passwords(&passwords).await?;
+
+        let backup = Backup::collect(&state).await?;
+        backup.validate()?;
+        let file = backup.encrypt_with_kdf("correct horse battery staple", TEST_KDF)?;
+
+        // Short passphrases are rejected
+        assert!(backup.encrypt_with_kdf("short", TEST_KDF).is_err());
+
+        // Parameters that would exhaust the server are rejected
+        for kdf in &[
+            KdfParams {
+                memory: 1024 * 1024,
+                ..TEST_KDF
+            },
+            KdfParams {
+                iterations: 1000,
+                ..TEST_KDF
+            },
+            KdfParams {
+                lanes: 64,
+                ..TEST_KDF
+            },
+        ] {
+            assert!(backup
+                .encrypt_with_kdf("correct horse battery staple", *kdf)
+                .is_err());
+        }
+
+        // The wrong passphrase can't decrypt it
+        assert!(Backup::decrypt(&file, "wrong horse battery staple").is_err());
+
+        // Changing a single byte in the header or the contents is detected
+        for index in &[12, file.len() - 1] {
+            let mut tampered = file.clone();
+            tampered[*index] ^= 1;
+            assert!(Backup::decrypt(&tampered, "correct horse battery staple").is_err());
+        }
+
+        let decrypted = Backup::decrypt(&file, "correct horse battery staple")?;
+        assert_eq!(decrypted, backup);
+        assert_eq!(decrypted.passwords.iter().count(), 1);
+
+        Ok(())
+    }
+
+    /// Store an attachment with a single chunk.
+    async fn store_attachment(state: &AppState, id: &str, contents: &[u8]) -> Result<()> {
+        let mut attachments = state.attachments().await?;
+        attachments.register(Attachment {
+            id: id.to_string(),
+            entry_id: "entry".to_string(),
+            name: format!("{}.txt", id),
+            size: contents.len() as u64,
+            chunks: 1,
+        });
+        state.set_attachments(&attachments).await?;
+        state
+            .storage
+            .lock()
+            .await
+            .set_bytes(attachment::chunk_key(id, 0), contents)
+            .await
+            .map_err(|err| anyhow!("{}", err))
+    }
+
+    #[actix_rt::test]
+    async fn restore() -> Result<()> {
+        let source = test::app_state();
+        let mut passwords = source.passwords().await?;
+        passwords.register(Password::new("test", "test_password"));
+        source.set_passwords(&passwords).await?;
+        store_attachment(&source, "restored", b"restored").await?;
+
+        let backup = Backup::collect(&source).await?;
+
+        // Restore it into a server with a database on disk and another key
+        let dir = tempfile::tempdir()?;
+        let key_path = dir.path().join("key");
+        let target = AppState::from_config(&Config::from_raw_str(&format!(
+            "key_path = {:?}\ndatabase_path = {:?}",
+            key_path,
+            dir.path().join("db")
+        ))?)?;
+        store_attachment(&target, "replaced", b"replaced").await?;
+        backup.restore(&target).await?;
+
+        assert_eq!(target.passwords().await?, source.passwords().await?);
+        assert_eq!(target.attachments().await?, source.attachments().await?);
+        let storage = target.storage.lock().await;
+        assert_eq!(
+            storage
+                .get_bytes(attachment::chunk_key("restored", 0))
+                .await
+                .map_err(|err| anyhow!("{}", err))?,
+            Some(b"restored".to_vec())
+        );
+        // The contents of the attachments that aren't in the backup are removed
+        assert_eq!(
+            storage
+                .get_bytes(attachment::chunk_key("replaced", 0))
+                .await
+                .map_err(|err| anyhow!("{}", err))?,
+            None
+        );
+        assert_eq!(
+            StaticSecret::from_file(&key_path)?.to_bytes(),
+            source.secret_key.to_bytes()
+        );
+        // The secret key is written next to the current one first
+        assert!(!dir.path().join(".key.partial").exists());
+
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn post_backup() -> Result<()> {
+        let state = test::app_state();
+        store_attachment(&state, "file", b"contents").await?;
+
+        let response = backup::post_backup(
+            EncryptedBody::new(BackupRequest {
+                passphrase: "correct horse battery staple".to_string(),
+            }),
+            state.clone(),
+        )
+        .await
+        .map_err(|err| anyhow!("{}", err))?;
+
+        let backup = Backup::decrypt(
+            &base64::decode(&response.data)?,
+            "correct horse battery staple",
+        )?;
+        backup.validate()?;
+        assert!(!backup.contains_secrets());
+        assert_eq!(backup.attachments, state.attachments().await?);
+
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn without_secrets() -> Result<()> {
+        let source = test::app_state_with_config(Config::from_raw_str(
+            "[tor]\ncontrol_password = \"secret\"",
+        )?);
+        let mut passwords = source.passwords().await?;
+        passwords.register(Password::new("test", "test_password"));
+        source.set_passwords(&passwords).await?;
+
+        let backup = Backup::collect_without_secrets(&source).await?;
+        assert!(!backup.contains_secrets());
+        assert_eq!(backup.settings().tor_control_password(), None);
+
+        // It survives encryption without the secret key
+        let backup = Backup::decrypt(
+            &backup.encrypt_with_kdf("correct horse battery staple", TEST_KDF)?,
+            "correct horse battery staple",
+        )?;
+        backup.validate()?;
+
+        // Restoring it keeps the key of the target
+        let dir = tempfile::tempdir()?;
+        let key_path = dir.path().join("key");
+        let target = test::app_state_with_config(Config::from_raw_str(&format!(
+            "key_path = {:?}",
+            key_path
+        ))?);
+        backup.restore(&target).await?;
+
+        assert_eq!(target.passwords().await?, source.passwords().await?);
+        assert!(!key_path.exists());
+
+        Ok(())
+    }
+}
@@ -6,6 +6,10 @@ use actix_web::{
     Error, FromRequest, HttpRequest, HttpResponse, Responder,
 };
 use anyhow::{anyhow, bail, Result};
+use chacha20poly1305::{
+    aead::{Aead, NewAead, Payload as AeadPayload},
+    ChaCha20Poly1305, Key,
+};
 use futures::{executor::block_on, Future};
 use futures_util::{
     future::{self, Ready},
@@ -18,6 +22,7 @@ use keybear_core::{
 use log::debug;
 use serde::{de::DeserializeOwned, Serialize};
 use std::{
+    convert::TryInto,
     fmt::{Debug, Display, Formatter, Result as FmtResult},
     ops::{Deref, DerefMut},
     pin::Pin,
@@ -115,7 +120,7 @@ where
         match self.key {
             Some(key) => {
                 // Encrypt the object
-                let encrypted = crypto::encrypt(&key, &nonce, &self.data)?;
+                let encrypted = crypto::encrypt(&key, nonce, &self.data)?;
 
                 Ok(Bytes::from(encrypted))
             }
@@ -193,9 +198,11 @@ where
             let device = state.device(&id).await.map_err(ErrorUnauthorized)?;
 
             // Decrypt the message contained in the body
-            let data = device
-                .decrypt(&state.secret_key, &body)
-                .map_err(ErrorInternalServerError)?;
+            let data = device.decrypt(&state.secret_key, &body).map_err(|err| {
+                state.metrics.record_decrypt_failure();
+
+                ErrorInternalServerError(err)
+            })?;
 
             // Get a shared key from the device, this will be passed so an encrypted response can
             // be sent back
@@ -233,6 +240,259 @@ where
     }
 }
 
+/// Maximum amount of plaintext bytes in a single chunk.
+pub const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Size of the authentication tag appended to every encrypted chunk.
+const CHUNK_TAG_SIZE: usize = 16;
+/// Size of the header in front of every encrypted chunk, a flag byte and the length.
+const CHUNK_HEADER_SIZE: usize = 5;
+/// Associated data of every chunk except the last one.
+const CHUNK_AAD: &[u8] = b"keybear-chunk";
+/// Associated data of the last chunk, prevents truncating the stream.
+const FINAL_CHUNK_AAD: &[u8] = b"keybear-final-chunk";
+
+/// Encrypts and decrypts a stream of chunks with a single nonce.
+///
+/// Every chunk is framed as a flag byte marking whether it's the last chunk, the length of the
+/// cipher bytes as a big endian `u32` and the cipher bytes themselves. The nonce of every chunk is
+/// the device nonce with the chunk counter, starting at 1, XOR-ed into the last 8 bytes.
+pub struct ChunkCipher {
+    /// The cipher created from the shared key.
+    cipher: ChaCha20Poly1305,
+    /// The nonce requested by the device.
+    nonce: [u8; 12],
+    /// How many chunks have been processed.
+    counter: u64,
+}
+
+impl ChunkCipher {
+    /// Construct a new cipher for a stream of chunks.
+    pub fn new(key: &SharedSecret, nonce: &Nonce) -> Self {
+        let mut nonce_bytes = [0; 12];
+        nonce_bytes.copy_from_slice(nonce.as_slice());
+
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key.as_bytes())),
+            nonce: nonce_bytes,
+            counter: 0,
+        }
+    }
+
+    /// Construct the cipher for an encrypted chunked response to the device making the request.
+    ///
+    /// This clears the nonce of the device.
+    pub async fn for_response(req: &HttpRequest) -> Result<Self> {
+        let (id, state) = request_id_and_app_state(req)?;
+
+        // Find the device from the ID
+        let mut device = state.device(&id).await?;
+
+        let cipher = Self::new(
+            &device.shared_key(&state.secret_key),
+            device.nonce()?.to_nonce(),
+        );
+
+        // Clear the nonce and persist the device with the cleared nonce
+        device.clear_nonce();
+        state.set_device(&device).await?;
+
+        Ok(cipher)
+    }
+
+    /// Encrypt a chunk into a frame.
+    pub fn encrypt_chunk(&mut self, data: &[u8], last: bool) -> Result<Vec<u8>> {
+        if data.len() > MAX_CHUNK_SIZE {
+            bail!("Chunk of {} bytes is too large", data.len());
+        }
+
+        let nonce = self.next_nonce();
+        let encrypted = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                AeadPayload {
+                    msg: data,
+                    aad: Self::aad(last),
+                },
+            )
+            .map_err(|err| anyhow!("Encrypting chunk: {}", err))?;
+
+        // Prepend the header
+        let mut frame = Vec::with_capacity(CHUNK_HEADER_SIZE + encrypted.len());
+        frame.push(last as u8);
+        frame.extend_from_slice(&(encrypted.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&encrypted);
+
+        Ok(frame)
+    }
+
+    /// Try to take a single frame from the buffer and decrypt it.
+    ///
+    /// Returns `None` when the buffer doesn't contain a full frame yet, otherwise the decrypted
+    /// chunk and whether it's the last one.
+    pub fn decrypt_frame(&mut self, buffer: &mut BytesMut) -> Result<Option<(Vec<u8>, bool)>> {
+        if buffer.len() < CHUNK_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let last = match buffer[0] {
+            0 => false,
+            1 => true,
+            flag => bail!("Invalid chunk flag {}", flag),
+        };
+        let len = u32::from_be_bytes(buffer[1..CHUNK_HEADER_SIZE].try_into()?) as usize;
+        if len > MAX_CHUNK_SIZE + CHUNK_TAG_SIZE {
+            bail!("Chunk of {} bytes is too large", len);
+        }
+
+        if buffer.len() < CHUNK_HEADER_SIZE + len {
+            return Ok(None);
+        }
+
+        // Remove the frame from the buffer
+        let frame = buffer.split_to(CHUNK_HEADER_SIZE + len);
+
+        let nonce = self.next_nonce();
+        let data = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                AeadPayload {
+                    msg: &frame[CHUNK_HEADER_SIZE..],
+                    aad: Self::aad(last),
+                },
+            )
+            .map_err(|err| anyhow!("Decrypting chunk: {}", err))?;
+
+        Ok(Some((data, last)))
+    }
+
+    /// Get the nonce for the next chunk.
+    fn next_nonce(&mut self) -> [u8; 12] {
+        self.counter += 1;
+
+        let mut nonce = self.nonce;
+        nonce[4..]
+            .iter_mut()
+            .zip(self.counter.to_be_bytes().iter())
+            .for_each(|(byte, counter)| *byte ^= counter);
+
+        nonce
+    }
+
+    /// The associated data of a chunk.
+    fn aad(last: bool) -> &'static [u8] {
+        if last {
+            FINAL_CHUNK_AAD
+        } else {
+            CHUNK_AAD
+        }
+    }
+}
+
+/// A payload that's encrypted by the client in separate chunks.
+///
+/// Unlike [`EncryptedBody`] the body is not read fully into memory, the chunks are decrypted one
+/// by one when requested.
+pub struct EncryptedChunks {
+    /// The stream of the request body.
+    payload: Payload,
+    /// Bytes received that don't form a full chunk yet.
+    buffer: BytesMut,
+    /// The cipher to decrypt the chunks with.
+    cipher: ChunkCipher,
+    /// Whether the last chunk is received.
+    finished: bool,
+    /// The client identifier.
+    client_id: String,
+}
+
+impl EncryptedChunks {
+    /// Get the next decrypted chunk, `None` when the last chunk has already been returned.
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            if let Some((data, last)) = self.cipher.decrypt_frame(&mut self.buffer)? {
+                if self.finished {
+                    bail!("Received chunks after the last chunk");
+                }
+                self.finished = last;
+
+                return Ok(Some(data));
+            }
+
+            match self.payload.next().await {
+                Some(bytes) => self
+                    .buffer
+                    .extend_from_slice(&bytes.map_err(|err| anyhow!("{}", err))?),
+                None if !self.buffer.is_empty() => bail!("Request body ended in a partial chunk"),
+                None if !self.finished => bail!("Request body ended before the last chunk"),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Get the next chunk and deserialize it from JSON.
+    pub async fn next_json<T>(&mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let chunk = self
+            .next_chunk()
+            .await?
+            .ok_or_else(|| anyhow!("Request body is missing a chunk"))?;
+
+        serde_json::from_slice(&chunk).map_err(|err| anyhow!("Chunk JSON is invalid: {}", err))
+    }
+
+    /// Get the client ID.
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+}
+
+impl FromRequest for EncryptedChunks {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>> + 'static>>;
+    type Config = ();
+
+    #[inline]
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        // Clone the request so it can be sent to the async block
+        let req = req.clone();
+
+        // Take the payload so it can be send to the async block
+        let payload = payload.take();
+
+        async move {
+            debug!(
+                "Received encrypted chunked request to path \"{}\"",
+                req.path()
+            );
+
+            // Get the app state and the client ID from the request
+            let (id, state) = request_id_and_app_state(&req).map_err(ErrorUnauthorized)?;
+
+            // Find the device from the ID
+            let device = state.device(&id).await.map_err(ErrorUnauthorized)?;
+
+            let cipher = ChunkCipher::new(
+                &device.shared_key(&state.secret_key),
+                device.nonce().map_err(ErrorUnauthorized)?.to_nonce(),
+            );
+
+            Ok(Self {
+                payload,
+                buffer: BytesMut::new(),
+                cipher,
+                finished: false,
+                client_id: id,
+            })
+        }
+        .boxed_local()
+    }
+}
+
 /// Get the requesting client ID and the app state object reference from an HTTP request.
 fn request_id_and_app_state(req: &HttpRequest) -> Result<(String, &AppState)> {
     let headers = req.headers();
@@ -0,0 +1,394 @@
+use crate::{
+    app::AppState,
+    body::EncryptedBody,
+    config::Config,
+    password::{PasswordSummary, Passwords},
+};
+use actix_web::{
+    error::{ErrorInternalServerError, ErrorNotFound},
+    web::Data,
+    Result as WebResult,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// The header of a binary breach index file.
+const INDEX_MAGIC: &[u8; 8] = b"KBHIBP\x00\x01";
+/// Size of a SHA-1 hash in bytes.
+const HASH_SIZE: u64 = 20;
+/// Amount of hexadecimal characters of the hash used as the name of a range file.
+const PREFIX_LEN: usize = 5;
+
+/// A local copy of the Have I Been Pwned password dataset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreachDatabase {
+    /// A directory of range files as downloaded from the API.
+    ///
+    /// Every file is named after the first 5 hexadecimal characters of the SHA-1 hashes it
+    /// contains, optionally with a `.txt` extension. Every line contains the rest of a hash and
+    /// the amount of times it was seen, separated by a colon.
+    RangeDirectory(PathBuf),
+    /// A compact file of sorted binary SHA-1 hashes built from the range files.
+    Index(PathBuf),
+}
+
+impl BreachDatabase {
+    /// Open the dataset configured, `None` when no dataset is configured.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        config.hibp_path().map(Self::open).transpose()
+    }
+
+    /// Open the dataset, the type is determined by whether the path is a directory or a file.
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        if path.is_dir() {
+            Ok(BreachDatabase::RangeDirectory(path.to_path_buf()))
+        } else if path.is_file() {
+            // Verify that it's a valid index
+            let mut magic = [0; 8];
+            File::open(path)
+                .and_then(|mut file| file.read_exact(&mut magic))
+                .with_context(|| format!("Reading breach index {:?} failed", path))?;
+            if &magic != INDEX_MAGIC {
+                bail!("File {:?} is not a breach index", path);
+            }
+
+            Ok(BreachDatabase::Index(path.to_path_buf()))
+        } else {
+            bail!("Breach dataset {:?} does not exist", path)
+        }
+    }
+
+    /// Check whether the password occurs in the dataset.
+    pub fn is_compromised(&self, password: &str) -> Result<bool> {
+        let hash = sha1_hash(password);
+
+        match self {
+            BreachDatabase::RangeDirectory(dir) => range_contains(dir, &hash),
+            BreachDatabase::Index(file) => index_contains(file, &hash),
+        }
+    }
+
+    /// Check all passwords, returns for every checked password whether it's compromised.
+    pub fn check_passwords(&self, passwords: &Passwords) -> Result<HashMap<String, bool>> {
+        let mut checked = HashMap::new();
+
+        for password in passwords.iter() {
+            if !checked.contains_key(&password.password) {
+                let compromised = self.is_compromised(&password.password)?;
+                checked.insert(password.password.clone(), compromised);
+            }
+        }
+
+        Ok(checked)
+    }
+}
+
+/// Build a compact binary index from a directory of range files.
+///
+/// Returns the amount of hashes written.
+pub fn build_index<P1, P2>(range_dir: P1, output: P2) -> Result<u64>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let range_dir = range_dir.as_ref();
+
+    // Get all range files sorted by their prefix
+    let mut ranges = fs::read_dir(range_dir)
+        .with_context(|| format!("Reading range directory {:?} failed", range_dir))?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let prefix = range_prefix(&path)?;
+
+            Some((prefix, path))
+        })
+        .collect::<Vec<_>>();
+    ranges.sort();
+
+    let mut writer = BufWriter::new(File::create(output.as_ref())?);
+    writer.write_all(INDEX_MAGIC)?;
+
+    let mut count = 0;
+    for (prefix, path) in ranges {
+        let mut hashes = BufReader::new(File::open(&path)?)
+            .lines()
+            .map(|line| {
+                let line = line?;
+                let suffix = line.split(':').next().unwrap_or_default().trim();
+
+                decode_hash(&format!("{}{}", prefix, suffix))
+                    .with_context(|| format!("Invalid line \"{}\" in {:?}", line, path))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        hashes.sort_unstable();
+
+        for hash in hashes {
+            writer.write_all(&hash)?;
+            count += 1;
+        }
+    }
+    writer.flush()?;
+
+    Ok(count)
+}
+
+/// Calculate the uppercase hexadecimal SHA-1 hash of a password.
+fn sha1_hash(password: &str) -> String {
+    Sha1::digest(password.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect()
+}
+
+/// Convert a hexadecimal SHA-1 hash into bytes.
+fn decode_hash(hex: &str) -> Result<[u8; HASH_SIZE as usize]> {
+    if hex.len() != HASH_SIZE as usize * 2 || !hex.is_ascii() {
+        bail!("Hash \"{}\" has an invalid length", hex);
+    }
+
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| anyhow!("Hash \"{}\" is not hexadecimal: {}", hex, err))?;
+
+    Ok(bytes.as_slice().try_into()?)
+}
+
+/// Get the uppercase hash prefix from the path of a range file.
+fn range_prefix(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?;
+
+    if stem.len() == PREFIX_LEN && stem.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(stem.to_uppercase())
+    } else {
+        None
+    }
+}
+
+/// Look the hash up in the matching range file.
+fn range_contains(dir: &Path, hash: &str) -> Result<bool> {
+    let (prefix, suffix) = hash.split_at(PREFIX_LEN);
+
+    // Try both the upper and lowercase names with and without an extension
+    let path = [prefix.to_string(), prefix.to_lowercase()]
+        .iter()
+        .flat_map(|name| vec![dir.join(name), dir.join(format!("{}.txt", name))])
+        .find(|path| path.is_file());
+    let path = match path {
+        Some(path) => path,
+        // Without a range file nothing with this prefix was breached
+        None => return Ok(false),
+    };
+
+    for line in BufReader::new(File::open(&path)?).lines() {
+        let line = line?;
+
+        let line_suffix = line.split(':').next().unwrap_or_default().trim();
+        if line_suffix.eq_ignore_ascii_case(suffix) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Look the hash up in the binary index with a binary search.
+fn index_contains(path: &Path, hash: &str) -> Result<bool> {
+    let needle = decode_hash(hash)?;
+
+    let mut file =
+        File::open(path).with_context(|| format!("Opening breach index {:?} failed", path))?;
+    let len = file.metadata()?.len();
+    let header = INDEX_MAGIC.len() as u64;
+
+    let (mut low, mut high) = (0, len.saturating_sub(header) / HASH_SIZE);
+    let mut current = [0; HASH_SIZE as usize];
+    while low < high {
+        let middle = low + (high - low) / 2;
+
+        file.seek(SeekFrom::Start(header + middle * HASH_SIZE))?;
+        file.read_exact(&mut current)?;
+
+        match current.cmp(&needle) {
+            std::cmp::Ordering::Equal => return Ok(true),
+            std::cmp::Ordering::Less => low = middle + 1,
+            std::cmp::Ordering::Greater => high = middle,
+        }
+    }
+
+    Ok(false)
+}
+
+/// Check all stored passwords against the configured breach dataset and persist the flags.
+///
+/// Returns the compromised passwords.
+pub async fn check_stored_passwords(state: &AppState) -> Result<Vec<PasswordSummary>> {
+    let database = match BreachDatabase::from_config(&state.config)? {
+        Some(database) => database,
+        None => bail!("No breach dataset is configured"),
+    };
+
+    // Looking the passwords up is slow, so don't hold the lock on the storage while doing it
+    let checked = database.check_passwords(&state.passwords().await?)?;
+
+    // Get a mutex lock on the storage for the whole update, so no concurrent change is lost
+    let storage = state.storage.lock().await;
+
+    let mut passwords = storage
+        .get::<_, Passwords>("passwords")
+        .await
+        .map_err(|err| anyhow!("Could not get passwords from storage: {}", err))?
+        .unwrap_or_else(Passwords::default);
+
+    // Only flag the passwords that are checked, new ones are flagged when they are registered
+    let mut changed = false;
+    for password in passwords.iter_mut() {
+        if let Some(&compromised) = checked.get(&password.password) {
+            if compromised != password.compromised {
+                password.compromised = compromised;
+                changed = true;
+            }
+        }
+    }
+    if changed {
+        storage
+            .set("passwords", &passwords)
+            .await
+            .map_err(|err| anyhow!("Error setting passwords on database: {}", err))?;
+    }
+
+    let compromised = passwords
+        .iter()
+        .filter(|password| password.compromised)
+        .map(|password| password.to_public())
+        .collect::<Vec<_>>();
+    if !compromised.is_empty() {
+        warn!("{} stored passwords are compromised", compromised.len());
+    }
+
+    Ok(compromised)
+}
+
+/// Check all stored passwords and get the compromised ones.
+pub async fn breaches(state: Data<AppState>) -> WebResult<EncryptedBody<Vec<PasswordSummary>>> {
+    if state.config.hibp_path().is_none() {
+        return Err(ErrorNotFound("No breach dataset is configured"));
+    }
+
+    Ok(EncryptedBody::new(
+        check_stored_passwords(&state)
+            .await
+            .map_err(ErrorInternalServerError)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        breach::{self, BreachDatabase},
+        config::Config,
+        password::Password,
+        test,
+    };
+    use actix_web::http::StatusCode;
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn range_directory_and_index() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let ranges = dir.path().join("ranges");
+        fs::create_dir(&ranges)?;
+
+        // SHA-1 of "password" is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+        fs::write(
+            ranges.join("5BAA6.txt"),
+            "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n",
+        )?;
+        // SHA-1 of "123456" is 7C4A8D09CA3762AF61E59520943DC26494F8941B
+        fs::write(
+            ranges.join("7C4A8"),
+            "D09CA3762AF61E59520943DC26494F8941B:37359195\n",
+        )?;
+
+        let database = BreachDatabase::open(&ranges)?;
+        assert_eq!(database, BreachDatabase::RangeDirectory(ranges.clone()));
+        assert!(database.is_compromised("password")?);
+        assert!(database.is_compromised("123456")?);
+        assert!(!database.is_compromised("correct horse battery staple")?);
+
+        // Build a binary index from the range files
+        let index = dir.path().join("index");
+        assert_eq!(breach::build_index(&ranges, &index)?, 3);
+
+        let database = BreachDatabase::open(&index)?;
+        assert_eq!(database, BreachDatabase::Index(index));
+        assert!(database.is_compromised("password")?);
+        assert!(database.is_compromised("123456")?);
+        assert!(!database.is_compromised("correct horse battery staple")?);
+
+        // A random file is not a valid index
+        let invalid = dir.path().join("invalid");
+        fs::write(&invalid, "not an index")?;
+        assert!(BreachDatabase::open(&invalid).is_err());
+
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn check_stored_passwords() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        // SHA-1 of "password" is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+        fs::write(
+            dir.path().join("5BAA6"),
+            "1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\n",
+        )?;
+        let state = test::app_state_with_config(Config::from_raw_str(&format!(
+            "[breach]\nhibp_path = {:?}",
+            dir.path()
+        ))?);
+
+        let mut passwords = state.passwords().await?;
+        passwords.register(Password::new("weak", "password"));
+        passwords.register(Password::new("strong", "correct horse battery staple"));
+        state.set_passwords(&passwords).await?;
+
+        let compromised = breach::check_stored_passwords(&state).await?;
+        assert_eq!(compromised.len(), 1);
+        assert_eq!(compromised[0].name(), "weak");
+
+        // The flags are persisted
+        let passwords = state.passwords().await?;
+        assert_eq!(
+            passwords
+                .iter()
+                .map(|password| password.compromised)
+                .collect::<Vec<_>>(),
+            vec![true, false]
+        );
+
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn breaches_without_dataset() {
+        match breach::breaches(test::app_state()).await {
+            Ok(_) => panic!("Breaches without a dataset should fail"),
+            Err(err) => assert_eq!(err.as_response_error().status_code(), StatusCode::NOT_FOUND),
+        }
+    }
+}
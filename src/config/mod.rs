@@ -1,20 +1,61 @@
-use anyhow::{anyhow, Result};
-use log::info;
-use serde::Deserialize;
-use std::{fmt::Debug, fs, path::Path};
+use crate::{logging::LogTarget, net::ListenAddress};
+use anyhow::{anyhow, bail, Result};
+use log::{info, LevelFilter};
+use serde::{Deserialize, Serialize, Serializer};
+use std::{
+    fmt::Debug,
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
 
 /// Where the configuration file is trying to be found if not specified.
 pub const DEFAULT_CONFIG_FILE_PATH: &str = "/var/lib/keybear/config.toml";
 
+/// Prefix of the environment variables overriding the configuration.
+pub const ENV_PREFIX: &str = "KEYBEAR_";
+/// What secrets are replaced with when the configuration is shown.
+pub const REDACTED_VALUE: &str = "[redacted]";
+
 /// Where the file containing the crypto keys resides.
 pub const DEFAULT_KEY_PATH: &str = "/var/lib/keybear/key";
 /// Where the database resides.
 pub const DEFAULT_DATABASE_PATH: &str = "/var/lib/keybear/db";
 /// The port that the server will listen on for the Tor service.
 pub const DEFAULT_SERVER_PORT: u16 = 52477;
+/// Whether the Tor hidden service sends a PROXY protocol header with the circuit ID.
+pub const DEFAULT_PROXY_PROTOCOL: bool = false;
+/// Where the log messages are written to.
+pub const DEFAULT_LOG_TARGET: LogTarget = LogTarget::Syslog;
+/// The most verbose level of the log messages that are written.
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+/// The file the log messages are written to with the file target.
+pub const DEFAULT_LOG_PATH: &str = "/var/log/keybear/keybear.log";
+/// The maximum size of a single attachment in bytes.
+pub const DEFAULT_ATTACHMENT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+/// The maximum size of all attachments combined in bytes.
+pub const DEFAULT_ATTACHMENT_QUOTA: u64 = 100 * 1024 * 1024;
+/// Directory of the Tor hidden service, containing the onion hostname.
+pub const DEFAULT_TOR_HIDDEN_SERVICE_DIR: &str = "/var/lib/tor/keybear";
+/// Tor configuration file with the hidden service of keybear.
+pub const DEFAULT_TORRC_PATH: &str = "/etc/keybear/torrc";
+/// Port of the onion service when it's created through the Tor control port.
+pub const DEFAULT_TOR_ONION_PORT: u16 = 5219;
+/// Whether only authorized clients can connect to the onion service.
+pub const DEFAULT_TOR_CLIENT_AUTHORIZATION: bool = false;
+/// Whether new devices can register.
+pub const DEFAULT_REGISTRATION_OPEN: bool = true;
+/// How many minutes to wait between taking snapshots of the database.
+pub const DEFAULT_SNAPSHOT_INTERVAL_MINUTES: u64 = 60;
+/// How many of the most recent hourly snapshots are kept.
+pub const DEFAULT_SNAPSHOT_KEEP_HOURLY: usize = 24;
+/// How many of the most recent daily snapshots are kept.
+pub const DEFAULT_SNAPSHOT_KEEP_DAILY: usize = 7;
+/// How many of the most recent weekly snapshots are kept.
+pub const DEFAULT_SNAPSHOT_KEEP_WEEKLY: usize = 4;
 
 /// The application configuration.
-#[derive(Debug, Default, Eq, PartialEq, Deserialize)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
 pub struct Config {
     /// Location of the file containing the secret key.
     key_path: Option<String>,
@@ -22,6 +63,22 @@ pub struct Config {
     database_path: Option<String>,
     /// Information about things like the ports to run on.
     server: Option<ServerConfig>,
+    /// Limits for the file attachments.
+    attachments: Option<AttachmentsConfig>,
+    /// Checking passwords against known data breaches.
+    breach: Option<BreachConfig>,
+    /// Periodic snapshots of the database.
+    snapshots: Option<SnapshotsConfig>,
+    /// Registration of new devices.
+    registration: Option<RegistrationConfig>,
+    /// The Tor hidden service.
+    tor: Option<TorConfig>,
+    /// Serving clients on the local network over TLS.
+    lan: Option<LanConfig>,
+    /// Exposing metrics for monitoring.
+    metrics: Option<MetricsConfig>,
+    /// Where and what to log.
+    log: Option<LogConfig>,
 }
 
 impl Config {
@@ -48,22 +105,57 @@ impl Config {
     where
         P: AsRef<Path>,
     {
-        // Get the generic as the actual reference so it's traits can be used
-        let file = file.as_ref();
+        Self::from_raw_str(&Self::read_file(file.as_ref())?)
+    }
 
-        info!("Loading configuration file {:?}", file);
+    /// Load the config from a file, returning the unknown keys instead of rejecting them.
+    pub fn from_file_with_unknown_keys<P>(file: P) -> Result<(Self, Vec<String>)>
+    where
+        P: AsRef<Path>,
+    {
+        Self::from_raw_str_with_unknown_keys(&Self::read_file(file.as_ref())?)
+    }
 
+    /// Read the contents of the configuration file.
+    fn read_file(file: &Path) -> Result<String> {
         // Attempt to open the configuration file
-        let contents = fs::read_to_string(file)
-            .map_err(|err| anyhow!("Reading configuration file {:?} failed: {}", file, err))?;
-
-        Self::from_raw_str(&contents)
+        fs::read_to_string(file)
+            .map_err(|err| anyhow!("Reading configuration file {:?} failed: {}", file, err))
     }
 
     /// Create the config from a string with all defaults filled.
+    ///
+    /// Unknown keys are rejected so typos don't go unnoticed.
     pub fn from_raw_str(toml: &str) -> Result<Self> {
-        toml::from_str(&toml)
-            .map_err(|err| anyhow!("Reading keybear configuration failed: {}", err))
+        let (config, unknown_keys) = Self::from_raw_str_with_unknown_keys(toml)?;
+        if !unknown_keys.is_empty() {
+            bail!(
+                "Reading keybear configuration failed: unknown keys {}",
+                unknown_keys.join(", ")
+            );
+        }
+
+        Ok(config)
+    }
+
+    /// Create the config from a string, returning the unknown keys with their line numbers
+    /// instead of rejecting them.
+    pub fn from_raw_str_with_unknown_keys(toml: &str) -> Result<(Self, Vec<String>)> {
+        let mut unknown_keys = Vec::new();
+        let config: Self = serde_ignored::deserialize(&mut toml::Deserializer::new(toml), |path| {
+            unknown_keys.push(dotted_key(&path))
+        })
+        .map_err(|err| anyhow!("Reading keybear configuration failed: {}", err))?;
+
+        let unknown_keys = unknown_keys
+            .iter()
+            .map(|key| match key_line(toml, key) {
+                Some(line) => format!("\"{}\" on line {}", key, line),
+                None => format!("\"{}\"", key),
+            })
+            .collect();
+
+        Ok((config, unknown_keys))
     }
 
     /// Path of the secret key.
@@ -71,7 +163,7 @@ impl Config {
         self.key_path
             .as_ref()
             // Convert the string to a path
-            .map(|path_str| Path::new(path_str))
+            .map(Path::new)
             // If no string is set use the default value
             .unwrap_or_else(|| Path::new(DEFAULT_KEY_PATH))
     }
@@ -81,7 +173,7 @@ impl Config {
         self.database_path
             .as_ref()
             // Convert the string to a path
-            .map(|path_str| Path::new(path_str))
+            .map(Path::new)
             // If no string is set use the default value
             .unwrap_or_else(|| Path::new(DEFAULT_DATABASE_PATH))
     }
@@ -95,13 +187,466 @@ impl Config {
             // Otherwise use the default
             .unwrap_or(DEFAULT_SERVER_PORT)
     }
+
+    /// Addresses to listen on for the Tor hidden service.
+    ///
+    /// When none are configured the server only listens on localhost with the server port.
+    pub fn listen_addresses(&self) -> Vec<ListenAddress> {
+        self.server
+            .as_ref()
+            .and_then(|server| server.listen())
+            .filter(|listen| !listen.is_empty())
+            .map(|listen| listen.to_vec())
+            .unwrap_or_else(|| {
+                vec![ListenAddress::Tcp(SocketAddr::from((
+                    Ipv4Addr::LOCALHOST,
+                    self.server_port(),
+                )))]
+            })
+    }
+
+    /// Addresses the Tor hidden service is trusted to connect from.
+    ///
+    /// When not set the loopback addresses the server listens on are trusted.
+    pub fn trusted_sources(&self) -> Option<&[IpAddr]> {
+        self.server
+            .as_ref()
+            .and_then(|server| server.trusted_sources())
+    }
+
+    /// Whether every connection starts with a PROXY protocol header containing the Tor circuit.
+    pub fn proxy_protocol(&self) -> bool {
+        self.server
+            .as_ref()
+            .map(|server| server.proxy_protocol())
+            .unwrap_or(DEFAULT_PROXY_PROTOCOL)
+    }
+
+    /// Maximum amount of requests per minute of a single client, unlimited when not set.
+    pub fn rate_limit_per_minute(&self) -> Option<u32> {
+        self.server
+            .as_ref()
+            .and_then(|server| server.rate_limit_per_minute())
+    }
+
+    /// Maximum size in bytes of a single attachment.
+    pub fn attachment_max_size(&self) -> u64 {
+        self.attachments
+            .as_ref()
+            .map(|attachments| attachments.max_size())
+            .unwrap_or(DEFAULT_ATTACHMENT_MAX_SIZE)
+    }
+
+    /// Maximum size in bytes of all attachments combined.
+    pub fn attachment_quota(&self) -> u64 {
+        self.attachments
+            .as_ref()
+            .map(|attachments| attachments.quota())
+            .unwrap_or(DEFAULT_ATTACHMENT_QUOTA)
+    }
+
+    /// Path of the local Have I Been Pwned dataset, either a directory of range files or a binary
+    /// index.
+    pub fn hibp_path(&self) -> Option<&Path> {
+        self.breach.as_ref().and_then(|breach| breach.hibp_path())
+    }
+
+    /// Directory of the Tor hidden service, containing the onion hostname.
+    pub fn tor_hidden_service_dir(&self) -> &Path {
+        self.tor
+            .as_ref()
+            .map(|tor| tor.hidden_service_dir())
+            .unwrap_or_else(|| Path::new(DEFAULT_TOR_HIDDEN_SERVICE_DIR))
+    }
+
+    /// Tor configuration file with the hidden service of keybear.
+    pub fn torrc_path(&self) -> &Path {
+        self.tor
+            .as_ref()
+            .map(|tor| tor.torrc_path())
+            .unwrap_or_else(|| Path::new(DEFAULT_TORRC_PATH))
+    }
+
+    /// Address of the Tor control port, the onion service is created through it when set.
+    pub fn tor_control_address(&self) -> Option<&str> {
+        self.tor.as_ref().and_then(|tor| tor.control_address())
+    }
+
+    /// Password to authenticate with the Tor control port, the cookie is used when not set.
+    pub fn tor_control_password(&self) -> Option<&str> {
+        self.tor.as_ref().and_then(|tor| tor.control_password())
+    }
+
+    /// Port of the onion service when it's created through the Tor control port.
+    pub fn tor_onion_port(&self) -> u16 {
+        self.tor
+            .as_ref()
+            .map(|tor| tor.onion_port())
+            .unwrap_or(DEFAULT_TOR_ONION_PORT)
+    }
+
+    /// Whether only authorized clients can connect to the onion service, every device gets its own
+    /// key.
+    pub fn tor_client_authorization(&self) -> bool {
+        self.tor
+            .as_ref()
+            .map(|tor| tor.client_authorization())
+            .unwrap_or(DEFAULT_TOR_CLIENT_AUTHORIZATION)
+    }
+
+    /// Directory with the key and hostname of the onion service created through the Tor control
+    /// port, it's next to the secret key.
+    pub fn tor_onion_dir(&self) -> PathBuf {
+        self.key_path()
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join("onion")
+    }
+
+    /// Address to serve clients on the local network over TLS, disabled when not set.
+    pub fn lan_address(&self) -> Option<SocketAddr> {
+        self.lan.as_ref().and_then(|lan| lan.address())
+    }
+
+    /// Path of the self-signed certificate of the LAN listener, it's next to the secret key.
+    pub fn lan_certificate_path(&self) -> PathBuf {
+        self.key_path().with_file_name("lan.crt")
+    }
+
+    /// Path of the private key of the LAN certificate, it's next to the secret key.
+    pub fn lan_private_key_path(&self) -> PathBuf {
+        self.key_path().with_file_name("lan.key")
+    }
+
+    /// Local address to expose the metrics on, disabled when not set.
+    pub fn metrics_address(&self) -> Option<SocketAddr> {
+        self.metrics.as_ref().and_then(|metrics| metrics.address())
+    }
+
+    /// Where the log messages are written to.
+    pub fn log_target(&self) -> LogTarget {
+        self.log
+            .as_ref()
+            .map(|log| log.target())
+            .unwrap_or(DEFAULT_LOG_TARGET)
+    }
+
+    /// The most verbose level of the log messages that are written.
+    pub fn log_level(&self) -> LevelFilter {
+        self.log
+            .as_ref()
+            .map(|log| log.level())
+            .unwrap_or(DEFAULT_LOG_LEVEL)
+    }
+
+    /// The file the log messages are written to with the file target.
+    pub fn log_path(&self) -> &Path {
+        self.log
+            .as_ref()
+            .map(|log| log.path())
+            .unwrap_or_else(|| Path::new(DEFAULT_LOG_PATH))
+    }
+
+    /// Whether new devices can register, existing devices keep working when closed.
+    pub fn registration_open(&self) -> bool {
+        self.registration
+            .as_ref()
+            .map(|registration| registration.open())
+            .unwrap_or(DEFAULT_REGISTRATION_OPEN)
+    }
+
+    /// Directory to write the database snapshots to, snapshots are disabled when not set.
+    pub fn snapshot_directory(&self) -> Option<&Path> {
+        self.snapshots
+            .as_ref()
+            .and_then(|snapshots| snapshots.directory())
+    }
+
+    /// Minutes to wait between taking snapshots of the database.
+    pub fn snapshot_interval_minutes(&self) -> u64 {
+        self.snapshots
+            .as_ref()
+            .map(|snapshots| snapshots.interval_minutes())
+            .unwrap_or(DEFAULT_SNAPSHOT_INTERVAL_MINUTES)
+    }
+
+    /// Amount of the most recent hourly snapshots to keep.
+    pub fn snapshot_keep_hourly(&self) -> usize {
+        self.snapshots
+            .as_ref()
+            .map(|snapshots| snapshots.keep_hourly())
+            .unwrap_or(DEFAULT_SNAPSHOT_KEEP_HOURLY)
+    }
+
+    /// Amount of the most recent daily snapshots to keep.
+    pub fn snapshot_keep_daily(&self) -> usize {
+        self.snapshots
+            .as_ref()
+            .map(|snapshots| snapshots.keep_daily())
+            .unwrap_or(DEFAULT_SNAPSHOT_KEEP_DAILY)
+    }
+
+    /// Amount of the most recent weekly snapshots to keep.
+    pub fn snapshot_keep_weekly(&self) -> usize {
+        self.snapshots
+            .as_ref()
+            .map(|snapshots| snapshots.keep_weekly())
+            .unwrap_or(DEFAULT_SNAPSHOT_KEEP_WEEKLY)
+    }
+
+    /// Override settings by their dotted key, like `server.port`.
+    ///
+    /// The values are parsed as TOML, when that fails or the setting doesn't accept the type
+    /// they are used as a string.
+    pub fn with_overrides<I, K, V>(self, overrides: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let (config, unknown_keys) = self.apply_overrides(overrides)?;
+        if !unknown_keys.is_empty() {
+            bail!(
+                "Overriding keybear configuration failed: unknown keys \"{}\"",
+                unknown_keys.join("\", \"")
+            );
+        }
+
+        Ok(config)
+    }
+
+    /// Override settings with the environment variables starting with `KEYBEAR_`.
+    ///
+    /// The rest of the name is the lowercase key with tables separated by a double underscore,
+    /// `KEYBEAR_SERVER__PORT` overrides `server.port`. Variables that don't match a setting are
+    /// ignored since other programs might use the same prefix, their names are returned.
+    pub fn with_env_overrides<I>(self, vars: I) -> Result<(Self, Vec<String>)>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let (config, unknown_keys) =
+            self.apply_overrides(vars.into_iter().filter_map(|(name, value)| {
+                name.strip_prefix(ENV_PREFIX)
+                    .map(|key| (key.to_lowercase().replace("__", "."), value))
+            }))?;
+
+        let ignored = unknown_keys
+            .iter()
+            .map(|key| format!("{}{}", ENV_PREFIX, key.to_uppercase().replace('.', "__")))
+            .collect();
+
+        Ok((config, ignored))
+    }
+
+    /// Apply the overrides, returns the configuration with the keys that don't match a setting.
+    fn apply_overrides<I, K, V>(&self, overrides: I) -> Result<(Self, Vec<String>)>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut table = toml::Value::try_from(self)
+            .map_err(|err| anyhow!("Serializing keybear configuration failed: {}", err))?;
+        for (key, value) in overrides {
+            let (key, value) = (key.as_ref(), value.as_ref());
+            set_value(&mut table, key, parse_value(value))?;
+
+            // Settings like a password can look like a number, use the string when only that fits
+            if table.clone().try_into::<Self>().is_err() {
+                let mut string_table = table.clone();
+                set_value(
+                    &mut string_table,
+                    key,
+                    toml::Value::String(value.to_string()),
+                )?;
+                if string_table.clone().try_into::<Self>().is_ok() {
+                    table = string_table;
+                }
+            }
+        }
+
+        let mut unknown_keys = Vec::new();
+        let config = serde_ignored::deserialize(table, |path| unknown_keys.push(dotted_key(&path)))
+            .map_err(|err| anyhow!("Overriding keybear configuration failed: {}", err))?;
+
+        Ok((config, unknown_keys))
+    }
+
+    /// The configuration without the Tor control password, so it can leave the server.
+    pub fn without_secrets(&self) -> Self {
+        let mut config = self.clone();
+        if let Some(tor) = &mut config.tor {
+            tor.control_password = None;
+        }
+
+        config
+    }
+
+    /// The configuration with all defaults filled in, as it's used by the server.
+    ///
+    /// The Tor control password is hidden so it can be shown.
+    pub fn effective(&self) -> Self {
+        Self {
+            key_path: Some(self.key_path().display().to_string()),
+            database_path: Some(self.database_path().display().to_string()),
+            server: Some(ServerConfig {
+                port: Some(self.server_port()),
+                listen: Some(self.listen_addresses()),
+                trusted_sources: self.trusted_sources().map(|sources| sources.to_vec()),
+                proxy_protocol: Some(self.proxy_protocol()),
+                rate_limit_per_minute: self.rate_limit_per_minute(),
+            }),
+            attachments: Some(AttachmentsConfig {
+                max_size: Some(self.attachment_max_size()),
+                quota: Some(self.attachment_quota()),
+            }),
+            breach: self.hibp_path().map(|path| BreachConfig {
+                hibp_path: Some(path.display().to_string()),
+            }),
+            snapshots: Some(SnapshotsConfig {
+                directory: self
+                    .snapshot_directory()
+                    .map(|path| path.display().to_string()),
+                interval_minutes: Some(self.snapshot_interval_minutes()),
+                keep_hourly: Some(self.snapshot_keep_hourly()),
+                keep_daily: Some(self.snapshot_keep_daily()),
+                keep_weekly: Some(self.snapshot_keep_weekly()),
+            }),
+            registration: Some(RegistrationConfig {
+                open: Some(self.registration_open()),
+            }),
+            tor: Some(TorConfig {
+                hidden_service_dir: Some(self.tor_hidden_service_dir().display().to_string()),
+                torrc_path: Some(self.torrc_path().display().to_string()),
+                control_address: self.tor_control_address().map(String::from),
+                control_password: self
+                    .tor_control_password()
+                    .map(|_| REDACTED_VALUE.to_string()),
+                onion_port: Some(self.tor_onion_port()),
+                client_authorization: Some(self.tor_client_authorization()),
+            }),
+            lan: self.lan_address().map(|address| LanConfig {
+                address: Some(address),
+            }),
+            metrics: self.metrics_address().map(|address| MetricsConfig {
+                address: Some(address),
+            }),
+            log: Some(LogConfig {
+                target: Some(self.log_target()),
+                level: Some(self.log_level()),
+                path: Some(self.log_path().display().to_string()),
+            }),
+        }
+    }
+}
+
+/// Parse an override value as TOML, falling back to a string.
+fn parse_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::value::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+/// Set a value in the configuration by its dotted key, creating missing tables.
+fn set_value(table: &mut toml::Value, key: &str, value: toml::Value) -> Result<()> {
+    let mut parts = key.split('.').collect::<Vec<_>>();
+    let last = parts
+        .pop()
+        .filter(|last| !last.is_empty())
+        .ok_or_else(|| anyhow!("Configuration key \"{}\" is invalid", key))?;
+
+    let mut current = table;
+    for part in parts {
+        current = current
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("Configuration key \"{}\" is not a table", key))?
+            .entry(part)
+            .or_insert_with(|| toml::Value::Table(Default::default()));
+    }
+    current
+        .as_table_mut()
+        .ok_or_else(|| anyhow!("Configuration key \"{}\" is not a table", key))?
+        .insert(last.to_string(), value);
+
+    Ok(())
+}
+
+/// The dotted key of a setting that's not part of the configuration.
+fn dotted_key(path: &serde_ignored::Path) -> String {
+    match path {
+        serde_ignored::Path::Map { parent, key } => match dotted_key(parent) {
+            parent if parent.is_empty() => key.clone(),
+            parent => format!("{}.{}", parent, key),
+        },
+        serde_ignored::Path::Seq { parent, index } => format!("{}[{}]", dotted_key(parent), index),
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => dotted_key(parent),
+        serde_ignored::Path::Root => String::new(),
+    }
+}
+
+/// The line number of a dotted key or table in the TOML source.
+fn key_line(toml: &str, key: &str) -> Option<usize> {
+    // Remove the quotes and whitespace around the parts of a key
+    let normalize = |key: &str| {
+        key.split('.')
+            .map(|part| part.trim().trim_matches('"'))
+            .collect::<Vec<_>>()
+            .join(".")
+    };
+
+    let mut table = String::new();
+    for (index, line) in toml.lines().enumerate() {
+        let line = line.trim();
+        let full_key = if line.starts_with('[') {
+            table = normalize(
+                line.trim_start_matches('[')
+                    .split(']')
+                    .next()
+                    .unwrap_or_default(),
+            );
+
+            table.clone()
+        } else if let Some((line_key, _)) = line.split_once('=') {
+            match normalize(line_key) {
+                line_key if table.is_empty() => line_key,
+                line_key => format!("{}.{}", table, line_key),
+            }
+        } else {
+            continue;
+        };
+
+        if full_key == key {
+            return Some(index + 1);
+        }
+    }
+
+    None
+}
+
+/// Split a `key=value` override from the command line.
+pub fn parse_override(assignment: &str) -> Result<(&str, &str)> {
+    assignment
+        .split_once('=')
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .ok_or_else(|| anyhow!("Override \"{}\" is not in the form key=value", assignment))
 }
 
 /// Configuration table for the server.
-#[derive(Debug, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
 pub struct ServerConfig {
     /// Port to listen to the Tor hidden service.
     port: Option<u16>,
+    /// Addresses to listen to the Tor hidden service, overrides the port.
+    listen: Option<Vec<ListenAddress>>,
+    /// Addresses the Tor hidden service connects from.
+    trusted_sources: Option<Vec<IpAddr>>,
+    /// Whether Tor sends a PROXY protocol header.
+    proxy_protocol: Option<bool>,
+    /// Maximum amount of requests per minute of a client.
+    rate_limit_per_minute: Option<u32>,
 }
 
 impl ServerConfig {
@@ -109,12 +654,251 @@ impl ServerConfig {
     pub fn port(&self) -> u16 {
         self.port.unwrap_or(DEFAULT_SERVER_PORT)
     }
+
+    /// Addresses to listen on, like `127.0.0.1:52477`, `[::1]:52477` or `unix:/path/to/socket`.
+    pub fn listen(&self) -> Option<&[ListenAddress]> {
+        self.listen.as_deref()
+    }
+
+    /// Addresses the Tor hidden service connects from.
+    pub fn trusted_sources(&self) -> Option<&[IpAddr]> {
+        self.trusted_sources.as_deref()
+    }
+
+    /// Whether Tor sends a PROXY protocol header, enabled with `HiddenServiceExportCircuitID`.
+    pub fn proxy_protocol(&self) -> bool {
+        self.proxy_protocol.unwrap_or(DEFAULT_PROXY_PROTOCOL)
+    }
+
+    /// Maximum amount of requests per minute of a client.
+    pub fn rate_limit_per_minute(&self) -> Option<u32> {
+        self.rate_limit_per_minute
+    }
+}
+
+/// Configuration table for the file attachments.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub struct AttachmentsConfig {
+    /// Maximum size in bytes of a single attachment.
+    max_size: Option<u64>,
+    /// Maximum size in bytes of all attachments combined.
+    quota: Option<u64>,
+}
+
+impl AttachmentsConfig {
+    /// Maximum size in bytes of a single attachment.
+    pub fn max_size(&self) -> u64 {
+        self.max_size.unwrap_or(DEFAULT_ATTACHMENT_MAX_SIZE)
+    }
+
+    /// Maximum size in bytes of all attachments combined.
+    pub fn quota(&self) -> u64 {
+        self.quota.unwrap_or(DEFAULT_ATTACHMENT_QUOTA)
+    }
+}
+
+/// Configuration table for checking passwords against known data breaches.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub struct BreachConfig {
+    /// Path of the local Have I Been Pwned dataset.
+    hibp_path: Option<String>,
+}
+
+impl BreachConfig {
+    /// Path of the local Have I Been Pwned dataset.
+    pub fn hibp_path(&self) -> Option<&Path> {
+        self.hibp_path.as_ref().map(Path::new)
+    }
+}
+
+/// Configuration table for the Tor hidden service.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub struct TorConfig {
+    /// Directory of the hidden service.
+    hidden_service_dir: Option<String>,
+    /// Tor configuration file with the hidden service.
+    torrc_path: Option<String>,
+    /// Address of the control port.
+    control_address: Option<String>,
+    /// Password to authenticate with the control port.
+    control_password: Option<String>,
+    /// Port of the onion service created through the control port.
+    onion_port: Option<u16>,
+    /// Whether only authorized clients can connect to the onion service.
+    client_authorization: Option<bool>,
+}
+
+impl TorConfig {
+    /// Directory of the hidden service.
+    pub fn hidden_service_dir(&self) -> &Path {
+        self.hidden_service_dir
+            .as_ref()
+            .map(Path::new)
+            .unwrap_or_else(|| Path::new(DEFAULT_TOR_HIDDEN_SERVICE_DIR))
+    }
+
+    /// Tor configuration file with the hidden service.
+    pub fn torrc_path(&self) -> &Path {
+        self.torrc_path
+            .as_ref()
+            .map(Path::new)
+            .unwrap_or_else(|| Path::new(DEFAULT_TORRC_PATH))
+    }
+
+    /// Address of the control port.
+    pub fn control_address(&self) -> Option<&str> {
+        self.control_address.as_deref()
+    }
+
+    /// Password to authenticate with the control port.
+    pub fn control_password(&self) -> Option<&str> {
+        self.control_password.as_deref()
+    }
+
+    /// Port of the onion service created through the control port.
+    pub fn onion_port(&self) -> u16 {
+        self.onion_port.unwrap_or(DEFAULT_TOR_ONION_PORT)
+    }
+
+    /// Whether only authorized clients can connect to the onion service.
+    pub fn client_authorization(&self) -> bool {
+        self.client_authorization
+            .unwrap_or(DEFAULT_TOR_CLIENT_AUTHORIZATION)
+    }
+}
+
+/// Configuration table for serving clients on the local network.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub struct LanConfig {
+    /// Address to listen on with TLS.
+    address: Option<SocketAddr>,
+}
+
+impl LanConfig {
+    /// Address to listen on with TLS, like `192.168.1.10:52478`.
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.address
+    }
+}
+
+/// Configuration table for exposing metrics.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub struct MetricsConfig {
+    /// Address to serve the metrics on.
+    address: Option<SocketAddr>,
+}
+
+impl MetricsConfig {
+    /// Address to serve the metrics on, like `127.0.0.1:9477`.
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.address
+    }
+}
+
+/// Configuration table for logging.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub struct LogConfig {
+    /// Where the log messages are written to.
+    target: Option<LogTarget>,
+    /// The most verbose level that's written.
+    #[serde(serialize_with = "serialize_level")]
+    level: Option<LevelFilter>,
+    /// The file written to with the file target.
+    path: Option<String>,
+}
+
+impl LogConfig {
+    /// Where the log messages are written to, one of `syslog`, `journald`, `stderr` or `file`.
+    pub fn target(&self) -> LogTarget {
+        self.target.unwrap_or(DEFAULT_LOG_TARGET)
+    }
+
+    /// The most verbose level that's written, like `info` or `debug`.
+    pub fn level(&self) -> LevelFilter {
+        self.level.unwrap_or(DEFAULT_LOG_LEVEL)
+    }
+
+    /// The file written to with the file target.
+    pub fn path(&self) -> &Path {
+        Path::new(self.path.as_deref().unwrap_or(DEFAULT_LOG_PATH))
+    }
+}
+
+/// Write the log level in lowercase like it's documented.
+fn serialize_level<S>(level: &Option<LevelFilter>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    level
+        .map(|level| level.to_string().to_lowercase())
+        .serialize(serializer)
+}
+
+/// Configuration table for the registration of new devices.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub struct RegistrationConfig {
+    /// Whether new devices can register.
+    open: Option<bool>,
+}
+
+impl RegistrationConfig {
+    /// Whether new devices can register.
+    pub fn open(&self) -> bool {
+        self.open.unwrap_or(DEFAULT_REGISTRATION_OPEN)
+    }
+}
+
+/// Configuration table for the periodic database snapshots.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+pub struct SnapshotsConfig {
+    /// Directory to write the snapshots to.
+    directory: Option<String>,
+    /// Minutes between two snapshots.
+    interval_minutes: Option<u64>,
+    /// Amount of hourly snapshots to keep.
+    keep_hourly: Option<usize>,
+    /// Amount of daily snapshots to keep.
+    keep_daily: Option<usize>,
+    /// Amount of weekly snapshots to keep.
+    keep_weekly: Option<usize>,
+}
+
+impl SnapshotsConfig {
+    /// Directory to write the snapshots to.
+    pub fn directory(&self) -> Option<&Path> {
+        self.directory.as_ref().map(Path::new)
+    }
+
+    /// Minutes between two snapshots.
+    pub fn interval_minutes(&self) -> u64 {
+        self.interval_minutes
+            .unwrap_or(DEFAULT_SNAPSHOT_INTERVAL_MINUTES)
+    }
+
+    /// Amount of hourly snapshots to keep.
+    pub fn keep_hourly(&self) -> usize {
+        self.keep_hourly.unwrap_or(DEFAULT_SNAPSHOT_KEEP_HOURLY)
+    }
+
+    /// Amount of daily snapshots to keep.
+    pub fn keep_daily(&self) -> usize {
+        self.keep_daily.unwrap_or(DEFAULT_SNAPSHOT_KEEP_DAILY)
+    }
+
+    /// Amount of weekly snapshots to keep.
+    pub fn keep_weekly(&self) -> usize {
+        self.keep_weekly.unwrap_or(DEFAULT_SNAPSHOT_KEEP_WEEKLY)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::config::{self, Config};
+    use crate::{
+        config::{self, Config},
+        logging::LogTarget,
+    };
     use anyhow::Result;
+    use log::LevelFilter;
     use std::path::Path;
 
     #[test]
@@ -130,6 +914,63 @@ mod tests {
             Path::new(config::DEFAULT_DATABASE_PATH)
         );
         assert_eq!(config.server_port(), config::DEFAULT_SERVER_PORT);
+        assert_eq!(config.listen_addresses(), vec!["127.0.0.1:52477".parse()?]);
+        assert_eq!(config.trusted_sources(), None);
+        assert_eq!(config.proxy_protocol(), config::DEFAULT_PROXY_PROTOCOL);
+        assert_eq!(config.rate_limit_per_minute(), None);
+        assert_eq!(
+            config.attachment_max_size(),
+            config::DEFAULT_ATTACHMENT_MAX_SIZE
+        );
+        assert_eq!(config.attachment_quota(), config::DEFAULT_ATTACHMENT_QUOTA);
+        assert_eq!(config.hibp_path(), None);
+        assert_eq!(
+            config.tor_hidden_service_dir(),
+            Path::new(config::DEFAULT_TOR_HIDDEN_SERVICE_DIR)
+        );
+        assert_eq!(config.torrc_path(), Path::new(config::DEFAULT_TORRC_PATH));
+        assert_eq!(config.tor_control_address(), None);
+        assert_eq!(config.tor_control_password(), None);
+        assert_eq!(config.tor_onion_port(), config::DEFAULT_TOR_ONION_PORT);
+        assert_eq!(
+            config.tor_client_authorization(),
+            config::DEFAULT_TOR_CLIENT_AUTHORIZATION
+        );
+        assert_eq!(config.tor_onion_dir(), Path::new("/var/lib/keybear/onion"));
+        assert_eq!(config.lan_address(), None);
+        assert_eq!(config.metrics_address(), None);
+        assert_eq!(config.log_target(), config::DEFAULT_LOG_TARGET);
+        assert_eq!(config.log_level(), config::DEFAULT_LOG_LEVEL);
+        assert_eq!(config.log_path(), Path::new(config::DEFAULT_LOG_PATH));
+        assert_eq!(
+            config.lan_certificate_path(),
+            Path::new("/var/lib/keybear/lan.crt")
+        );
+        assert_eq!(
+            config.lan_private_key_path(),
+            Path::new("/var/lib/keybear/lan.key")
+        );
+        assert_eq!(
+            config.registration_open(),
+            config::DEFAULT_REGISTRATION_OPEN
+        );
+        assert_eq!(config.snapshot_directory(), None);
+        assert_eq!(
+            config.snapshot_interval_minutes(),
+            config::DEFAULT_SNAPSHOT_INTERVAL_MINUTES
+        );
+        assert_eq!(
+            config.snapshot_keep_hourly(),
+            config::DEFAULT_SNAPSHOT_KEEP_HOURLY
+        );
+        assert_eq!(
+            config.snapshot_keep_daily(),
+            config::DEFAULT_SNAPSHOT_KEEP_DAILY
+        );
+        assert_eq!(
+            config.snapshot_keep_weekly(),
+            config::DEFAULT_SNAPSHOT_KEEP_WEEKLY
+        );
 
         Ok(())
     }
@@ -151,14 +992,244 @@ mod tests {
 
             [server]
             port = 1234
+            listen = ["[::1]:1234", "unix:/run/keybear/keybear.sock"]
+            trusted_sources = ["::1"]
+            proxy_protocol = true
+            rate_limit_per_minute = 60
+
+            [attachments]
+            max_size = 1024
+            quota = 4096
+
+            [breach]
+            hibp_path = "/var/lib/hibp"
+
+            [tor]
+            hidden_service_dir = "/var/lib/tor/other"
+            torrc_path = "/etc/tor/torrc"
+            control_address = "127.0.0.1:9051"
+            control_password = "secret"
+            onion_port = 80
+            client_authorization = true
+
+            [registration]
+            open = false
+
+            [lan]
+            address = "192.168.1.10:52478"
+
+            [metrics]
+            address = "127.0.0.1:9477"
+
+            [log]
+            target = "journald"
+            level = "warn"
+            path = "/tmp/keybear.log"
+
+            [snapshots]
+            directory = "/var/backups/keybear"
+            interval_minutes = 15
+            keep_hourly = 12
+            keep_daily = 3
+            keep_weekly = 0
         "#,
         )?;
         assert_eq!(config.key_path(), Path::new("some_path"));
         assert_eq!(config.database_path(), Path::new("some_other_path"));
         assert_eq!(config.server_port(), 1234);
+        assert_eq!(
+            config.listen_addresses(),
+            vec![
+                "[::1]:1234".parse()?,
+                "unix:/run/keybear/keybear.sock".parse()?
+            ]
+        );
+        assert_eq!(config.trusted_sources(), Some(&["::1".parse()?][..]));
+        assert!(config.proxy_protocol());
+        assert_eq!(config.rate_limit_per_minute(), Some(60));
+        assert_eq!(config.attachment_max_size(), 1024);
+        assert_eq!(config.attachment_quota(), 4096);
+        assert_eq!(config.hibp_path(), Some(Path::new("/var/lib/hibp")));
+        assert_eq!(
+            config.tor_hidden_service_dir(),
+            Path::new("/var/lib/tor/other")
+        );
+        assert_eq!(config.torrc_path(), Path::new("/etc/tor/torrc"));
+        assert_eq!(config.tor_control_address(), Some("127.0.0.1:9051"));
+        assert_eq!(config.tor_control_password(), Some("secret"));
+        assert_eq!(config.tor_onion_port(), 80);
+        assert!(config.tor_client_authorization());
+        assert_eq!(config.tor_onion_dir(), Path::new("onion"));
+        assert_eq!(config.lan_address(), Some("192.168.1.10:52478".parse()?));
+        assert_eq!(config.lan_certificate_path(), Path::new("lan.crt"));
+        assert_eq!(config.metrics_address(), Some("127.0.0.1:9477".parse()?));
+        assert_eq!(config.log_target(), LogTarget::Journald);
+        assert_eq!(config.log_level(), LevelFilter::Warn);
+        assert_eq!(config.log_path(), Path::new("/tmp/keybear.log"));
+        assert!(!config.registration_open());
+        assert_eq!(
+            config.snapshot_directory(),
+            Some(Path::new("/var/backups/keybear"))
+        );
+        assert_eq!(config.snapshot_interval_minutes(), 15);
+        assert_eq!(config.snapshot_keep_hourly(), 12);
+        assert_eq!(config.snapshot_keep_daily(), 3);
+        assert_eq!(config.snapshot_keep_weekly(), 0);
 
         // Verify that we get errors when an invalid config is used
         assert!(Config::from_raw_str("*invalid*").is_err());
+        assert!(Config::from_raw_str("[server]\nlisten = [\"localhost\"]").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_keys() -> Result<()> {
+        let err = Config::from_raw_str(
+            r#"
+            databse_path = "typo"
+
+            [server]
+            port = 1234
+            prot = 4321
+
+            [snapshot]
+            directory = "/var/backups/keybear"
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+        // All unknown keys are reported at once
+        assert!(err.contains("\"databse_path\" on line 2"));
+        assert!(err.contains("\"server.prot\" on line 6"));
+        assert!(err.contains("\"snapshot\" on line 8"));
+
+        assert!(Config::from_raw_str("server = { prot = 1 }")
+            .unwrap_err()
+            .to_string()
+            .contains("\"server.prot\""));
+
+        // They can also be returned to report them together with other problems
+        let (config, unknown_keys) =
+            Config::from_raw_str_with_unknown_keys("key_path = \"key\"\nprot = 1")?;
+        assert_eq!(config.key_path(), Path::new("key"));
+        assert_eq!(unknown_keys, vec!["\"prot\" on line 2".to_string()]);
+
+        // Overrides are checked as well
+        assert!(Config::default()
+            .with_overrides(vec![("server.prot", "1234")])
+            .is_err());
+        // Except for the environment, which is shared with other programs
+        assert_eq!(
+            Config::default().with_env_overrides(vec![(
+                "KEYBEAR_DATABSE_PATH".to_string(),
+                "typo".to_string()
+            )])?,
+            (Config::default(), vec!["KEYBEAR_DATABSE_PATH".to_string()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn overrides() -> Result<()> {
+        let config = Config::from_raw_str(
+            r#"
+            key_path = "some_path"
+
+            [server]
+            port = 1234
+            "#,
+        )?
+        .with_overrides(vec![
+            ("server.port", "4321"),
+            ("server.listen", "[\"[::1]:4321\"]"),
+            ("tor.control_address", "127.0.0.1:9051"),
+            ("log.level", "debug"),
+        ])?;
+        assert_eq!(config.key_path(), Path::new("some_path"));
+        assert_eq!(config.server_port(), 4321);
+        assert_eq!(config.listen_addresses(), vec!["[::1]:4321".parse()?]);
+        // Values that aren't valid TOML are strings
+        assert_eq!(config.tor_control_address(), Some("127.0.0.1:9051"));
+        assert_eq!(config.log_level(), LevelFilter::Debug);
+
+        // Numbers can be quoted to be used as a string
+        let config =
+            Config::default().with_overrides(vec![("tor.control_password", "\"1234\"")])?;
+        assert_eq!(config.tor_control_password(), Some("1234"));
+        // But that's not needed when only a string fits
+        let config = Config::default().with_overrides(vec![("tor.control_password", "1234")])?;
+        assert_eq!(config.tor_control_password(), Some("1234"));
+
+        assert!(Config::default()
+            .with_overrides(vec![("server.port", "invalid")])
+            .is_err());
+        assert!(Config::default()
+            .with_overrides(vec![("key_path.nested", "invalid")])
+            .is_err());
+        assert!(Config::default().with_overrides(vec![("", "1")]).is_err());
+
+        assert_eq!(
+            config::parse_override("server.port=1234")?,
+            ("server.port", "1234")
+        );
+        assert_eq!(config::parse_override("a=b=c")?, ("a", "b=c"));
+        assert!(config::parse_override("server.port").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn env_overrides() -> Result<()> {
+        let (config, ignored) = Config::default().with_env_overrides(vec![
+            ("KEYBEAR_DATABASE_PATH".to_string(), "/data/db".to_string()),
+            (
+                "KEYBEAR_SERVER__PROXY_PROTOCOL".to_string(),
+                "true".to_string(),
+            ),
+            ("KEYBEAR_SNAPSHOTS__KEEP_DAILY".to_string(), "2".to_string()),
+            (
+                "KEYBEAR_TOR__CONTROL_PASSWORD".to_string(),
+                "123456".to_string(),
+            ),
+            // Other variables are ignored
+            ("HOME".to_string(), "/root".to_string()),
+            // Including unknown ones with the same prefix
+            ("KEYBEAR_VERSION".to_string(), "1.0".to_string()),
+        ])?;
+        assert_eq!(config.database_path(), Path::new("/data/db"));
+        assert!(config.proxy_protocol());
+        assert_eq!(config.snapshot_keep_daily(), 2);
+        assert_eq!(config.tor_control_password(), Some("123456"));
+        assert_eq!(config.key_path(), Path::new(config::DEFAULT_KEY_PATH));
+        assert_eq!(ignored, vec!["KEYBEAR_VERSION".to_string()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn effective() -> Result<()> {
+        let config = Config::from_raw_str(
+            r#"
+            [tor]
+            control_password = "secret"
+            "#,
+        )?;
+        let effective = config.effective();
+        assert_eq!(effective.server_port(), config::DEFAULT_SERVER_PORT);
+        assert_eq!(effective.listen_addresses(), config.listen_addresses());
+        assert_eq!(
+            effective.tor_control_password(),
+            Some(config::REDACTED_VALUE)
+        );
+        assert_eq!(effective.lan_address(), None);
+
+        // It can be loaded again
+        let shown = toml::to_string(&effective)?;
+        assert!(!shown.contains("secret"));
+        assert!(shown.contains("level = \"info\""));
+        assert_eq!(Config::from_raw_str(&shown)?, effective);
 
         Ok(())
     }
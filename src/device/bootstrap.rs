@@ -0,0 +1,119 @@
+use crate::app::AppState;
+use actix_web::{error::ErrorForbidden, HttpRequest, Result as WebResult};
+use anyhow::Result;
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+
+/// Header containing the bootstrap token when registering the first device.
+pub const BOOTSTRAP_TOKEN_HEADER: &str = "keybear-bootstrap-token";
+
+/// Amount of characters in a bootstrap token.
+const TOKEN_LENGTH: usize = 32;
+
+/// Generate a new random bootstrap token.
+pub fn generate() -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Get the token the first device must register with, a new one is generated when needed.
+///
+/// Returns `None` when devices are already registered.
+pub async fn ensure_token(state: &AppState) -> Result<Option<String>> {
+    if !state.devices().await?.is_empty() {
+        return Ok(None);
+    }
+
+    match state.bootstrap_token().await? {
+        Some(token) => Ok(Some(token)),
+        None => {
+            let token = generate();
+            state.set_bootstrap_token(Some(&token)).await?;
+
+            Ok(Some(token))
+        }
+    }
+}
+
+/// Check that the request contains the bootstrap token.
+pub fn check(request: &HttpRequest, token: &str) -> WebResult<()> {
+    let header = request
+        .headers()
+        .get(BOOTSTRAP_TOKEN_HEADER)
+        .and_then(|header| header.to_str().ok());
+
+    if header == Some(token) {
+        Ok(())
+    } else {
+        Err(ErrorForbidden(
+            "The first device must register with the bootstrap token",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        app::AppState,
+        config::Config,
+        device::bootstrap::{self, BOOTSTRAP_TOKEN_HEADER},
+        test,
+    };
+    use actix_web::{http::StatusCode, test::TestRequest, web::Data};
+    use anyhow::Result;
+
+    /// Register a new device, returning the status code.
+    async fn register(state: &Data<AppState>, token: Option<&str>) -> StatusCode {
+        let mut request = TestRequest::default();
+        if let Some(token) = token {
+            request = request.header(BOOTSTRAP_TOKEN_HEADER, token);
+        }
+
+        match test::register_device_with_request(state, request, "test_device").await {
+            Ok(_) => StatusCode::OK,
+            Err(err) => err.as_response_error().status_code(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn first_device() -> Result<()> {
+        let state = test::app_state();
+        let token = bootstrap::ensure_token(&state).await?.unwrap();
+        // The same token is used until a device is registered
+        assert_eq!(bootstrap::ensure_token(&state).await?, Some(token.clone()));
+
+        assert_eq!(register(&state, None).await, StatusCode::FORBIDDEN);
+        assert_eq!(register(&state, Some("wrong")).await, StatusCode::FORBIDDEN);
+        assert_eq!(register(&state, Some(&token)).await, StatusCode::OK);
+
+        // The token can't be used anymore after the first device is registered
+        assert_eq!(bootstrap::ensure_token(&state).await?, None);
+        assert_eq!(state.bootstrap_token().await?, None);
+        // Other devices need to be verified instead
+        assert_eq!(register(&state, None).await, StatusCode::OK);
+        assert_eq!(
+            state
+                .verification_devices()
+                .await?
+                .to_needs_verification_vec()
+                .len(),
+            1
+        );
+
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn closed_registration() -> Result<()> {
+        let state =
+            test::app_state_with_config(Config::from_raw_str("[registration]\nopen = false")?);
+        let token = bootstrap::ensure_token(&state).await?.unwrap();
+
+        assert_eq!(register(&state, Some(&token)).await, StatusCode::FORBIDDEN);
+        assert!(state.devices().await?.is_empty());
+
+        Ok(())
+    }
+}
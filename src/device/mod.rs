@@ -1,3 +1,4 @@
+pub mod bootstrap;
 pub mod nonce;
 pub mod register;
 
@@ -38,6 +39,18 @@ impl Devices {
         self.devices.iter().find(|device| device.id == id)
     }
 
+    /// Remove a device, returning it when it was registered.
+    pub fn remove(&mut self, id: &str) -> Option<Device> {
+        let index = self.devices.iter().position(|device| device.id == id)?;
+
+        Some(self.devices.remove(index))
+    }
+
+    /// Iterate over all devices.
+    pub fn iter(&self) -> impl Iterator<Item = &Device> {
+        self.devices.iter()
+    }
+
     /// Override a device.
     pub fn set(&mut self, device: &Device) {
         self.devices.iter_mut().for_each(|cached| {
@@ -72,9 +85,40 @@ pub struct Device {
     public_key: PublicKey,
     /// A single use nonce.
     nonce: Option<SerializableNonce>,
+    /// Base32 encoded private key to connect to the onion service with client authorization.
+    #[serde(default)]
+    client_auth_key: Option<String>,
 }
 
 impl Device {
+    /// Random generated identifier of the device.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Name of the device as configured by the user.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Change the name of the device.
+    pub fn set_name<S>(&mut self, name: S)
+    where
+        S: Into<String>,
+    {
+        self.name = name.into();
+    }
+
+    /// Base32 encoded private key to connect to the onion service with client authorization.
+    pub fn client_auth_key(&self) -> Option<&str> {
+        self.client_auth_key.as_deref()
+    }
+
+    /// Set the private key to connect to the onion service with client authorization.
+    pub fn set_client_auth_key(&mut self, client_auth_key: Option<String>) {
+        self.client_auth_key = client_auth_key;
+    }
+
     /// Create a public view device of this device.
     pub fn to_public_device(&self) -> PublicDevice {
         PublicDevice::new(&self.id, &self.name)
@@ -114,7 +158,7 @@ impl Device {
         T: Serialize,
     {
         match &self.nonce {
-            Some(nonce) => crypto::encrypt(&self.shared_key(server_key), &nonce.to_nonce(), obj),
+            Some(nonce) => crypto::encrypt(&self.shared_key(server_key), nonce.to_nonce(), obj),
             None => bail!("Could not encrypt response because no nonce was saved for this device"),
         }
     }
@@ -125,11 +169,9 @@ impl Device {
         T: DeserializeOwned,
     {
         match &self.nonce {
-            Some(nonce) => crypto::decrypt(
-                &self.shared_key(server_key),
-                &nonce.to_nonce(),
-                cipher_bytes,
-            ),
+            Some(nonce) => {
+                crypto::decrypt(&self.shared_key(server_key), nonce.to_nonce(), cipher_bytes)
+            }
             None => bail!("Could not decrypt request because no nonce was saved for this device"),
         }
     }
@@ -65,6 +65,8 @@ pub async fn nonce(
                 .await
                 .map_err(ErrorInternalServerError)?;
 
+            state.metrics.record_nonce_issued();
+
             // Return the nonce as JSON
             Ok(Json(
                 device.nonce().map_err(ErrorInternalServerError)?.clone(),
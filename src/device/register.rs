@@ -1,17 +1,19 @@
 use crate::{
     app::AppState,
     body::EncryptedBody,
-    device::{Device, ToDevice},
+    device::{bootstrap, Device, ToDevice},
+    lan,
+    tor::client_auth::{self, ClientAuthKey},
 };
 use actix_web::{
-    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    error::{ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorNotFound},
     web::{Data, Json},
-    Result as WebResult,
+    HttpRequest, Result as WebResult,
 };
 use anyhow::{anyhow, Context, Result};
 use keybear_core::types::{NeedsVerificationDevice, RegisterDeviceRequest, RegisterDeviceResponse};
 use serde::{Deserialize, Serialize};
-use std::convert::TryInto;
+use std::{convert::TryInto, ops::Deref};
 use uuid::Uuid;
 use x25519_dalek::PublicKey;
 
@@ -36,6 +38,27 @@ impl PartialEq<str> for VerificationCode {
     }
 }
 
+/// The result from successfully registering a device.
+///
+/// Extends the response of `keybear_core` with the information only known by this server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisterResponse {
+    /// The response every client understands.
+    #[serde(flatten)]
+    pub device: RegisterDeviceResponse,
+    /// SHA-256 fingerprint of the certificate of the LAN listener, so clients can pin it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lan_certificate_fingerprint: Option<String>,
+}
+
+impl Deref for RegisterResponse {
+    type Target = RegisterDeviceResponse;
+
+    fn deref(&self) -> &Self::Target {
+        &self.device
+    }
+}
+
 /// A list of endpoints awaiting registration.
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct VerificationDevices {
@@ -55,9 +78,14 @@ impl VerificationDevices {
         self.devices.iter().find(|(_, device)| device.id == id)
     }
 
-    /// Remove a verification device from the list.
-    pub fn remove(&mut self, id: &str) {
-        self.devices.retain(|(_, device)| device.id != id)
+    /// Remove a verification device from the list, returning it when it was awaiting verification.
+    pub fn remove(&mut self, id: &str) -> Option<Device> {
+        let index = self
+            .devices
+            .iter()
+            .position(|(_, device)| device.id == id)?;
+
+        Some(self.devices.remove(index).1)
     }
 
     /// Get a vector of devices that need to be registered as allowed to be shown to the clients.
@@ -89,6 +117,7 @@ impl ToDevice for RegisterDeviceRequest {
             id,
             public_key,
             nonce: None,
+            client_auth_key: None,
         })
     }
 }
@@ -119,17 +148,32 @@ pub async fn verification_devices(
 
 /// Register a new device endpoint.
 pub async fn register(
+    request: HttpRequest,
     register_device: Json<RegisterDeviceRequest>,
     state: Data<AppState>,
-) -> WebResult<Json<RegisterDeviceResponse>> {
+) -> WebResult<Json<RegisterResponse>> {
+    // Refuse all new devices when registration is closed
+    if !state.config.registration_open() {
+        return Err(ErrorForbidden("Registration of new devices is closed"));
+    }
+
+    // Clients pin the certificate so they can safely connect over the local network later
+    let lan_certificate_fingerprint =
+        lan::certificate_fingerprint(&state.config).map_err(ErrorInternalServerError)?;
+
     // Extract the device from the JSON
     let register_device = register_device.into_inner();
 
     // Convert the register device into a device that we can put in the database
-    let device = register_device
+    let mut device = register_device
         .to_device()
         .map_err(ErrorInternalServerError)?;
 
+    // Give every device its own key to connect to the onion service
+    if state.config.tor_client_authorization() {
+        device.set_client_auth_key(Some(ClientAuthKey::generate().private_key()));
+    }
+
     // Get the registered devices
     let mut devices = state
         .devices()
@@ -137,7 +181,15 @@ pub async fn register(
         // Convert the anyhow error to an internal server error
         .map_err(ErrorInternalServerError)?;
     if devices.is_empty() {
-        // This is the first device, no need to verify it
+        // This is the first device, it only has to prove it knows the bootstrap token
+        if let Some(token) = state
+            .bootstrap_token()
+            .await
+            .map_err(ErrorInternalServerError)?
+        {
+            bootstrap::check(&request, &token)?;
+        }
+
         devices.register(device.clone());
 
         // Set the devices
@@ -146,10 +198,19 @@ pub async fn register(
             .await
             .map_err(ErrorInternalServerError)?;
 
+        client_auth::authorize_device(&state.config, &device).map_err(ErrorInternalServerError)?;
+
+        // The token can't be used again
+        state
+            .set_bootstrap_token(None)
+            .await
+            .map_err(ErrorInternalServerError)?;
+
         // TODO: return a different device type
-        Ok(Json(
-            device.to_register_device_result(&state.secret_key, ""),
-        ))
+        Ok(Json(RegisterResponse {
+            device: device.to_register_device_result(&state.secret_key, ""),
+            lan_certificate_fingerprint,
+        }))
     } else {
         // Get the list of devices that still need to be verified from the state
         let mut verification_devices = state
@@ -168,10 +229,10 @@ pub async fn register(
         state.set_verification_devices(verification_devices).await?;
 
         // Return a view of the device
-        Ok(Json(device.to_register_device_result(
-            &state.secret_key,
-            verification_code.as_str(),
-        )))
+        Ok(Json(RegisterResponse {
+            device: device.to_register_device_result(&state.secret_key, verification_code.as_str()),
+            lan_certificate_fingerprint,
+        }))
     }
 }
 
@@ -181,7 +242,7 @@ pub async fn verify(
     state: Data<AppState>,
 ) -> WebResult<EncryptedBody<()>> {
     // Get the list of devices that still need to be verified from the state
-    let mut verification_devices = state
+    let verification_devices = state
         .verification_devices()
         .await
         // Convert the anyhow error to an internal server error
@@ -201,7 +262,7 @@ pub async fn verify(
     }
 
     // Find the device with the matching ID
-    let (verification_code, device) = verification_devices
+    let (verification_code, _) = verification_devices
         .find(verification_device.id())
         .ok_or_else(|| {
             ErrorNotFound(format!(
@@ -216,25 +277,39 @@ pub async fn verify(
     }
 
     // The verification code is valid, register the device
-
-    // Add the verification device to the registered devices
-    let mut devices = state
-        .devices()
+    approve(&state, verification_device.id())
         .await
-        // Convert the anyhow error to an internal server error
         .map_err(ErrorInternalServerError)?;
+
+    // TODO: allow empty returns
+    Ok(EncryptedBody::new(()))
+}
+
+/// Register a device that is awaiting verification, without checking the verification code.
+pub async fn approve(state: &AppState, id: &str) -> Result<Device> {
+    // Get the list of devices that still need to be verified from the state
+    let mut verification_devices = state.verification_devices().await?;
+
+    let (_, device) = verification_devices
+        .find(id)
+        .cloned()
+        .ok_or_else(|| anyhow!("Device with ID \"{}\" is not awaiting verification", id))?;
+
+    // Add the verification device to the registered devices
+    let mut devices = state.devices().await?;
     devices.register(device.clone());
 
     // Remove the verification device
-    verification_devices.remove(verification_device.id());
+    verification_devices.remove(id);
 
     // Set the devices
-    state.set_verification_devices(verification_devices).await?;
     state
-        .set_devices(devices)
+        .set_verification_devices(verification_devices)
         .await
-        .map_err(ErrorInternalServerError)?;
+        .map_err(|err| anyhow!("Error setting verification devices on database: {}", err))?;
+    state.set_devices(devices).await?;
 
-    // TODO: allow empty returns
-    Ok(EncryptedBody::new(()))
+    client_auth::authorize_device(&state.config, &device)?;
+
+    Ok(device)
 }
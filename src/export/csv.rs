@@ -0,0 +1,88 @@
+use crate::password::Passwords;
+use anyhow::Result;
+use csv::Writer;
+
+/// The header of the exported file, the first columns match the Chrome layout.
+const HEADER: &[&str] = &[
+    "name", "url", "username", "password", "note", "totp", "folder", "email",
+];
+
+/// Export all passwords into an unencrypted CSV file.
+pub fn export(passwords: &Passwords) -> Result<Vec<u8>> {
+    let mut writer = Writer::from_writer(Vec::new());
+
+    writer.write_record(HEADER)?;
+    for password in passwords.iter() {
+        writer.write_record([
+            password.name.as_str(),
+            password.website.as_deref().unwrap_or_default(),
+            password.login().unwrap_or_default(),
+            password.password.as_str(),
+            password.notes.as_deref().unwrap_or_default(),
+            password.totp.as_deref().unwrap_or_default(),
+            password.folder.as_deref().unwrap_or_default(),
+            password.email.as_deref().unwrap_or_default(),
+        ])?;
+    }
+
+    Ok(writer.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        export::csv,
+        import::csv as import_csv,
+        password::{Password, Passwords},
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let mut passwords = Passwords::default();
+        passwords.register(Password {
+            email: Some("user@example.com".to_string()),
+            username: Some("user".to_string()),
+            website: Some("https://github.com".to_string()),
+            totp: Some("JBSWY3DPEHPK3PXP".to_string()),
+            notes: Some("Line one\nLine two, with a comma".to_string()),
+            folder: Some("Work/Code".to_string()),
+            ..Password::new("GitHub", "hunter\"2")
+        });
+
+        let data = String::from_utf8(csv::export(&passwords)?)?;
+        assert!(data.starts_with("name,url,username,password,note"));
+
+        let parsed = import_csv::parse(&data)?;
+        let github = &parsed.passwords[0];
+        assert_eq!(github.name, "GitHub");
+        assert_eq!(github.password, "hunter\"2");
+        assert_eq!(github.username.as_deref(), Some("user"));
+        assert_eq!(github.email.as_deref(), Some("user@example.com"));
+        assert_eq!(github.website.as_deref(), Some("https://github.com"));
+        assert_eq!(github.totp.as_deref(), Some("JBSWY3DPEHPK3PXP"));
+        assert_eq!(
+            github.notes.as_deref(),
+            Some("Line one\nLine two, with a comma")
+        );
+        assert_eq!(github.folder.as_deref(), Some("Work/Code"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn surrounding_spaces() -> Result<()> {
+        let mut passwords = Passwords::default();
+        passwords.register(Password {
+            notes: Some("\tindented\n".to_string()),
+            ..Password::new("Router", " pass word  ")
+        });
+
+        let parsed = import_csv::parse(&String::from_utf8(csv::export(&passwords)?)?)?;
+        let router = &parsed.passwords[0];
+        assert_eq!(router.password.as_bytes(), b" pass word  ");
+        assert_eq!(router.notes.as_deref(), Some("\tindented\n"));
+
+        Ok(())
+    }
+}
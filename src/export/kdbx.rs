@@ -0,0 +1,299 @@
+use crate::{
+    item::{
+        kind::{ItemFields, ItemType},
+        Items,
+    },
+    password::Passwords,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::DateTime;
+use keepass::{
+    config::{DatabaseConfig, KdfConfig},
+    db::{fields, GroupId},
+    Database, DatabaseKey,
+};
+use serde_json::Value;
+
+/// Name of the custom field holding the e-mail when it's not the username.
+pub(crate) const EMAIL_FIELD: &str = "E-mail";
+/// Name of the custom field holding the type of an item that isn't a login.
+pub(crate) const ITEM_TYPE_FIELD: &str = "Item type";
+/// Argon2 iterations of the key derivation.
+const KDF_ITERATIONS: u64 = 10;
+/// Argon2 memory in bytes of the key derivation.
+const KDF_MEMORY: u64 = 64 * 1024 * 1024;
+/// Argon2 parallelism of the key derivation.
+const KDF_PARALLELISM: u32 = 2;
+
+/// Export all passwords and items into a KeePass KDBX 4 database encrypted with the master
+/// password.
+///
+/// Folders are split on `/` into nested groups. An e-mail that differs from the username is
+/// written to a custom field. Items are put in a group per type, with every field of the item
+/// written to a custom field named after it.
+pub fn export(passwords: &Passwords, items: &Items, master_password: &str) -> Result<Vec<u8>> {
+    // Use the same key derivation strength as KeePassXC instead of the much heavier default
+    export_with_kdf(
+        passwords,
+        items,
+        master_password,
+        KDF_ITERATIONS,
+        KDF_MEMORY,
+        KDF_PARALLELISM,
+    )
+}
+
+/// Export all passwords and items with custom Argon2 parameters for the key derivation.
+fn export_with_kdf(
+    passwords: &Passwords,
+    items: &Items,
+    master_password: &str,
+    kdf_iterations: u64,
+    kdf_memory: u64,
+    kdf_parallelism: u32,
+) -> Result<Vec<u8>> {
+    let mut config = DatabaseConfig::default();
+    if let KdfConfig::Argon2 {
+        iterations,
+        memory,
+        parallelism,
+        ..
+    } = &mut config.kdf_config
+    {
+        *iterations = kdf_iterations;
+        *memory = kdf_memory;
+        *parallelism = kdf_parallelism;
+    }
+
+    let mut database = Database::with_config(config);
+    database.meta.database_name = Some("keybear".to_string());
+
+    for password in passwords.iter() {
+        let group_id = group(
+            &mut database,
+            password.folder.as_deref().unwrap_or_default(),
+        )?;
+
+        let mut group = database
+            .group_mut(group_id)
+            .ok_or_else(|| anyhow!("Group disappeared while exporting"))?;
+        let mut entry = group.add_entry();
+
+        entry.set_unprotected(fields::TITLE, &password.name);
+        entry.set_protected(fields::PASSWORD, &password.password);
+        if let Some(login) = password.login() {
+            entry.set_unprotected(fields::USERNAME, login);
+        }
+        if let Some(email) = password.email.as_deref().filter(|email| {
+            // Don't repeat it when it's already the username
+            Some(*email) != password.login()
+        }) {
+            entry.set_unprotected(EMAIL_FIELD, email);
+        }
+        if let Some(website) = &password.website {
+            entry.set_unprotected(fields::URL, website);
+        }
+        if let Some(notes) = &password.notes {
+            entry.set_unprotected(fields::NOTES, notes);
+        }
+        if let Some(totp) = &password.totp {
+            entry.set_protected(
+                fields::OTP,
+                format!("otpauth://totp/keybear?secret={}", totp),
+            );
+        }
+        if let Some(modified) = password
+            .modified
+            .and_then(|modified| DateTime::from_timestamp(modified as i64, 0))
+        {
+            entry.times.last_modification = Some(modified.naive_utc());
+        }
+    }
+
+    for item in items.iter() {
+        // Get the fields by their serialized names, so the import can deserialize them again
+        let mut item_fields = match serde_json::to_value(&item.kind)? {
+            Value::Object(item_fields) => item_fields,
+            _ => bail!("Item \"{}\" is not serialized as an object", item.name),
+        };
+        let item_type = match item_fields.remove("type") {
+            Some(Value::String(item_type)) => item_type,
+            _ => bail!("Item \"{}\" is serialized without a type", item.name),
+        };
+        let secret_fields = item.kind.secret_fields();
+
+        let group_id = group(&mut database, group_name(item.item_type()))?;
+        let mut group = database
+            .group_mut(group_id)
+            .ok_or_else(|| anyhow!("Group disappeared while exporting"))?;
+        let mut entry = group.add_entry();
+
+        entry.set_unprotected(fields::TITLE, &item.name);
+        entry.set_unprotected(ITEM_TYPE_FIELD, item_type);
+        for (name, value) in item_fields {
+            let value = match value {
+                Value::Null => continue,
+                Value::String(value) => value,
+                value => value.to_string(),
+            };
+
+            if secret_fields.contains_key(&name) {
+                entry.set_protected(name, value);
+            } else {
+                entry.set_unprotected(name, value);
+            }
+        }
+    }
+
+    let mut data = Vec::new();
+    database
+        .save(&mut data, DatabaseKey::new().with_password(master_password))
+        .context("Could not write the KeePass database")?;
+
+    Ok(data)
+}
+
+/// Name of the group the items of a type are exported to.
+fn group_name(item_type: ItemType) -> &'static str {
+    match item_type {
+        ItemType::Login => "Logins",
+        ItemType::Card => "Cards",
+        ItemType::Identity => "Identities",
+        ItemType::WifiNetwork => "Wi-Fi networks",
+        ItemType::ApiKey => "API keys",
+        ItemType::Database => "Databases",
+        ItemType::Note => "Notes",
+    }
+}
+
+/// Get the group for a folder, creating the missing groups along the way.
+fn group(database: &mut Database, folder: &str) -> Result<GroupId> {
+    let mut group_id = database.root().id();
+
+    for name in folder.split('/').filter(|name| !name.is_empty()) {
+        let mut group = database
+            .group_mut(group_id)
+            .ok_or_else(|| anyhow!("Group disappeared while exporting"))?;
+
+        let existing = group.as_ref().group_by_name(name).map(|child| child.id());
+        group_id = match existing {
+            Some(id) => id,
+            None => {
+                let mut child = group.add_group();
+                child.name = name.to_string();
+
+                child.id()
+            }
+        };
+    }
+
+    Ok(group_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        export::kdbx,
+        import::kdbx as import_kdbx,
+        item::{
+            kind::{Card, DatabaseCredential, ItemKind, ItemType, WifiNetwork, WifiSecurity},
+            Item, Items,
+        },
+        password::{Password, Passwords},
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let mut passwords = Passwords::default();
+        passwords.register(Password {
+            email: Some("user@example.com".to_string()),
+            website: Some("https://github.com".to_string()),
+            totp: Some("JBSWY3DPEHPK3PXP".to_string()),
+            notes: Some("Recovery codes are in the safe".to_string()),
+            folder: Some("Work/Code".to_string()),
+            modified: Some(1_600_000_000),
+            ..Password::new("GitHub", "hunter2")
+        });
+        let mut router = Password {
+            email: Some("admin@example.com".to_string()),
+            ..Password::new("Router", "admin")
+        };
+        router.set_username(Some("admin".to_string()));
+        passwords.register(router);
+
+        // Use a light key derivation to keep the test fast
+        let mut items = Items::default();
+        let card = ItemKind::Card(Card {
+            cardholder: "J. Doe".to_string(),
+            number: "4111111111111111".to_string(),
+            expiry_month: 4,
+            expiry_year: 2030,
+            security_code: Some("123".to_string()),
+        });
+        items.register(Item::new("Credit card", card.clone())?);
+        let wifi = ItemKind::WifiNetwork(WifiNetwork {
+            ssid: "home".to_string(),
+            security: WifiSecurity::Open,
+            password: None,
+        });
+        items.register(Item::new("Home", wifi.clone())?);
+        let database = ItemKind::Database(DatabaseCredential {
+            engine: Some("postgresql".to_string()),
+            host: "db.example.com".to_string(),
+            port: Some(5432),
+            database: None,
+            username: "app".to_string(),
+            password: "secret".to_string(),
+        });
+        items.register(Item::new("Production", database.clone())?);
+
+        let data = kdbx::export_with_kdf(&passwords, &items, "master", 1, 1024 * 1024, 1)?;
+
+        // The wrong master password can't open it
+        assert!(import_kdbx::parse(&data, "wrong").is_err());
+
+        let mut parsed = import_kdbx::parse(&data, "master")?;
+        assert!(parsed.skipped.is_empty());
+        parsed.passwords.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let github = &parsed.passwords[0];
+        assert_eq!(github.name, "GitHub");
+        assert_eq!(github.password, "hunter2");
+        assert_eq!(github.email.as_deref(), Some("user@example.com"));
+        assert_eq!(github.website.as_deref(), Some("https://github.com"));
+        assert_eq!(github.totp.as_deref(), Some("JBSWY3DPEHPK3PXP"));
+        assert_eq!(
+            github.notes.as_deref(),
+            Some("Recovery codes are in the safe")
+        );
+        assert_eq!(github.folder.as_deref(), Some("Work/Code"));
+        assert_eq!(github.modified, Some(1_600_000_000));
+
+        let router = &parsed.passwords[1];
+        assert_eq!(router.name, "Router");
+        assert_eq!(router.username.as_deref(), Some("admin"));
+        assert_eq!(router.email.as_deref(), Some("admin@example.com"));
+        assert_eq!(router.folder, None);
+
+        // The items are exported in a group per type and imported as the same items
+        assert_eq!(parsed.passwords.len(), 2);
+        parsed.items.sort_by(|a, b| a.name.cmp(&b.name));
+        let kinds = parsed
+            .items
+            .iter()
+            .map(|item| (item.name.as_str(), &item.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ("Credit card", &card),
+                ("Home", &wifi),
+                ("Production", &database)
+            ]
+        );
+        assert_eq!(parsed.items[0].item_type(), ItemType::Card);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+pub mod csv;
+pub mod kdbx;
+
+use crate::{app::AppState, body::EncryptedBody, item::Items, password::Passwords};
+use actix_web::{
+    error::{BlockingError, ErrorBadRequest, ErrorInternalServerError},
+    web::{self, Data},
+    Result as WebResult,
+};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// The formats the vault can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// An unencrypted CSV file in the Chrome layout.
+    Csv,
+    /// A KeePass KDBX 4 database.
+    Kdbx,
+}
+
+impl ExportFormat {
+    /// All formats, used for the command line options.
+    pub const NAMES: &'static [&'static str] = &["csv", "kdbx"];
+
+    /// Whether the export is a binary file instead of text.
+    pub fn is_binary(&self) -> bool {
+        matches!(self, ExportFormat::Kdbx)
+    }
+
+    /// Whether the export is encrypted with a master password.
+    pub fn needs_master_password(&self) -> bool {
+        matches!(self, ExportFormat::Kdbx)
+    }
+
+    /// Whether the items that aren't logins can be exported.
+    pub fn supports_items(&self) -> bool {
+        matches!(self, ExportFormat::Kdbx)
+    }
+
+    /// Export all passwords and, when supported, all items into the contents of a file.
+    pub fn export(
+        &self,
+        passwords: &Passwords,
+        items: &Items,
+        master_password: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        match self {
+            // The Chrome layout only has columns for logins
+            ExportFormat::Csv => csv::export(passwords),
+            ExportFormat::Kdbx => match master_password {
+                Some(master_password) => kdbx::export(passwords, items, master_password),
+                None => bail!("A master password is required to export a KeePass database"),
+            },
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "csv" => Ok(ExportFormat::Csv),
+            "kdbx" => Ok(ExportFormat::Kdbx),
+            _ => bail!("Unknown export format \"{}\"", format),
+        }
+    }
+}
+
+/// A request to export the vault.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportRequest {
+    /// The format of the export.
+    pub format: ExportFormat,
+    /// The master password of encrypted formats.
+    #[serde(default)]
+    pub master_password: Option<String>,
+}
+
+/// The exported vault.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportResponse {
+    /// The contents of the exported file, base64 encoded for binary formats.
+    pub data: String,
+}
+
+/// Export all passwords and items.
+pub async fn post_export(
+    request: EncryptedBody<ExportRequest>,
+    state: Data<AppState>,
+) -> WebResult<EncryptedBody<ExportResponse>> {
+    if request.format.needs_master_password() && request.master_password.is_none() {
+        return Err(ErrorBadRequest("A master password is required"));
+    }
+
+    // Read everything under a single lock so the export is consistent
+    let (passwords, items) = {
+        let storage = state.storage.lock().await;
+
+        let passwords = storage
+            .get::<_, Passwords>("passwords")
+            .await?
+            .unwrap_or_else(Passwords::default);
+        let items = storage
+            .get::<_, Items>("items")
+            .await?
+            .unwrap_or_else(Items::default);
+
+        (passwords, items)
+    };
+
+    // Deriving the key of an encrypted export takes a while, don't block the thread handling
+    // requests
+    let request = request.into_inner();
+    let format = request.format;
+    let data =
+        web::block(move || format.export(&passwords, &items, request.master_password.as_deref()))
+            .await
+            .map_err(|err| match err {
+                BlockingError::Error(err) => ErrorInternalServerError(err),
+                BlockingError::Canceled => ErrorInternalServerError("Exporting was canceled"),
+            })?;
+
+    Ok(EncryptedBody::new(ExportResponse {
+        data: if format.is_binary() {
+            base64::encode(data)
+        } else {
+            String::from_utf8(data).map_err(ErrorInternalServerError)?
+        },
+    }))
+}
@@ -0,0 +1,333 @@
+use crate::{
+    app::AppState,
+    body::EncryptedBody,
+    password::{self, Password, PasswordSummary, ToPassword},
+};
+use actix_web::{error::ErrorBadRequest, web::Data, Result as WebResult};
+use anyhow::{bail, Result};
+use chbs::{config::BasicConfig, prelude::*, probability::Probability};
+use keybear_core::types::RegisterPasswordRequest;
+use rand::{rngs::OsRng, seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+/// Lowercase letters.
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+/// Uppercase letters.
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+/// Digits.
+const DIGITS: &str = "0123456789";
+/// Symbols that can be typed on most keyboards.
+const SYMBOLS: &str = "!@#$%^&*()-_=+[]{};:,.<>/?~";
+/// Characters that are easily confused with each other.
+const AMBIGUOUS: &str = "Il1O0o|`'\";:,.";
+
+/// The shortest random password that can be generated.
+const MIN_LENGTH: usize = 4;
+/// The longest random password that can be generated.
+const MAX_LENGTH: usize = 1024;
+/// The maximum amount of words in a passphrase.
+const MAX_WORDS: usize = 64;
+
+/// How to generate a password.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GenerateKind {
+    /// A string of random characters.
+    Random(RandomOptions),
+    /// A passphrase of random dictionary words.
+    Passphrase(PassphraseOptions),
+}
+
+impl GenerateKind {
+    /// Generate a new password, returns the password with its entropy in bits.
+    pub fn generate(&self) -> Result<(String, f64)> {
+        match self {
+            GenerateKind::Random(options) => options.generate(),
+            GenerateKind::Passphrase(options) => options.generate(),
+        }
+    }
+}
+
+/// Options for generating a string of random characters.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RandomOptions {
+    /// Amount of characters.
+    pub length: usize,
+    /// Whether to use lowercase letters.
+    pub lowercase: bool,
+    /// Whether to use uppercase letters.
+    pub uppercase: bool,
+    /// Whether to use digits.
+    pub digits: bool,
+    /// Whether to use symbols.
+    pub symbols: bool,
+    /// Whether to leave out characters that are easily confused with each other.
+    pub exclude_ambiguous: bool,
+}
+
+impl Default for RandomOptions {
+    fn default() -> Self {
+        Self {
+            length: 20,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            exclude_ambiguous: false,
+        }
+    }
+}
+
+impl RandomOptions {
+    /// Generate a password where every enabled character class occurs at least once.
+    pub fn generate(&self) -> Result<(String, f64)> {
+        if !(MIN_LENGTH..=MAX_LENGTH).contains(&self.length) {
+            bail!(
+                "Length must be between {} and {} characters",
+                MIN_LENGTH,
+                MAX_LENGTH
+            );
+        }
+
+        // Get the characters of all enabled classes
+        let classes = [
+            (self.lowercase, LOWERCASE),
+            (self.uppercase, UPPERCASE),
+            (self.digits, DIGITS),
+            (self.symbols, SYMBOLS),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, class)| {
+            class
+                .chars()
+                .filter(|c| !self.exclude_ambiguous || !AMBIGUOUS.contains(*c))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+        if classes.is_empty() {
+            bail!("At least one character class must be enabled");
+        }
+
+        let all = classes.concat();
+        let mut rng = OsRng;
+
+        // Pick one character from every class so they are all represented
+        let mut password = classes
+            .iter()
+            .map(|class| class[rng.gen_range(0..class.len())])
+            .collect::<Vec<_>>();
+        // Fill the rest with characters from all classes
+        password.extend((password.len()..self.length).map(|_| all[rng.gen_range(0..all.len())]));
+        // Hide the positions of the characters picked per class
+        password.shuffle(&mut rng);
+
+        let entropy = self.length as f64 * (all.len() as f64).log2();
+
+        Ok((password.into_iter().collect(), entropy))
+    }
+}
+
+/// How words in a passphrase are capitalized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capitalization {
+    /// All letters are lowercase.
+    None,
+    /// The first letter of every word is uppercase.
+    First,
+    /// The first letter of random words is uppercase.
+    Random,
+    /// All letters are uppercase.
+    All,
+}
+
+/// Options for generating a passphrase.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PassphraseOptions {
+    /// Amount of words.
+    pub words: usize,
+    /// The string between the words.
+    pub separator: String,
+    /// How the words are capitalized.
+    pub capitalization: Capitalization,
+}
+
+impl Default for PassphraseOptions {
+    fn default() -> Self {
+        Self {
+            words: 5,
+            separator: " ".to_string(),
+            capitalization: Capitalization::None,
+        }
+    }
+}
+
+impl PassphraseOptions {
+    /// Generate a passphrase from the builtin EFF word list.
+    pub fn generate(&self) -> Result<(String, f64)> {
+        if !(1..=MAX_WORDS).contains(&self.words) {
+            bail!("Amount of words must be between 1 and {}", MAX_WORDS);
+        }
+
+        let (capitalize_first, capitalize_words) = match self.capitalization {
+            Capitalization::None => (Probability::Never, Probability::Never),
+            Capitalization::First => (Probability::Always, Probability::Never),
+            Capitalization::Random => (Probability::half(), Probability::Never),
+            Capitalization::All => (Probability::Never, Probability::Always),
+        };
+
+        let scheme = BasicConfig {
+            words: self.words,
+            separator: self.separator.clone(),
+            capitalize_first,
+            capitalize_words,
+            ..BasicConfig::default()
+        }
+        .to_scheme();
+
+        Ok((scheme.generate(), scheme.entropy().bits()))
+    }
+}
+
+/// The entry to create from a generated password.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoreGenerated {
+    /// Name of the password as configured by the user.
+    pub name: String,
+    /// The e-mail associated.
+    pub email: Option<String>,
+    /// The website associated.
+    pub website: Option<String>,
+}
+
+/// A request to generate a password.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeneratePasswordRequest {
+    /// How to generate the password.
+    #[serde(flatten)]
+    pub kind: GenerateKind,
+    /// When set the generated password is stored as a new entry.
+    pub store: Option<StoreGenerated>,
+}
+
+/// The generated password.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeneratePasswordResponse {
+    /// The generated password.
+    pub password: String,
+    /// The estimated entropy in bits.
+    pub entropy: f64,
+    /// The entry when the password is stored.
+    pub stored: Option<PasswordSummary>,
+}
+
+/// Generate a new password and optionally store it.
+pub async fn generate(
+    request: EncryptedBody<GeneratePasswordRequest>,
+    state: Data<AppState>,
+) -> WebResult<EncryptedBody<GeneratePasswordResponse>> {
+    let (password, entropy) = request.kind.generate().map_err(ErrorBadRequest)?;
+
+    let stored = match &request.store {
+        Some(store) => {
+            // Convert it to an internal password used for storage
+            let stored: Password = RegisterPasswordRequest::new(
+                &store.name,
+                &password,
+                store.email.as_ref(),
+                store.website.as_ref(),
+            )
+            .to_password();
+
+            // Register it like any other password, so it's also checked for breaches
+            Some(password::register(&state, stored).await?.to_public())
+        }
+        None => None,
+    };
+
+    Ok(EncryptedBody::new(GeneratePasswordResponse {
+        password,
+        entropy,
+        stored,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generator::{
+        Capitalization, PassphraseOptions, RandomOptions, AMBIGUOUS, DIGITS, SYMBOLS,
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn random() -> Result<()> {
+        let (password, entropy) = RandomOptions::default().generate()?;
+        assert_eq!(password.chars().count(), 20);
+        assert!(entropy > 100.0);
+
+        // Every class must be represented
+        assert!(password.chars().any(|c| c.is_ascii_lowercase()));
+        assert!(password.chars().any(|c| c.is_ascii_uppercase()));
+        assert!(password.chars().any(|c| DIGITS.contains(c)));
+        assert!(password.chars().any(|c| SYMBOLS.contains(c)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn random_options() -> Result<()> {
+        let (password, _) = RandomOptions {
+            length: 200,
+            symbols: false,
+            exclude_ambiguous: true,
+            ..RandomOptions::default()
+        }
+        .generate()?;
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert!(!password.chars().any(|c| AMBIGUOUS.contains(c)));
+
+        // Invalid options
+        assert!(RandomOptions {
+            length: 2,
+            ..RandomOptions::default()
+        }
+        .generate()
+        .is_err());
+        assert!(RandomOptions {
+            lowercase: false,
+            uppercase: false,
+            digits: false,
+            symbols: false,
+            ..RandomOptions::default()
+        }
+        .generate()
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn passphrase() -> Result<()> {
+        let (password, entropy) = PassphraseOptions {
+            words: 6,
+            separator: "+".to_string(),
+            capitalization: Capitalization::All,
+        }
+        .generate()?;
+        assert_eq!(password.split('+').count(), 6);
+        assert_eq!(password, password.to_uppercase());
+        assert!(entropy > 60.0);
+
+        assert!(PassphraseOptions {
+            words: 0,
+            ..PassphraseOptions::default()
+        }
+        .generate()
+        .is_err());
+
+        Ok(())
+    }
+}
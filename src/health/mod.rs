@@ -0,0 +1,220 @@
+use crate::{app::AppState, config::Config, net::ListenAddress, route};
+use actix_web::{web::Data, HttpResponse};
+use anyhow::{anyhow, bail, Result};
+use keybear_core::crypto::StaticSecretExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    os::unix::net::UnixStream,
+    time::Duration,
+};
+use x25519_dalek::StaticSecret;
+
+/// How long the health check waits for the server.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Whether the server can do its work.
+///
+/// Only contains booleans so nothing about the vault is revealed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Health {
+    /// The database can be read.
+    pub storage: bool,
+    /// The secret key in memory is the same as the key on disk.
+    pub key: bool,
+}
+
+impl Health {
+    /// Check the state of the server.
+    pub async fn check(state: &AppState) -> Self {
+        let storage = state.devices().await.is_ok();
+        let key = StaticSecret::from_file(state.config.key_path())
+            .map(|key| key.to_bytes() == state.secret_key.to_bytes())
+            .unwrap_or(false);
+
+        Self { storage, key }
+    }
+
+    /// Whether everything is working.
+    pub fn is_healthy(&self) -> bool {
+        self.storage && self.key
+    }
+}
+
+/// The version of the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Version {
+    /// Name of the server application.
+    pub name: String,
+    /// Version of the server application.
+    pub version: String,
+}
+
+impl Default for Version {
+    fn default() -> Self {
+        Self {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+/// The health endpoint, responds with service unavailable when something is wrong.
+pub async fn health(state: Data<AppState>) -> HttpResponse {
+    let health = Health::check(&state).await;
+    if health.is_healthy() {
+        HttpResponse::Ok().json(health)
+    } else {
+        HttpResponse::ServiceUnavailable().json(health)
+    }
+}
+
+/// The version endpoint.
+pub async fn version() -> HttpResponse {
+    HttpResponse::Ok().json(Version::default())
+}
+
+/// Ask the running server for its health through the first listen address.
+pub fn request(config: &Config) -> Result<Health> {
+    let address = config.listen_addresses().remove(0);
+
+    let mut request = String::new();
+    // Without a header the connection would be refused, the unknown source has no access to the
+    // rest of the API
+    if config.proxy_protocol() {
+        request.push_str("PROXY UNKNOWN\r\n");
+    }
+    request.push_str(&format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        route::HEALTH
+    ));
+
+    let response = match &address {
+        ListenAddress::Tcp(addr) => {
+            let mut stream = TcpStream::connect_timeout(addr, HEALTH_CHECK_TIMEOUT)?;
+            stream.set_read_timeout(Some(HEALTH_CHECK_TIMEOUT))?;
+            exchange(&mut stream, &request)
+        }
+        ListenAddress::Unix(path) => {
+            let mut stream = UnixStream::connect(path)?;
+            stream.set_read_timeout(Some(HEALTH_CHECK_TIMEOUT))?;
+            exchange(&mut stream, &request)
+        }
+    }
+    .map_err(|err| anyhow!("Connecting to the server on {} failed: {}", address, err))?;
+
+    parse_response(&response)
+}
+
+/// Send the request and read the whole response.
+fn exchange<S>(stream: &mut S, request: &str) -> std::io::Result<String>
+where
+    S: Read + Write,
+{
+    stream.write_all(request.as_bytes())?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    Ok(response)
+}
+
+/// Get the health from the HTTP response of the health endpoint.
+fn parse_response(response: &str) -> Result<Health> {
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| anyhow!("Server sent an invalid response"))?;
+    let status = head.split(' ').nth(1).unwrap_or_default();
+    if status != "200" && status != "503" {
+        bail!("Server responded with status {}", status);
+    }
+
+    serde_json::from_str(body).map_err(|err| anyhow!("Server sent an invalid health: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::Config,
+        health::{self, Health, Version},
+        route, test,
+    };
+    use actix_web::{http::StatusCode, test::TestRequest, App};
+    use anyhow::Result;
+    use keybear_core::crypto::StaticSecretExt;
+    use x25519_dalek::StaticSecret;
+
+    #[actix_rt::test]
+    async fn check() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let key_path = dir.path().join("key");
+        let state = test::app_state_with_config(Config::from_raw_str(&format!(
+            "key_path = {:?}",
+            key_path
+        ))?);
+
+        // The key isn't saved
+        let health = Health::check(&state).await;
+        assert_eq!(
+            health,
+            Health {
+                storage: true,
+                key: false
+            }
+        );
+        assert!(!health.is_healthy());
+
+        state.secret_key.save(&key_path)?;
+        assert!(Health::check(&state).await.is_healthy());
+
+        // The key was replaced without restarting the server
+        StaticSecret::new_with_os_rand().save(&key_path)?;
+        assert!(!Health::check(&state).await.key);
+
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn endpoints() -> Result<()> {
+        let mut app = actix_web::test::init_service(test::fill_app(App::new())).await;
+
+        // The endpoints don't need the Tor service
+        let response = actix_web::test::call_service(
+            &mut app,
+            TestRequest::get()
+                .uri(route::VERSION)
+                .peer_addr("192.168.1.2:1234".parse()?)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let version: Version = actix_web::test::read_body_json(response).await;
+        assert_eq!(version, Version::default());
+
+        // The key of the test state isn't saved
+        let response = actix_web::test::call_service(
+            &mut app,
+            TestRequest::get().uri(route::HEALTH).to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let health: Health = actix_web::test::read_body_json(response).await;
+        assert!(health.storage);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_response() -> Result<()> {
+        let health = health::parse_response(
+            "HTTP/1.1 200 OK\r\ncontent-length: 28\r\n\r\n{\"storage\":true,\"key\":true}",
+        )?;
+        assert!(health.is_healthy());
+
+        assert!(health::parse_response("HTTP/1.1 404 Not Found\r\n\r\n").is_err());
+        assert!(health::parse_response("invalid").is_err());
+
+        Ok(())
+    }
+}
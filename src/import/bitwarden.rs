@@ -0,0 +1,476 @@
+use crate::{
+    import::{self, ParsedImport, SkippedEntry},
+    item::{
+        kind::{self, ItemKind},
+        Item as VaultItem,
+    },
+    password::Password,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Item type of a login.
+const TYPE_LOGIN: u8 = 1;
+/// Item type of a secure note.
+const TYPE_SECURE_NOTE: u8 = 2;
+/// Item type of a payment card.
+const TYPE_CARD: u8 = 3;
+/// Item type of an identity.
+const TYPE_IDENTITY: u8 = 4;
+
+/// The root of a Bitwarden JSON export.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Export {
+    /// Whether the export is encrypted, in which case the contents can't be read.
+    #[serde(default)]
+    encrypted: bool,
+    /// The folders, absent in organization exports.
+    #[serde(default)]
+    folders: Vec<Folder>,
+    /// All items.
+    #[serde(default)]
+    items: Vec<Item>,
+}
+
+/// A folder the items can be organized in.
+#[derive(Debug, Deserialize)]
+struct Folder {
+    id: String,
+    name: String,
+}
+
+/// A single item of any type.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    #[serde(rename = "type")]
+    item_type: u8,
+    name: String,
+    notes: Option<String>,
+    folder_id: Option<String>,
+    login: Option<Login>,
+    card: Option<Card>,
+    identity: Option<Identity>,
+    /// Custom fields, can be `null`.
+    fields: Option<Vec<Field>>,
+}
+
+/// The login specific part of an item.
+#[derive(Debug, Deserialize)]
+struct Login {
+    username: Option<String>,
+    password: Option<String>,
+    totp: Option<String>,
+    /// The websites, can be `null`.
+    uris: Option<Vec<Uri>>,
+}
+
+/// The card specific part of an item, all values are strings.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Card {
+    cardholder_name: Option<String>,
+    number: Option<String>,
+    exp_month: Option<String>,
+    exp_year: Option<String>,
+    code: Option<String>,
+}
+
+impl Card {
+    /// Convert it to a card item.
+    fn to_kind(&self) -> Result<ItemKind> {
+        let expiry_month = non_empty(&self.exp_month)
+            .ok_or_else(|| anyhow!("Card has no expiry month"))?
+            .parse()
+            .context("Card has an invalid expiry month")?;
+        let expiry_year = non_empty(&self.exp_year)
+            .ok_or_else(|| anyhow!("Card has no expiry year"))?
+            .parse::<u16>()
+            .context("Card has an invalid expiry year")?;
+
+        Ok(ItemKind::Card(kind::Card {
+            cardholder: non_empty(&self.cardholder_name).unwrap_or_default(),
+            number: non_empty(&self.number).unwrap_or_default(),
+            expiry_month,
+            // Two digit years are in this century
+            expiry_year: if expiry_year < 100 {
+                expiry_year + 2000
+            } else {
+                expiry_year
+            },
+            security_code: non_empty(&self.code),
+        }))
+    }
+}
+
+/// The identity specific part of an item.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Identity {
+    first_name: Option<String>,
+    middle_name: Option<String>,
+    last_name: Option<String>,
+    address1: Option<String>,
+    address2: Option<String>,
+    address3: Option<String>,
+    city: Option<String>,
+    state: Option<String>,
+    postal_code: Option<String>,
+    country: Option<String>,
+    email: Option<String>,
+    phone: Option<String>,
+    ssn: Option<String>,
+    passport_number: Option<String>,
+    license_number: Option<String>,
+}
+
+impl Identity {
+    /// Convert it to an identity item.
+    fn to_kind(&self) -> ItemKind {
+        // The middle name is part of the first names
+        let first_name = [&self.first_name, &self.middle_name]
+            .iter()
+            .filter_map(|name| non_empty(name))
+            .collect::<Vec<_>>();
+
+        let address = [
+            &self.address1,
+            &self.address2,
+            &self.address3,
+            &self.postal_code,
+            &self.city,
+            &self.state,
+            &self.country,
+        ]
+        .iter()
+        .filter_map(|line| non_empty(line))
+        .collect::<Vec<_>>();
+
+        ItemKind::Identity(kind::Identity {
+            first_name: if first_name.is_empty() {
+                None
+            } else {
+                Some(first_name.join(" "))
+            },
+            last_name: non_empty(&self.last_name),
+            email: non_empty(&self.email),
+            phone: non_empty(&self.phone),
+            address: if address.is_empty() {
+                None
+            } else {
+                Some(address.join(", "))
+            },
+            document_number: non_empty(&self.passport_number)
+                .or_else(|| non_empty(&self.license_number))
+                .or_else(|| non_empty(&self.ssn)),
+        })
+    }
+}
+
+/// A website of a login.
+#[derive(Debug, Deserialize)]
+struct Uri {
+    uri: Option<String>,
+}
+
+/// A custom field.
+#[derive(Debug, Deserialize)]
+struct Field {
+    name: Option<String>,
+    value: Option<String>,
+}
+
+/// Parse an unencrypted Bitwarden JSON export.
+///
+/// Logins become passwords, cards, identities and secure notes become items. The first URI
+/// becomes the website, the rest of the URIs and the custom fields are appended to the notes of
+/// the passwords and the text of the secure notes.
+pub fn parse(contents: &str) -> Result<ParsedImport> {
+    let export: Export =
+        serde_json::from_str(contents).context("File is not a valid Bitwarden JSON export")?;
+    if export.encrypted {
+        bail!("Encrypted Bitwarden exports are not supported, export as unencrypted JSON");
+    }
+
+    let folders = export
+        .folders
+        .into_iter()
+        .map(|folder| (folder.id, folder.name))
+        .collect::<HashMap<_, _>>();
+
+    let mut parsed = ParsedImport::default();
+    for mut item in export.items {
+        let kind = match (item.item_type, &item.card, &item.identity) {
+            (TYPE_CARD, Some(card), _) => Some(card.to_kind()),
+            (TYPE_IDENTITY, _, Some(identity)) => Some(Ok(identity.to_kind())),
+            (TYPE_SECURE_NOTE, _, _) => Some(Ok(ItemKind::Note(kind::Note {
+                text: notes(item.notes.take(), Vec::new(), item.fields.take()).unwrap_or_default(),
+            }))),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            match kind.and_then(|kind| VaultItem::new(&item.name, kind)) {
+                Ok(vault_item) => parsed.items.push(vault_item),
+                Err(err) => parsed
+                    .skipped
+                    .push(SkippedEntry::new(item.name, err.to_string())),
+            }
+
+            continue;
+        }
+
+        let login = match (item.item_type, item.login) {
+            (TYPE_LOGIN, Some(login)) => login,
+            (item_type, _) => {
+                let reason = match item_type {
+                    TYPE_LOGIN => "Login has no login details",
+                    TYPE_CARD => "Card has no card details",
+                    TYPE_IDENTITY => "Identity has no identity details",
+                    _ => "Unknown item type",
+                };
+                parsed.skipped.push(SkippedEntry::new(item.name, reason));
+
+                continue;
+            }
+        };
+
+        let password = match login.password.filter(|password| !password.is_empty()) {
+            Some(password) => password,
+            None => {
+                parsed
+                    .skipped
+                    .push(SkippedEntry::new(item.name, "Login has no password"));
+
+                continue;
+            }
+        };
+
+        let mut uris = login
+            .uris
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|uri| uri.uri)
+            .filter(|uri| !uri.is_empty());
+        let website = uris.next();
+
+        let mut password = Password {
+            website,
+            totp: login.totp.as_deref().and_then(import::totp_secret),
+            notes: notes(item.notes, uris.collect(), item.fields),
+            folder: item
+                .folder_id
+                .and_then(|folder_id| folders.get(&folder_id).cloned()),
+            ..Password::new(item.name, password)
+        };
+        password.set_username(login.username);
+
+        parsed.passwords.push(password);
+    }
+
+    Ok(parsed)
+}
+
+/// Keep everything that doesn't have a field of its own in the notes, `None` when there's nothing.
+fn notes(notes: Option<String>, uris: Vec<String>, fields: Option<Vec<Field>>) -> Option<String> {
+    let mut lines = notes.into_iter().collect::<Vec<_>>();
+    lines.extend(uris.into_iter().map(|uri| format!("URI: {}", uri)));
+    lines.extend(fields.unwrap_or_default().into_iter().map(|field| {
+        format!(
+            "{}: {}",
+            field.name.unwrap_or_default(),
+            field.value.unwrap_or_default()
+        )
+    }));
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+/// The trimmed value when it's not empty.
+fn non_empty(value: &Option<String>) -> Option<String> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        import::bitwarden,
+        item::kind::{Card, Identity, ItemKind, Note},
+    };
+    use anyhow::Result;
+
+    const EXPORT: &str = r#"{
+        "encrypted": false,
+        "folders": [{ "id": "f1", "name": "Work" }],
+        "items": [
+            {
+                "id": "i1",
+                "organizationId": null,
+                "folderId": "f1",
+                "type": 1,
+                "name": "GitHub",
+                "notes": "Recovery codes are in the safe",
+                "favorite": false,
+                "fields": [{ "name": "PIN", "value": "1234", "type": 1 }],
+                "login": {
+                    "uris": [
+                        { "match": null, "uri": "https://github.com/login" },
+                        { "match": null, "uri": "https://gist.github.com" }
+                    ],
+                    "username": "user@example.com",
+                    "password": "hunter2",
+                    "totp": "otpauth://totp/GitHub:user?secret=JBSWY3DPEHPK3PXP&issuer=GitHub"
+                },
+                "collectionIds": null
+            },
+            {
+                "id": "i2",
+                "folderId": null,
+                "type": 1,
+                "name": "Router",
+                "notes": null,
+                "login": { "uris": null, "username": null, "password": "admin", "totp": null }
+            },
+            {
+                "id": "i3",
+                "type": 1,
+                "name": "Empty",
+                "login": { "uris": [], "username": "me", "password": null, "totp": null }
+            },
+            {
+                "id": "i4",
+                "type": 2,
+                "name": "Note",
+                "notes": "Secret",
+                "fields": [{ "name": "Safe", "value": "Basement", "type": 0 }],
+                "secureNote": { "type": 0 }
+            },
+            { "id": "i8", "type": 2, "name": "Blank", "notes": null, "secureNote": { "type": 0 } },
+            {
+                "id": "i5",
+                "type": 3,
+                "name": "Visa",
+                "card": {
+                    "cardholderName": "Jane Doe",
+                    "brand": "Visa",
+                    "number": "4111 1111 1111 1111",
+                    "expMonth": "7",
+                    "expYear": "2031",
+                    "code": "123"
+                }
+            },
+            {
+                "id": "i6",
+                "type": 3,
+                "name": "Expired",
+                "card": { "cardholderName": "Jane Doe", "number": "4111111111111111", "expMonth": null, "expYear": null, "code": null }
+            },
+            {
+                "id": "i7",
+                "type": 4,
+                "name": "Passport",
+                "identity": {
+                    "title": "Ms",
+                    "firstName": "Jane",
+                    "middleName": "Mary",
+                    "lastName": "Doe",
+                    "address1": "Main Street 1",
+                    "address2": "",
+                    "address3": null,
+                    "city": "Springfield",
+                    "state": null,
+                    "postalCode": "12345",
+                    "country": "US",
+                    "company": null,
+                    "email": "jane@example.com",
+                    "phone": "555-0100",
+                    "ssn": null,
+                    "username": null,
+                    "passportNumber": "X1234567",
+                    "licenseNumber": null
+                }
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn parse() -> Result<()> {
+        let parsed = bitwarden::parse(EXPORT)?;
+        assert_eq!(parsed.passwords.len(), 2);
+
+        let github = &parsed.passwords[0];
+        assert_eq!(github.name, "GitHub");
+        assert_eq!(github.password, "hunter2");
+        assert_eq!(github.username.as_deref(), Some("user@example.com"));
+        assert_eq!(github.email.as_deref(), Some("user@example.com"));
+        assert_eq!(github.website.as_deref(), Some("https://github.com/login"));
+        assert_eq!(github.totp.as_deref(), Some("JBSWY3DPEHPK3PXP"));
+        assert_eq!(github.folder.as_deref(), Some("Work"));
+        assert_eq!(
+            github.notes.as_deref(),
+            Some("Recovery codes are in the safe\nURI: https://gist.github.com\nPIN: 1234")
+        );
+
+        let router = &parsed.passwords[1];
+        assert_eq!(router.email, None);
+        assert_eq!(router.website, None);
+        assert_eq!(router.notes, None);
+        assert_eq!(router.folder, None);
+
+        assert_eq!(parsed.items.len(), 3);
+        assert_eq!(parsed.items[0].name, "Note");
+        assert_eq!(
+            parsed.items[0].kind,
+            ItemKind::Note(Note {
+                text: "Secret\nSafe: Basement".to_string(),
+            })
+        );
+        assert_eq!(parsed.items[1].name, "Visa");
+        assert_eq!(
+            parsed.items[1].kind,
+            ItemKind::Card(Card {
+                cardholder: "Jane Doe".to_string(),
+                number: "4111 1111 1111 1111".to_string(),
+                expiry_month: 7,
+                expiry_year: 2031,
+                security_code: Some("123".to_string()),
+            })
+        );
+        assert_eq!(parsed.items[2].name, "Passport");
+        assert_eq!(
+            parsed.items[2].kind,
+            ItemKind::Identity(Identity {
+                first_name: Some("Jane Mary".to_string()),
+                last_name: Some("Doe".to_string()),
+                email: Some("jane@example.com".to_string()),
+                phone: Some("555-0100".to_string()),
+                address: Some("Main Street 1, 12345, Springfield, US".to_string()),
+                document_number: Some("X1234567".to_string()),
+            })
+        );
+
+        assert_eq!(parsed.skipped.len(), 3);
+        assert_eq!(parsed.skipped[0].name, "Empty");
+        assert_eq!(parsed.skipped[1].name, "Blank");
+        assert_eq!(parsed.skipped[1].reason, "Field \"text\" can't be empty");
+        assert_eq!(parsed.skipped[2].name, "Expired");
+        assert_eq!(parsed.skipped[2].reason, "Card has no expiry month");
+
+        Ok(())
+    }
+
+    #[test]
+    fn encrypted() {
+        assert!(bitwarden::parse(r#"{ "encrypted": true, "passwordProtected": true }"#).is_err());
+        assert!(bitwarden::parse("not json").is_err());
+    }
+}
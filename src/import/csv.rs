@@ -0,0 +1,241 @@
+use crate::{
+    import::{self, ParsedImport, SkippedEntry},
+    password::Password,
+    report,
+};
+use anyhow::{bail, Context, Result};
+use csv::{ReaderBuilder, StringRecord, Trim};
+
+/// The URL LastPass uses for secure notes.
+const LASTPASS_SECURE_NOTE_URL: &str = "http://sn";
+
+/// Header names of the name column in the known layouts.
+const NAME_COLUMNS: &[&str] = &["name", "title"];
+/// Header names of the website column in the known layouts.
+const URL_COLUMNS: &[&str] = &["url", "website", "login_uri", "origin"];
+/// Header names of the username column in the known layouts.
+const USERNAME_COLUMNS: &[&str] = &["username", "login_username", "login", "user"];
+/// Header names of the e-mail column in the known layouts.
+const EMAIL_COLUMNS: &[&str] = &["email", "e-mail"];
+/// Header names of the password column in the known layouts.
+const PASSWORD_COLUMNS: &[&str] = &["password", "login_password"];
+/// Header names of the notes column in the known layouts.
+const NOTES_COLUMNS: &[&str] = &["note", "notes", "extra", "comments"];
+/// Header names of the TOTP column in the known layouts.
+const TOTP_COLUMNS: &[&str] = &["totp", "login_totp", "otp"];
+/// Header names of the folder column in the known layouts.
+const FOLDER_COLUMNS: &[&str] = &["folder", "grouping", "group"];
+/// Header names of the column with the time of the last password change in milliseconds.
+const MODIFIED_COLUMNS: &[&str] = &["timepasswordchanged"];
+
+/// The position of the known columns in a CSV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Columns {
+    name: Option<usize>,
+    url: Option<usize>,
+    username: Option<usize>,
+    email: Option<usize>,
+    password: usize,
+    notes: Option<usize>,
+    totp: Option<usize>,
+    folder: Option<usize>,
+    modified: Option<usize>,
+}
+
+impl Columns {
+    /// Detect the layout from the header.
+    fn detect(header: &StringRecord) -> Result<Self> {
+        let find = |names: &[&str]| {
+            header
+                .iter()
+                .position(|column| names.contains(&column.trim().to_lowercase().as_str()))
+        };
+
+        let password = match find(PASSWORD_COLUMNS) {
+            Some(password) => password,
+            None => bail!("CSV file has no password column"),
+        };
+        let columns = Self {
+            name: find(NAME_COLUMNS),
+            url: find(URL_COLUMNS),
+            username: find(USERNAME_COLUMNS),
+            email: find(EMAIL_COLUMNS),
+            password,
+            notes: find(NOTES_COLUMNS),
+            totp: find(TOTP_COLUMNS),
+            folder: find(FOLDER_COLUMNS),
+            modified: find(MODIFIED_COLUMNS),
+        };
+        if columns.name.is_none() && columns.url.is_none() {
+            bail!("CSV file has neither a name nor a URL column");
+        }
+
+        Ok(columns)
+    }
+}
+
+/// Parse a CSV password export.
+///
+/// The layout is detected from the header, which makes it work with the exports of Chrome,
+/// Firefox, LastPass and keybear itself. Entries without a name are named after the host of
+/// their website.
+pub fn parse(contents: &str) -> Result<ParsedImport> {
+    let mut reader = ReaderBuilder::new()
+        .flexible(true)
+        // Spaces around passwords and notes are part of them, so only trim the header here
+        .trim(Trim::Headers)
+        .from_reader(contents.as_bytes());
+
+    let columns = Columns::detect(reader.headers().context("CSV file has no header")?)?;
+
+    let mut parsed = ParsedImport::default();
+    for (index, record) in reader.records().enumerate() {
+        let record = record.context("Invalid CSV record")?;
+
+        // Get a column as an owned string when it's not empty, without touching its spaces
+        let raw_field = |column: Option<usize>| {
+            column
+                .and_then(|column| record.get(column))
+                .filter(|value| !value.trim().is_empty())
+                .map(|value| value.to_string())
+        };
+        // Get a trimmed column as an owned string when it's not empty
+        let field = |column: Option<usize>| raw_field(column).map(|value| value.trim().to_string());
+
+        let url = field(columns.url);
+        let name = field(columns.name)
+            .or_else(|| url.as_deref().and_then(report::website_host))
+            .unwrap_or_else(|| format!("Entry {}", index + 1));
+
+        if url.as_deref() == Some(LASTPASS_SECURE_NOTE_URL) {
+            parsed.skipped.push(SkippedEntry::new(
+                name,
+                "LastPass secure notes are not supported",
+            ));
+
+            continue;
+        }
+
+        let password = match raw_field(Some(columns.password)) {
+            Some(password) => password,
+            None => {
+                parsed
+                    .skipped
+                    .push(SkippedEntry::new(name, "Entry has no password"));
+
+                continue;
+            }
+        };
+
+        let mut password = Password {
+            email: field(columns.email),
+            website: url,
+            totp: field(columns.totp).and_then(|totp| import::totp_secret(&totp)),
+            notes: raw_field(columns.notes),
+            // LastPass separates nested folders with a backslash
+            folder: field(columns.folder).map(|folder| folder.replace('\\', "/")),
+            // Layouts without the column, like the one of Chrome, can't contain a one time password
+            totp_supported: columns.totp.is_some(),
+            ..Password::new(name, password)
+        };
+        password.set_username(field(columns.username));
+        if let Some(modified) = field(columns.modified).and_then(|ms| ms.parse::<u64>().ok()) {
+            password.modified = Some(modified / 1000);
+        }
+
+        parsed.passwords.push(password);
+    }
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::import::csv;
+    use anyhow::Result;
+
+    #[test]
+    fn chrome() -> Result<()> {
+        let parsed = csv::parse(
+            "name,url,username,password,note\n\
+             github.com,https://github.com/login,user@example.com,hunter2,\n\
+             Router,http://192.168.1.1,admin,\"pass,word\",\"Line one\nLine two\"\n\
+             Empty,https://example.com,me,,\n",
+        )?;
+        assert_eq!(parsed.passwords.len(), 2);
+        assert_eq!(parsed.skipped.len(), 1);
+
+        let github = &parsed.passwords[0];
+        assert_eq!(github.name, "github.com");
+        assert_eq!(github.password, "hunter2");
+        assert_eq!(github.email.as_deref(), Some("user@example.com"));
+        assert_eq!(github.notes, None);
+        // The Chrome layout can't contain a one time password
+        assert!(!github.totp_supported);
+
+        let router = &parsed.passwords[1];
+        assert_eq!(router.password, "pass,word");
+        assert_eq!(router.username.as_deref(), Some("admin"));
+        assert_eq!(router.email, None);
+        assert_eq!(router.notes.as_deref(), Some("Line one\nLine two"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn firefox() -> Result<()> {
+        let parsed = csv::parse(
+            "\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\"guid\",\"timeCreated\",\"timeLastUsed\",\"timePasswordChanged\"\n\
+             \"https://www.mozilla.org\",\"fox\",\"firefox\",,\"https://www.mozilla.org\",\"{1}\",\"1600000000000\",\"1600000000000\",\"1600000000000\"\n",
+        )?;
+        assert_eq!(parsed.passwords.len(), 1);
+
+        let mozilla = &parsed.passwords[0];
+        assert_eq!(mozilla.name, "www.mozilla.org");
+        assert_eq!(mozilla.website.as_deref(), Some("https://www.mozilla.org"));
+        assert_eq!(mozilla.modified, Some(1_600_000_000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn lastpass() -> Result<()> {
+        let parsed = csv::parse(
+            "url,username,password,totp,extra,name,grouping,fav\n\
+             https://github.com,user,hunter2,JBSWY3DPEHPK3PXP,,GitHub,Work\\Code,0\n\
+             http://sn,,,,Secret note,Note,,0\n",
+        )?;
+        assert_eq!(parsed.passwords.len(), 1);
+        assert_eq!(parsed.skipped[0].name, "Note");
+
+        let github = &parsed.passwords[0];
+        assert_eq!(github.name, "GitHub");
+        assert_eq!(github.totp.as_deref(), Some("JBSWY3DPEHPK3PXP"));
+        assert_eq!(github.folder.as_deref(), Some("Work/Code"));
+        assert!(github.totp_supported);
+
+        Ok(())
+    }
+
+    #[test]
+    fn surrounding_spaces() -> Result<()> {
+        let parsed = csv::parse(
+            " name , url ,username, password ,note\n\
+             Router , http://192.168.1.1 ,admin,\"  pass word \",\" indented note \"\n",
+        )?;
+
+        let router = &parsed.passwords[0];
+        assert_eq!(router.name, "Router");
+        assert_eq!(router.website.as_deref(), Some("http://192.168.1.1"));
+        assert_eq!(router.password, "  pass word ");
+        assert_eq!(router.notes.as_deref(), Some(" indented note "));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_layout() {
+        assert!(csv::parse("a,b,c\n1,2,3\n").is_err());
+        assert!(csv::parse("name,secret\ntest,test\n").is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::{
+    export::kdbx::{EMAIL_FIELD, ITEM_TYPE_FIELD},
+    import::{self, ParsedImport, SkippedEntry},
+    item::{kind::ItemKind, Item},
+    password::Password,
+};
+use anyhow::{Context, Result};
+use keepass::{
+    db::{fields, Entry},
+    Database, DatabaseKey,
+};
+use serde_json::{Map, Value};
+
+/// Item fields that are numbers instead of strings.
+const NUMERIC_FIELDS: &[&str] = &["expiry_month", "expiry_year", "port"];
+
+/// Parse a KeePass KDBX 4 database.
+///
+/// The groups an entry is in are joined with a `/` into its folder, the root group is left
+/// out. Entries in the recycle bin are skipped. The e-mail is read from the custom field written
+/// by the export, otherwise the username is used when it's an e-mail address. Entries with the
+/// item type field written by the export are imported as items.
+pub fn parse(data: &[u8], master_password: &str) -> Result<ParsedImport> {
+    let database = Database::parse(data, DatabaseKey::new().with_password(master_password))
+        .context("Could not open the KeePass database, is the master password correct?")?;
+
+    let root = database.root().id();
+    let recycle_bin = database.recycle_bin().map(|group| group.id());
+
+    let mut parsed = ParsedImport::default();
+    for entry in database.iter_all_entries() {
+        let name = entry.get_title().unwrap_or_default().to_string();
+
+        // Collect the names of the groups from the entry up to the root
+        let mut groups = Vec::new();
+        let mut group = Some(entry.parent());
+        let mut in_recycle_bin = false;
+        while let Some(current) = group {
+            if Some(current.id()) == recycle_bin {
+                in_recycle_bin = true;
+            }
+            if current.id() != root {
+                groups.push(current.name.clone());
+            }
+
+            group = current
+                .parent()
+                .and_then(|parent| database.group(parent.id()));
+        }
+        if in_recycle_bin {
+            parsed
+                .skipped
+                .push(SkippedEntry::new(name, "Entry is in the recycle bin"));
+
+            continue;
+        }
+
+        if let Some(item_type) = entry.get(ITEM_TYPE_FIELD) {
+            match parse_item(&entry, item_type).and_then(|kind| Item::new(&name, kind)) {
+                Ok(item) => parsed.items.push(item),
+                Err(err) => parsed.skipped.push(SkippedEntry::new(
+                    name,
+                    format!("Invalid {} item: {}", item_type, err),
+                )),
+            }
+
+            continue;
+        }
+
+        let password = match entry.get_password().filter(|password| !password.is_empty()) {
+            Some(password) => password,
+            None => {
+                parsed
+                    .skipped
+                    .push(SkippedEntry::new(name, "Entry has no password"));
+
+                continue;
+            }
+        };
+
+        groups.reverse();
+
+        let mut password = Password {
+            email: non_empty(entry.get(EMAIL_FIELD)),
+            website: non_empty(entry.get_url()),
+            totp: entry.get_raw_otp_value().and_then(import::totp_secret),
+            notes: non_empty(entry.get(fields::NOTES)),
+            folder: if groups.is_empty() {
+                None
+            } else {
+                Some(groups.join("/"))
+            },
+            ..Password::new(name, password)
+        };
+        password.set_username(non_empty(entry.get_username()));
+        if let Some(modified) = entry.times.last_modification {
+            password.modified = Some(modified.and_utc().timestamp().max(0) as u64);
+        }
+
+        parsed.passwords.push(password);
+    }
+
+    Ok(parsed)
+}
+
+/// Convert the custom fields of an exported item back to the item.
+fn parse_item(entry: &Entry, item_type: &str) -> Result<ItemKind> {
+    let mut item_fields = Map::new();
+    item_fields.insert("type".to_string(), Value::String(item_type.to_string()));
+
+    for (name, value) in &entry.fields {
+        let value = value.as_str();
+        // Empty fields are left out so they become `None`
+        if name == fields::TITLE || name == ITEM_TYPE_FIELD || value.is_empty() {
+            continue;
+        }
+
+        let value = match value.parse::<u64>() {
+            Ok(number) if NUMERIC_FIELDS.contains(&name.as_str()) => Value::from(number),
+            _ => Value::String(value.to_string()),
+        };
+        item_fields.insert(name.clone(), value);
+    }
+
+    Ok(serde_json::from_value(Value::Object(item_fields))?)
+}
+
+/// Convert an optional field to an owned string when it's not empty.
+fn non_empty(field: Option<&str>) -> Option<String> {
+    field
+        .filter(|field| !field.is_empty())
+        .map(|field| field.to_string())
+}
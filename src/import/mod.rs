@@ -0,0 +1,308 @@
+pub mod bitwarden;
+pub mod csv;
+pub mod kdbx;
+pub mod pass;
+
+use crate::{
+    app::AppState,
+    body::EncryptedBody,
+    item::{Item, Items, PublicItem},
+    password::{Password, PasswordSummary, Passwords},
+};
+use actix_web::{
+    error::{ErrorBadRequest, ErrorInternalServerError},
+    web::Data,
+    Result as WebResult,
+};
+use anyhow::{anyhow, bail, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    path::Path,
+    str::{self, FromStr},
+};
+
+/// The formats passwords can be imported from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    /// An unencrypted Bitwarden JSON export.
+    Bitwarden,
+    /// A CSV export of Chrome, Firefox or LastPass.
+    Csv,
+    /// A KeePass KDBX 4 database.
+    Kdbx,
+    /// A directory tree of plaintext `pass` entries.
+    Pass,
+}
+
+impl ImportFormat {
+    /// All formats, used for the command line options.
+    pub const NAMES: &'static [&'static str] = &["bitwarden", "csv", "kdbx", "pass"];
+
+    /// Whether the export is a binary file instead of text.
+    pub fn is_binary(&self) -> bool {
+        matches!(self, ImportFormat::Kdbx)
+    }
+
+    /// Whether the export is encrypted with a master password that's needed to read it.
+    pub fn needs_master_password(&self) -> bool {
+        matches!(self, ImportFormat::Kdbx)
+    }
+
+    /// Parse the exported contents into passwords.
+    pub fn parse(&self, data: &[u8], master_password: Option<&str>) -> Result<ParsedImport> {
+        match self {
+            ImportFormat::Bitwarden => bitwarden::parse(str::from_utf8(data)?),
+            ImportFormat::Csv => csv::parse(str::from_utf8(data)?),
+            ImportFormat::Kdbx => match master_password {
+                Some(master_password) => kdbx::parse(data, master_password),
+                None => bail!("A master password is required to import a KeePass database"),
+            },
+            ImportFormat::Pass => bail!("A pass tree can only be imported from a directory"),
+        }
+    }
+
+    /// Parse the export at a path, which is a directory for `pass` and a file otherwise.
+    pub fn parse_path<P>(&self, path: P, master_password: Option<&str>) -> Result<ParsedImport>
+    where
+        P: AsRef<Path>,
+    {
+        match self {
+            ImportFormat::Pass => pass::parse_dir(path),
+            _ => self.parse(&fs::read(path)?, master_password),
+        }
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "bitwarden" => Ok(ImportFormat::Bitwarden),
+            "csv" => Ok(ImportFormat::Csv),
+            "kdbx" => Ok(ImportFormat::Kdbx),
+            "pass" => Ok(ImportFormat::Pass),
+            _ => bail!("Unknown import format \"{}\"", format),
+        }
+    }
+}
+
+/// The result of parsing an export, before it's stored.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ParsedImport {
+    /// The passwords found.
+    pub passwords: Vec<Password>,
+    /// The items found that aren't logins.
+    pub items: Vec<Item>,
+    /// The entries that couldn't be converted.
+    pub skipped: Vec<SkippedEntry>,
+}
+
+/// An entry from the export that isn't imported.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedEntry {
+    /// Name of the entry in the export.
+    pub name: String,
+    /// Why the entry isn't imported.
+    pub reason: String,
+}
+
+impl SkippedEntry {
+    /// Construct a new skipped entry.
+    pub fn new<S1, S2>(name: S1, reason: S2) -> Self
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        Self {
+            name: name.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+/// A request to import an export.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportRequest {
+    /// The format of the export.
+    pub format: ImportFormat,
+    /// The contents of the exported file, base64 encoded for binary formats.
+    pub data: String,
+    /// The master password of encrypted formats.
+    #[serde(default)]
+    pub master_password: Option<String>,
+    /// When set nothing is stored, only the report is returned.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl ImportRequest {
+    /// Parse the uploaded contents into passwords.
+    pub fn parse(&self) -> Result<ParsedImport> {
+        let data = if self.format.is_binary() {
+            base64::decode(&self.data)?
+        } else {
+            self.data.as_bytes().to_vec()
+        };
+
+        self.format.parse(&data, self.master_password.as_deref())
+    }
+}
+
+/// What is or would be imported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// Whether nothing is stored.
+    pub dry_run: bool,
+    /// The passwords created.
+    pub created: Vec<PasswordSummary>,
+    /// The items created that aren't logins.
+    #[serde(default)]
+    pub created_items: Vec<PublicItem>,
+    /// The entries that aren't imported.
+    pub skipped: Vec<SkippedEntry>,
+}
+
+impl Display for ImportReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let verb = if self.dry_run {
+            "Would create"
+        } else {
+            "Created"
+        };
+
+        writeln!(f, "{} {} passwords:", verb, self.created.len())?;
+        for password in &self.created {
+            writeln!(f, "  {}", password.name())?;
+        }
+
+        if !self.created_items.is_empty() {
+            writeln!(f, "{} {} items:", verb, self.created_items.len())?;
+            for item in &self.created_items {
+                writeln!(f, "  {}", item.name)?;
+            }
+        }
+
+        writeln!(f, "Skipped {} entries:", self.skipped.len())?;
+        for entry in &self.skipped {
+            writeln!(f, "  {}: {}", entry.name, entry.reason)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Store the parsed passwords and items in the vault.
+///
+/// Passwords that are already stored with the same name, website, login and password are
+/// skipped, just like items with the same name and contents.
+pub async fn import(state: &AppState, parsed: ParsedImport, dry_run: bool) -> Result<ImportReport> {
+    // Get a mutex lock on the storage for the whole import, so it's applied completely and no
+    // concurrent change is lost
+    let storage = state.storage.lock().await;
+
+    let mut passwords = storage
+        .get::<_, Passwords>("passwords")
+        .await
+        .map_err(|err| anyhow!("Could not get passwords from storage: {}", err))?
+        .unwrap_or_else(Passwords::default);
+    let mut items = storage
+        .get::<_, Items>("items")
+        .await
+        .map_err(|err| anyhow!("Could not get items from storage: {}", err))?
+        .unwrap_or_else(Items::default);
+
+    let mut report = ImportReport {
+        dry_run,
+        created: Vec::new(),
+        created_items: Vec::new(),
+        skipped: parsed.skipped,
+    };
+    for password in parsed.passwords {
+        let exists = passwords.iter().any(|existing| {
+            existing.name == password.name
+                && existing.website == password.website
+                && existing.login() == password.login()
+                && existing.password == password.password
+        });
+        if exists {
+            report
+                .skipped
+                .push(SkippedEntry::new(password.name, "Already exists"));
+            continue;
+        }
+
+        report.created.push(password.to_public());
+        passwords.register(password);
+    }
+    for item in parsed.items {
+        let exists = items
+            .iter()
+            .any(|existing| existing.name == item.name && existing.kind == item.kind);
+        if exists {
+            report
+                .skipped
+                .push(SkippedEntry::new(item.name, "Already exists"));
+            continue;
+        }
+
+        report.created_items.push(item.to_public());
+        items.register(item);
+    }
+
+    if !dry_run {
+        storage
+            .set("passwords", &passwords)
+            .await
+            .map_err(|err| anyhow!("Error setting passwords on database: {}", err))?;
+        storage
+            .set("items", &items)
+            .await
+            .map_err(|err| anyhow!("Error setting items on database: {}", err))?;
+
+        info!(
+            "Imported {} passwords and {} items",
+            report.created.len(),
+            report.created_items.len()
+        );
+    }
+
+    Ok(report)
+}
+
+/// Get the secret from a TOTP value, which is either the secret or an `otpauth://` URI.
+pub(crate) fn totp_secret(totp: &str) -> Option<String> {
+    let totp = totp.trim();
+
+    if let Some(uri) = totp.strip_prefix("otpauth://") {
+        uri.split_once('?')?
+            .1
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| key.eq_ignore_ascii_case("secret"))
+            .map(|(_, secret)| secret.to_string())
+    } else if totp.is_empty() {
+        None
+    } else {
+        Some(totp.to_string())
+    }
+}
+
+/// Import an uploaded export.
+pub async fn post_import(
+    request: EncryptedBody<ImportRequest>,
+    state: Data<AppState>,
+) -> WebResult<EncryptedBody<ImportReport>> {
+    // Parsing errors are caused by the uploaded data
+    let parsed = request.parse().map_err(ErrorBadRequest)?;
+
+    Ok(EncryptedBody::new(
+        import(&state, parsed, request.dry_run)
+            .await
+            .map_err(ErrorInternalServerError)?,
+    ))
+}
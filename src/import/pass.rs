@@ -0,0 +1,249 @@
+use crate::{
+    import::{self, ParsedImport, SkippedEntry},
+    password::Password,
+};
+use anyhow::{Context, Result};
+use std::{fs, path::Path};
+
+/// Extensions that are removed from the file names to get the entry names.
+const EXTENSIONS: &[&str] = &["gpg", "txt"];
+
+/// Keys of the lines containing the username.
+const USERNAME_KEYS: &[&str] = &["user", "username", "login"];
+/// Keys of the lines containing the e-mail.
+const EMAIL_KEYS: &[&str] = &["email", "e-mail", "mail"];
+/// Keys of the lines containing the website.
+const WEBSITE_KEYS: &[&str] = &["url", "website", "site"];
+/// Keys of the lines containing the TOTP secret.
+const TOTP_KEYS: &[&str] = &["totp", "otp"];
+
+/// Parse a directory tree of plaintext `pass` entries.
+///
+/// Every file is an entry named after the file, the directories it's in are joined with a `/`
+/// into its folder. Hidden files and directories such as `.git` and `.gpg-id` are ignored, and
+/// symbolic links are skipped so they can't read files outside the tree or loop forever.
+pub fn parse_dir<P>(dir: P) -> Result<ParsedImport>
+where
+    P: AsRef<Path>,
+{
+    let mut parsed = ParsedImport::default();
+    walk(dir.as_ref(), &mut Vec::new(), &mut parsed)?;
+
+    Ok(parsed)
+}
+
+/// Parse all entries in a directory recursively.
+fn walk(dir: &Path, folders: &mut Vec<String>, parsed: &mut ParsedImport) -> Result<()> {
+    let mut paths = fs::read_dir(dir)
+        .with_context(|| format!("Reading directory {:?} failed", dir))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+
+    for path in paths {
+        let file_name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(file_name) if !file_name.starts_with('.') => file_name.to_string(),
+            _ => continue,
+        };
+
+        // Don't follow symbolic links, they can point anywhere
+        let metadata = fs::symlink_metadata(&path)
+            .with_context(|| format!("Reading metadata of {:?} failed", path))?;
+        if metadata.file_type().is_symlink() {
+            parsed.skipped.push(SkippedEntry::new(
+                file_name,
+                "Symbolic links are not followed",
+            ));
+
+            continue;
+        }
+
+        if metadata.is_dir() {
+            folders.push(file_name);
+            walk(&path, folders, parsed)?;
+            folders.pop();
+
+            continue;
+        }
+
+        let name = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if EXTENSIONS.contains(&extension) => path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or(&file_name)
+                .to_string(),
+            _ => file_name,
+        };
+
+        let data = fs::read(&path).with_context(|| format!("Reading {:?} failed", path))?;
+        let contents = match String::from_utf8(data) {
+            Ok(contents) => contents,
+            Err(_) => {
+                parsed.skipped.push(SkippedEntry::new(
+                    name,
+                    "File is not plaintext, decrypt the tree first",
+                ));
+
+                continue;
+            }
+        };
+
+        let folder = if folders.is_empty() {
+            None
+        } else {
+            Some(folders.join("/"))
+        };
+        match parse_entry(&name, folder, &contents) {
+            Some(password) => parsed.passwords.push(password),
+            None => parsed
+                .skipped
+                .push(SkippedEntry::new(name, "Entry has no password")),
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse the contents of a single entry as printed by `pass show`.
+///
+/// The first line is the password. Lines with a known key are mapped to their fields, the rest
+/// is kept in the notes. Returns `None` when there's no password.
+pub fn parse_entry(name: &str, folder: Option<String>, contents: &str) -> Option<Password> {
+    let mut lines = contents.lines();
+    let password = lines.next().filter(|password| !password.is_empty())?;
+
+    let mut username = None;
+    let mut entry = Password {
+        folder,
+        ..Password::new(name, password)
+    };
+
+    let mut notes = Vec::new();
+    for line in lines {
+        // Lines added by pass-otp contain the full URI
+        if line.trim().starts_with("otpauth://") {
+            entry.totp = import::totp_secret(line);
+
+            continue;
+        }
+
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key.trim().to_lowercase(), value.trim().to_string()),
+            None => {
+                notes.push(line);
+
+                continue;
+            }
+        };
+
+        if USERNAME_KEYS.contains(&key.as_str()) {
+            username = Some(value);
+        } else if EMAIL_KEYS.contains(&key.as_str()) {
+            entry.email = Some(value);
+        } else if WEBSITE_KEYS.contains(&key.as_str()) {
+            entry.website = Some(value);
+        } else if TOTP_KEYS.contains(&key.as_str()) {
+            entry.totp = import::totp_secret(&value);
+        } else {
+            notes.push(line);
+        }
+    }
+
+    // Remove the empty lines surrounding the notes
+    let notes = notes.join("\n");
+    let notes = notes.trim();
+    if !notes.is_empty() {
+        entry.notes = Some(notes.to_string());
+    }
+    entry.set_username(username);
+
+    Some(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::import::pass;
+    use anyhow::Result;
+    use std::{fs, os::unix};
+
+    #[test]
+    fn parse_entry() {
+        let entry = pass::parse_entry(
+            "github.com",
+            Some("Work".to_string()),
+            "hunter2\n\
+             login: user\n\
+             Email: user@example.com\n\
+             URL: https://github.com/login\n\
+             otpauth://totp/GitHub:user?secret=JBSWY3DPEHPK3PXP\n\
+             Recovery codes are in the safe\n\
+             PIN: 1234\n",
+        )
+        .unwrap();
+        assert_eq!(entry.name, "github.com");
+        assert_eq!(entry.password, "hunter2");
+        assert_eq!(entry.username.as_deref(), Some("user"));
+        assert_eq!(entry.email.as_deref(), Some("user@example.com"));
+        assert_eq!(entry.website.as_deref(), Some("https://github.com/login"));
+        assert_eq!(entry.totp.as_deref(), Some("JBSWY3DPEHPK3PXP"));
+        assert_eq!(
+            entry.notes.as_deref(),
+            Some("Recovery codes are in the safe\nPIN: 1234")
+        );
+        assert_eq!(entry.folder.as_deref(), Some("Work"));
+
+        // A single line only contains the password
+        let entry = pass::parse_entry("wifi", None, "correct horse").unwrap();
+        assert_eq!(entry.password, "correct horse");
+        assert_eq!(entry.notes, None);
+
+        assert!(pass::parse_entry("empty", None, "\nuser: me").is_none());
+    }
+
+    #[test]
+    fn parse_dir() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir_all(dir.path().join("infra/db"))?;
+        fs::create_dir(dir.path().join(".git"))?;
+        fs::write(dir.path().join(".gpg-id"), "ops@example.com\n")?;
+        fs::write(dir.path().join(".git/config"), "[core]\n")?;
+        fs::write(dir.path().join("router.txt"), "admin\n")?;
+        fs::write(
+            dir.path().join("infra/db/postgres"),
+            "s3cret\nuser: postgres\n",
+        )?;
+        fs::write(
+            dir.path().join("infra/encrypted.gpg"),
+            [0x85, 0x02, 0xff, 0xfe],
+        )?;
+        // A link back to the root would recurse forever
+        unix::fs::symlink(dir.path(), dir.path().join("loop"))?;
+        // A link to a file outside the tree would leak its contents
+        let outside = tempfile::tempdir()?;
+        fs::write(
+            outside.path().join("secret"),
+            "not for the vault
+",
+        )?;
+        unix::fs::symlink(outside.path().join("secret"), dir.path().join("leak"))?;
+
+        let parsed = pass::parse_dir(dir.path())?;
+        assert_eq!(parsed.passwords.len(), 2);
+
+        let postgres = &parsed.passwords[0];
+        assert_eq!(postgres.name, "postgres");
+        assert_eq!(postgres.folder.as_deref(), Some("infra/db"));
+        assert_eq!(postgres.username.as_deref(), Some("postgres"));
+
+        let router = &parsed.passwords[1];
+        assert_eq!(router.name, "router");
+        assert_eq!(router.folder, None);
+
+        assert_eq!(parsed.skipped.len(), 3);
+        assert_eq!(parsed.skipped[0].name, "encrypted");
+        assert_eq!(parsed.skipped[1].name, "leak");
+        assert_eq!(parsed.skipped[2].name, "loop");
+
+        Ok(())
+    }
+}
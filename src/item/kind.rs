@@ -0,0 +1,590 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Named values of an item as they are sent to the clients.
+pub type Fields = BTreeMap<String, String>;
+
+/// Functionality every type of vault item must implement.
+pub trait ItemFields {
+    /// Check whether all the fields contain valid values.
+    fn validate(&self) -> Result<()>;
+
+    /// The fields that are allowed to be shown in overviews.
+    fn public_fields(&self) -> Fields;
+
+    /// The fields that should only be sent when the item is explicitly requested.
+    fn secret_fields(&self) -> Fields;
+}
+
+/// The discriminant of the different types of items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemType {
+    Login,
+    Card,
+    Identity,
+    WifiNetwork,
+    ApiKey,
+    Database,
+    Note,
+}
+
+/// The type specific contents of an item.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ItemKind {
+    Login(Login),
+    Card(Card),
+    Identity(Identity),
+    WifiNetwork(WifiNetwork),
+    ApiKey(ApiKey),
+    Database(DatabaseCredential),
+    Note(Note),
+}
+
+impl ItemKind {
+    /// The discriminant of this item.
+    pub fn item_type(&self) -> ItemType {
+        match self {
+            ItemKind::Login(_) => ItemType::Login,
+            ItemKind::Card(_) => ItemType::Card,
+            ItemKind::Identity(_) => ItemType::Identity,
+            ItemKind::WifiNetwork(_) => ItemType::WifiNetwork,
+            ItemKind::ApiKey(_) => ItemType::ApiKey,
+            ItemKind::Database(_) => ItemType::Database,
+            ItemKind::Note(_) => ItemType::Note,
+        }
+    }
+
+    /// Get the inner item as a trait object.
+    fn fields(&self) -> &dyn ItemFields {
+        match self {
+            ItemKind::Login(login) => login,
+            ItemKind::Card(card) => card,
+            ItemKind::Identity(identity) => identity,
+            ItemKind::WifiNetwork(wifi) => wifi,
+            ItemKind::ApiKey(api_key) => api_key,
+            ItemKind::Database(database) => database,
+            ItemKind::Note(note) => note,
+        }
+    }
+}
+
+impl ItemFields for ItemKind {
+    fn validate(&self) -> Result<()> {
+        self.fields().validate()
+    }
+
+    fn public_fields(&self) -> Fields {
+        self.fields().public_fields()
+    }
+
+    fn secret_fields(&self) -> Fields {
+        self.fields().secret_fields()
+    }
+}
+
+/// Credentials for logging in to a website or application.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Login {
+    /// The username.
+    pub username: Option<String>,
+    /// The e-mail associated.
+    pub email: Option<String>,
+    /// The website associated.
+    pub website: Option<String>,
+    /// The actual password.
+    pub password: String,
+    /// The base32 encoded secret of the time-based one time password.
+    pub totp: Option<String>,
+}
+
+impl ItemFields for Login {
+    fn validate(&self) -> Result<()> {
+        require_non_empty("password", &self.password)?;
+
+        if let Some(totp) = &self.totp {
+            // The TOTP secret is base32 encoded, padding and spaces are allowed
+            if !totp
+                .chars()
+                .filter(|c| *c != ' ' && *c != '=')
+                .all(|c| matches!(c.to_ascii_uppercase(), 'A'..='Z' | '2'..='7'))
+            {
+                bail!("Field \"totp\" must be a base32 encoded secret");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn public_fields(&self) -> Fields {
+        let mut fields = Fields::new();
+        insert_optional(&mut fields, "username", &self.username);
+        insert_optional(&mut fields, "email", &self.email);
+        insert_optional(&mut fields, "website", &self.website);
+
+        fields
+    }
+
+    fn secret_fields(&self) -> Fields {
+        let mut fields = Fields::new();
+        fields.insert("password".to_string(), self.password.clone());
+        insert_optional(&mut fields, "totp", &self.totp);
+
+        fields
+    }
+}
+
+/// A payment card.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Card {
+    /// Name of the card holder as printed on the card.
+    pub cardholder: String,
+    /// The card number, spaces are allowed.
+    pub number: String,
+    /// Month the card expires, starting at 1.
+    pub expiry_month: u8,
+    /// Year the card expires in four digits.
+    pub expiry_year: u16,
+    /// The security code on the back of the card.
+    pub security_code: Option<String>,
+}
+
+impl Card {
+    /// The card number without any formatting.
+    fn digits(&self) -> String {
+        self.number.chars().filter(|c| *c != ' ').collect()
+    }
+}
+
+impl ItemFields for Card {
+    fn validate(&self) -> Result<()> {
+        require_non_empty("cardholder", &self.cardholder)?;
+
+        let digits = self.digits();
+        if !(12..=19).contains(&digits.len()) || !digits.chars().all(|c| c.is_ascii_digit()) {
+            bail!("Field \"number\" must contain between 12 and 19 digits");
+        }
+        if !luhn_valid(&digits) {
+            bail!("Field \"number\" is not a valid card number");
+        }
+
+        if !(1..=12).contains(&self.expiry_month) {
+            bail!("Field \"expiry_month\" must be between 1 and 12");
+        }
+        if !(1000..=9999).contains(&self.expiry_year) {
+            bail!("Field \"expiry_year\" must contain four digits");
+        }
+
+        if let Some(security_code) = &self.security_code {
+            if !(3..=4).contains(&security_code.len())
+                || !security_code.chars().all(|c| c.is_ascii_digit())
+            {
+                bail!("Field \"security_code\" must contain 3 or 4 digits");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn public_fields(&self) -> Fields {
+        let digits = self.digits();
+
+        let mut fields = Fields::new();
+        fields.insert("cardholder".to_string(), self.cardholder.clone());
+        // Only the last four digits are allowed to be shown
+        fields.insert(
+            "last_digits".to_string(),
+            digits[digits.len().saturating_sub(4)..].to_string(),
+        );
+        fields.insert(
+            "expiry".to_string(),
+            format!("{:02}/{}", self.expiry_month, self.expiry_year),
+        );
+
+        fields
+    }
+
+    fn secret_fields(&self) -> Fields {
+        let mut fields = Fields::new();
+        fields.insert("number".to_string(), self.digits());
+        insert_optional(&mut fields, "security_code", &self.security_code);
+
+        fields
+    }
+}
+
+/// Personal information for filling in forms.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Identity {
+    /// First name.
+    pub first_name: Option<String>,
+    /// Last name.
+    pub last_name: Option<String>,
+    /// E-mail address.
+    pub email: Option<String>,
+    /// Phone number.
+    pub phone: Option<String>,
+    /// Full postal address.
+    pub address: Option<String>,
+    /// Number of a passport, driver's license or other identity document.
+    pub document_number: Option<String>,
+}
+
+impl ItemFields for Identity {
+    fn validate(&self) -> Result<()> {
+        if self.first_name.is_none() && self.last_name.is_none() {
+            bail!("Either field \"first_name\" or \"last_name\" must be set");
+        }
+
+        if let Some(email) = &self.email {
+            if !email.contains('@') {
+                bail!("Field \"email\" must be a valid e-mail address");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn public_fields(&self) -> Fields {
+        let mut fields = Fields::new();
+        insert_optional(&mut fields, "first_name", &self.first_name);
+        insert_optional(&mut fields, "last_name", &self.last_name);
+        insert_optional(&mut fields, "email", &self.email);
+
+        fields
+    }
+
+    fn secret_fields(&self) -> Fields {
+        let mut fields = Fields::new();
+        insert_optional(&mut fields, "phone", &self.phone);
+        insert_optional(&mut fields, "address", &self.address);
+        insert_optional(&mut fields, "document_number", &self.document_number);
+
+        fields
+    }
+}
+
+/// The security protocol of a Wi-Fi network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WifiSecurity {
+    Open,
+    Wep,
+    Wpa,
+    Wpa2,
+    Wpa3,
+}
+
+/// Credentials for a wireless network.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WifiNetwork {
+    /// Name of the network.
+    pub ssid: String,
+    /// Security protocol used by the network.
+    pub security: WifiSecurity,
+    /// The passphrase, not set for open networks.
+    pub password: Option<String>,
+}
+
+impl ItemFields for WifiNetwork {
+    fn validate(&self) -> Result<()> {
+        if !(1..=32).contains(&self.ssid.len()) {
+            bail!("Field \"ssid\" must be between 1 and 32 bytes");
+        }
+
+        match (self.security, &self.password) {
+            (WifiSecurity::Open, Some(_)) => {
+                bail!("Field \"password\" can't be set for an open network")
+            }
+            (WifiSecurity::Open, None) => (),
+            (_, None) => bail!("Field \"password\" is required for a secured network"),
+            (WifiSecurity::Wep, Some(password)) => {
+                if ![5, 10, 13, 26].contains(&password.len()) {
+                    bail!("Field \"password\" must be a valid WEP key");
+                }
+            }
+            (_, Some(password)) => {
+                if !(8..=63).contains(&password.len()) {
+                    bail!("Field \"password\" must be between 8 and 63 characters");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn public_fields(&self) -> Fields {
+        let mut fields = Fields::new();
+        fields.insert("ssid".to_string(), self.ssid.clone());
+        fields.insert(
+            "security".to_string(),
+            format!("{:?}", self.security).to_lowercase(),
+        );
+
+        fields
+    }
+
+    fn secret_fields(&self) -> Fields {
+        let mut fields = Fields::new();
+        insert_optional(&mut fields, "password", &self.password);
+
+        fields
+    }
+}
+
+/// An API key or access token for a service.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiKey {
+    /// Name of the service the key grants access to.
+    pub service: String,
+    /// The key or token.
+    pub key: String,
+    /// An optional secret belonging to the key.
+    pub secret: Option<String>,
+    /// When the key expires, as the user entered it.
+    pub expires: Option<String>,
+}
+
+impl ItemFields for ApiKey {
+    fn validate(&self) -> Result<()> {
+        require_non_empty("service", &self.service)?;
+        require_non_empty("key", &self.key)
+    }
+
+    fn public_fields(&self) -> Fields {
+        let mut fields = Fields::new();
+        fields.insert("service".to_string(), self.service.clone());
+        insert_optional(&mut fields, "expires", &self.expires);
+
+        fields
+    }
+
+    fn secret_fields(&self) -> Fields {
+        let mut fields = Fields::new();
+        fields.insert("key".to_string(), self.key.clone());
+        insert_optional(&mut fields, "secret", &self.secret);
+
+        fields
+    }
+}
+
+/// Credentials for connecting to a database server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatabaseCredential {
+    /// The database software, like "postgresql".
+    pub engine: Option<String>,
+    /// Hostname or IP address of the server.
+    pub host: String,
+    /// Port the server listens on.
+    pub port: Option<u16>,
+    /// Name of the database.
+    pub database: Option<String>,
+    /// The username.
+    pub username: String,
+    /// The actual password.
+    pub password: String,
+}
+
+impl ItemFields for DatabaseCredential {
+    fn validate(&self) -> Result<()> {
+        require_non_empty("host", &self.host)?;
+        require_non_empty("username", &self.username)?;
+        require_non_empty("password", &self.password)?;
+
+        if self.port == Some(0) {
+            bail!("Field \"port\" can't be 0");
+        }
+
+        Ok(())
+    }
+
+    fn public_fields(&self) -> Fields {
+        let mut fields = Fields::new();
+        insert_optional(&mut fields, "engine", &self.engine);
+        fields.insert("host".to_string(), self.host.clone());
+        if let Some(port) = self.port {
+            fields.insert("port".to_string(), port.to_string());
+        }
+        insert_optional(&mut fields, "database", &self.database);
+        fields.insert("username".to_string(), self.username.clone());
+
+        fields
+    }
+
+    fn secret_fields(&self) -> Fields {
+        let mut fields = Fields::new();
+        fields.insert("password".to_string(), self.password.clone());
+
+        fields
+    }
+}
+
+/// Free form text that should be kept secret.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Note {
+    /// The contents of the note.
+    pub text: String,
+}
+
+impl ItemFields for Note {
+    fn validate(&self) -> Result<()> {
+        require_non_empty("text", &self.text)
+    }
+
+    fn public_fields(&self) -> Fields {
+        // Only the name of the note is shown in overviews
+        Fields::new()
+    }
+
+    fn secret_fields(&self) -> Fields {
+        let mut fields = Fields::new();
+        fields.insert("text".to_string(), self.text.clone());
+
+        fields
+    }
+}
+
+/// Throw an error when a required field is empty.
+fn require_non_empty(name: &str, value: &str) -> Result<()> {
+    if value.trim().is_empty() {
+        bail!("Field \"{}\" can't be empty", name);
+    }
+
+    Ok(())
+}
+
+/// Add a field when it contains a value.
+fn insert_optional(fields: &mut Fields, name: &str, value: &Option<String>) {
+    if let Some(value) = value {
+        fields.insert(name.to_string(), value.clone());
+    }
+}
+
+/// Verify the checksum of a card number with the Luhn algorithm.
+fn luhn_valid(digits: &str) -> bool {
+    let mut sum = 0;
+    // Every second digit counted from the right is doubled
+    let mut double = false;
+    for digit in digits.chars().rev().filter_map(|c| c.to_digit(10)) {
+        sum += match (double, digit * 2) {
+            // Subtract 9 when the doubled digit is larger than a single digit
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => digit,
+        };
+        double = !double;
+    }
+
+    matches!(sum % 10, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::item::kind::{
+        Card, DatabaseCredential, ItemFields, ItemKind, ItemType, WifiNetwork, WifiSecurity,
+    };
+    use anyhow::Result;
+
+    fn card(number: &str) -> Card {
+        Card {
+            cardholder: "J. Doe".to_string(),
+            number: number.to_string(),
+            expiry_month: 4,
+            expiry_year: 2030,
+            security_code: Some("123".to_string()),
+        }
+    }
+
+    #[test]
+    fn card_validation() {
+        // Valid
+        assert!(card("4111 1111 1111 1111").validate().is_ok());
+        assert!(card("5500005555555559").validate().is_ok());
+
+        // Invalid
+        assert!(card("4111 1111 1111 1112").validate().is_err());
+        assert!(card("1234").validate().is_err());
+        assert!(card("4111-1111-1111-1111").validate().is_err());
+        assert!(Card {
+            expiry_month: 13,
+            ..card("4111 1111 1111 1111")
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn card_public_fields() {
+        let card = card("4111 1111 1111 1111");
+
+        let public = card.public_fields();
+        assert_eq!(public["last_digits"], "1111");
+        assert_eq!(public["expiry"], "04/2030");
+        assert!(!public.contains_key("number"));
+
+        let secret = card.secret_fields();
+        assert_eq!(secret["number"], "4111111111111111");
+        assert_eq!(secret["security_code"], "123");
+    }
+
+    #[test]
+    fn wifi_validation() {
+        let wifi = |security, password: Option<&str>| WifiNetwork {
+            ssid: "home".to_string(),
+            security,
+            password: password.map(|p| p.to_string()),
+        };
+
+        // Valid
+        assert!(wifi(WifiSecurity::Open, None).validate().is_ok());
+        assert!(wifi(WifiSecurity::Wpa2, Some("long enough"))
+            .validate()
+            .is_ok());
+
+        // Invalid
+        assert!(wifi(WifiSecurity::Open, Some("password"))
+            .validate()
+            .is_err());
+        assert!(wifi(WifiSecurity::Wpa3, None).validate().is_err());
+        assert!(wifi(WifiSecurity::Wpa2, Some("short")).validate().is_err());
+    }
+
+    #[test]
+    fn database_validation() {
+        let database = |password: &str| DatabaseCredential {
+            engine: None,
+            host: "localhost".to_string(),
+            port: Some(5432),
+            database: None,
+            username: "app".to_string(),
+            password: password.to_string(),
+        };
+
+        // Valid
+        assert!(database("secret").validate().is_ok());
+
+        // Invalid
+        assert!(database("").validate().is_err());
+        assert!(database("  ").validate().is_err());
+    }
+
+    #[test]
+    fn tagged_deserialize() -> Result<()> {
+        let kind: ItemKind = serde_json::from_str(
+            r#"{
+                "type": "api_key",
+                "service": "example",
+                "key": "abcdef",
+                "secret": null,
+                "expires": null
+            }"#,
+        )?;
+        assert_eq!(kind.item_type(), ItemType::ApiKey);
+        assert!(kind.validate().is_ok());
+        assert_eq!(kind.secret_fields()["key"], "abcdef");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,339 @@
+pub mod kind;
+
+use crate::{
+    app::AppState,
+    attachment,
+    body::EncryptedBody,
+    password::{self, Password, Passwords},
+};
+use actix_web::{
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    web::{Data, Path, Query},
+    Result as WebResult,
+};
+use anyhow::Result;
+use kind::{Fields, ItemFields, ItemKind, ItemType, Login};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Allow converting an incoming message to an item.
+trait ToItem {
+    fn to_item(&self) -> Result<Item>;
+}
+
+/// All the vault items.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Items {
+    /// The items.
+    items: Vec<Item>,
+}
+
+impl Items {
+    /// Register a new item.
+    pub fn register(&mut self, item: Item) {
+        self.items.push(item);
+    }
+
+    /// Get an item by ID.
+    pub fn by_id(&self, id: &str) -> Option<&Item> {
+        self.items.iter().find(|item| item.id == id)
+    }
+
+    /// Remove an item, returns whether it existed.
+    pub fn remove(&mut self, id: &str) -> bool {
+        let len = self.items.len();
+        self.items.retain(|item| item.id != id);
+
+        len != self.items.len()
+    }
+
+    /// Iterate over all items.
+    pub fn iter(&self) -> impl Iterator<Item = &Item> {
+        self.items.iter()
+    }
+
+    /// Get a vector of items as allowed to be shown to the clients.
+    ///
+    /// When a type is passed only the items of that type are returned.
+    pub fn to_public_vec(&self, item_type: Option<ItemType>) -> Vec<PublicItem> {
+        self.items
+            .iter()
+            .filter(|item| match item_type {
+                Some(item_type) => item.item_type() == item_type,
+                None => true,
+            })
+            .map(|item| item.to_public())
+            .collect()
+    }
+}
+
+/// A vault item of any type.
+///
+/// Logins are stored as passwords, so the password and the item endpoints show the same entries.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Item {
+    /// Unique identifier.
+    pub id: String,
+    /// Name of the item as configured by the user.
+    pub name: String,
+    /// The type specific contents.
+    #[serde(flatten)]
+    pub kind: ItemKind,
+}
+
+impl Item {
+    /// Validate the fields and construct a new item with a generated unique identifier.
+    pub fn new<S>(name: S, kind: ItemKind) -> Result<Self>
+    where
+        S: Into<String>,
+    {
+        kind.validate()?;
+
+        Ok(Self {
+            id: Uuid::new_v4().to_simple().to_string(),
+            name: name.into(),
+            kind,
+        })
+    }
+
+    /// The discriminant of this item.
+    pub fn item_type(&self) -> ItemType {
+        self.kind.item_type()
+    }
+
+    /// Convert it to a message response containing the secret fields.
+    pub fn to_response(&self) -> ItemResponse {
+        ItemResponse {
+            id: self.id.clone(),
+            item_type: self.item_type(),
+            secrets: self.kind.secret_fields(),
+        }
+    }
+
+    /// Convert it to a public item, without the secret fields.
+    pub fn to_public(&self) -> PublicItem {
+        PublicItem {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            item_type: self.item_type(),
+            fields: self.kind.public_fields(),
+        }
+    }
+
+    /// Convert a login to the password it's stored as, `None` for all other types.
+    pub fn to_password(&self) -> Option<Password> {
+        match &self.kind {
+            ItemKind::Login(login) => {
+                let mut password = Password {
+                    id: self.id.clone(),
+                    email: login.email.clone(),
+                    website: login.website.clone(),
+                    totp: login.totp.clone(),
+                    ..Password::new(&self.name, &login.password)
+                };
+                password.set_username(login.username.clone());
+
+                Some(password)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<&Password> for Item {
+    /// Show a password as a login item.
+    fn from(password: &Password) -> Self {
+        Self {
+            id: password.id.clone(),
+            name: password.name.clone(),
+            kind: ItemKind::Login(Login {
+                username: password.username.clone(),
+                email: password.email.clone(),
+                website: password.website.clone(),
+                password: password.password.clone(),
+                totp: password.totp.clone(),
+            }),
+        }
+    }
+}
+
+/// An item registration request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisterItemRequest {
+    /// Name of the item as configured by the user.
+    pub name: String,
+    /// The type specific contents.
+    #[serde(flatten)]
+    pub kind: ItemKind,
+}
+
+impl ToItem for RegisterItemRequest {
+    /// Validate the fields and convert this into an item struct that can be added to the database.
+    fn to_item(&self) -> Result<Item> {
+        Item::new(&self.name, self.kind.clone())
+    }
+}
+
+/// Item information without the secret fields.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PublicItem {
+    /// Unique identifier.
+    pub id: String,
+    /// Name of the item.
+    pub name: String,
+    /// The type of the item.
+    #[serde(rename = "type")]
+    pub item_type: ItemType,
+    /// The fields that are allowed to be shown in overviews.
+    pub fields: Fields,
+}
+
+/// The secret fields of an item.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ItemResponse {
+    /// Unique identifier.
+    pub id: String,
+    /// The type of the item.
+    #[serde(rename = "type")]
+    pub item_type: ItemType,
+    /// The secret fields.
+    pub secrets: Fields,
+}
+
+/// Query parameters to filter the list of items.
+#[derive(Debug, Deserialize)]
+pub struct ItemFilter {
+    /// Only return the items of this type.
+    #[serde(rename = "type")]
+    item_type: Option<ItemType>,
+}
+
+/// Get a single item with the secret fields.
+pub async fn get_item(
+    Path((id,)): Path<(String,)>,
+    state: Data<AppState>,
+) -> WebResult<EncryptedBody<ItemResponse>> {
+    let items = state.items().await.map_err(ErrorInternalServerError)?;
+    let passwords = state.passwords().await.map_err(ErrorInternalServerError)?;
+
+    // Find the specific item, logins are stored as passwords
+    match items
+        .by_id(&id)
+        .cloned()
+        .or_else(|| passwords.by_id(&id).map(Item::from))
+    {
+        Some(item) => Ok(EncryptedBody::new(item.to_response())),
+        None => Err(ErrorNotFound(format!(
+            "Item with ID \"{}\" does not exist",
+            id
+        ))),
+    }
+}
+
+/// Get a list of all items, optionally filtered by type.
+pub async fn get_items(
+    filter: Query<ItemFilter>,
+    state: Data<AppState>,
+) -> WebResult<EncryptedBody<Vec<PublicItem>>> {
+    let items = state.items().await.map_err(ErrorInternalServerError)?;
+    let mut public_items = items.to_public_vec(filter.item_type);
+
+    // Logins are stored as passwords
+    if matches!(filter.item_type, None | Some(ItemType::Login)) {
+        let passwords = state.passwords().await.map_err(ErrorInternalServerError)?;
+        public_items.extend(
+            passwords
+                .iter()
+                .map(|password| Item::from(password).to_public()),
+        );
+    }
+
+    Ok(EncryptedBody::new(public_items))
+}
+
+/// Register a new item.
+pub async fn post_items(
+    item: EncryptedBody<RegisterItemRequest>,
+    state: Data<AppState>,
+) -> WebResult<EncryptedBody<PublicItem>> {
+    // Validate the request and convert it to an internal item used for storage
+    let item = item.to_item().map_err(ErrorBadRequest)?;
+
+    // Get a mutex lock on the storage for the whole update
+    let storage = state.storage.lock().await;
+
+    if let Some(mut password) = item.to_password() {
+        // Get the passwords from the database or use the default
+        let mut passwords = storage
+            .get::<_, Passwords>("passwords")
+            .await?
+            .unwrap_or_else(Passwords::default);
+
+        password::flag_compromised(&state, &mut password)?;
+
+        // Register the login as a password
+        passwords.register(password);
+
+        // Persist the passwords in the storage
+        storage.set("passwords", &passwords).await?;
+    } else {
+        // Get the items from the database or use the default
+        let mut items = storage
+            .get::<_, Items>("items")
+            .await?
+            .unwrap_or_else(Items::default);
+
+        // Register the passed item
+        items.register(item.clone());
+
+        // Persist the items in the storage
+        storage.set("items", &items).await?;
+    }
+
+    Ok(EncryptedBody::new(item.to_public()))
+}
+
+/// Delete an item.
+pub async fn delete_item(
+    Path((id,)): Path<(String,)>,
+    state: Data<AppState>,
+) -> WebResult<EncryptedBody<()>> {
+    // Get a mutex lock on the storage for the whole update
+    let storage = state.storage.lock().await;
+
+    let mut items = storage
+        .get::<_, Items>("items")
+        .await?
+        .unwrap_or_else(Items::default);
+    if items.remove(&id) {
+        // Persist the items in the storage
+        storage.set("items", &items).await?;
+
+        attachment::delete_entry_attachments(&storage, &id)
+            .await
+            .map_err(ErrorInternalServerError)?;
+
+        return Ok(EncryptedBody::new(()));
+    }
+
+    // Logins are stored as passwords
+    let mut passwords = storage
+        .get::<_, Passwords>("passwords")
+        .await?
+        .unwrap_or_else(Passwords::default);
+    if passwords.remove(&id).is_none() {
+        return Err(ErrorNotFound(format!(
+            "Item with ID \"{}\" does not exist",
+            id
+        )));
+    }
+
+    // Persist the passwords in the storage
+    storage.set("passwords", &passwords).await?;
+
+    attachment::delete_entry_attachments(&storage, &id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(EncryptedBody::new(()))
+}
@@ -0,0 +1,169 @@
+use crate::{app, config::Config};
+use anyhow::{anyhow, Result};
+use log::info;
+use rustls::{internal::pemfile, Certificate, NoClientAuth, ServerConfig};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::Path,
+};
+
+/// Name the self-signed certificate is issued for.
+///
+/// Clients can't verify it with a certificate authority, they pin the fingerprint instead.
+pub const CERTIFICATE_NAME: &str = "keybear";
+
+/// Generate the self-signed certificate of the LAN listener when it doesn't exist yet.
+///
+/// Only the owner can read the private key.
+pub fn ensure_certificate(config: &Config) -> Result<()> {
+    let certificate_path = config.lan_certificate_path();
+    let private_key_path = config.lan_private_key_path();
+    if certificate_path.exists() && private_key_path.exists() {
+        return Ok(());
+    }
+
+    let certificate = rcgen::generate_simple_self_signed(vec![CERTIFICATE_NAME.to_string()])
+        .map_err(|err| anyhow!("Generating LAN certificate failed: {}", err))?;
+    let certificate_pem = certificate
+        .serialize_pem()
+        .map_err(|err| anyhow!("Serializing LAN certificate failed: {}", err))?;
+
+    app::write_private_file(
+        &private_key_path,
+        certificate.serialize_private_key_pem().as_bytes(),
+    )?;
+    fs::write(&certificate_path, certificate_pem).map_err(|err| {
+        anyhow!(
+            "Writing LAN certificate {:?} failed: {}",
+            certificate_path,
+            err
+        )
+    })?;
+
+    info!("Generated LAN certificate {:?}", certificate_path);
+
+    Ok(())
+}
+
+/// The fingerprint of the LAN certificate clients can pin, `None` when the LAN listener is
+/// disabled.
+pub fn certificate_fingerprint(config: &Config) -> Result<Option<String>> {
+    if config.lan_address().is_none() {
+        return Ok(None);
+    }
+
+    let certificates = read_certificates(&config.lan_certificate_path())?;
+
+    Ok(Some(fingerprint(&certificates[0])))
+}
+
+/// The SHA-256 fingerprint of a certificate as colon separated uppercase hexadecimal bytes.
+pub fn fingerprint(certificate: &Certificate) -> String {
+    Sha256::digest(&certificate.0)
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// The TLS configuration of the LAN listener with the stored certificate.
+pub fn tls_config(config: &Config) -> Result<ServerConfig> {
+    let certificates = read_certificates(&config.lan_certificate_path())?;
+
+    let private_key_path = config.lan_private_key_path();
+    let private_key = pemfile::pkcs8_private_keys(&mut BufReader::new(open(&private_key_path)?))
+        .ok()
+        .and_then(|keys| keys.into_iter().next())
+        .ok_or_else(|| anyhow!("LAN private key {:?} is invalid", private_key_path))?;
+
+    // Clients authenticate with their own keys in the encrypted bodies
+    let mut tls_config = ServerConfig::new(NoClientAuth::new());
+    tls_config
+        .set_single_cert(certificates, private_key)
+        .map_err(|err| anyhow!("LAN certificate can't be used: {}", err))?;
+
+    Ok(tls_config)
+}
+
+/// Read the certificate chain from a PEM file.
+fn read_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    pemfile::certs(&mut BufReader::new(open(path)?))
+        .ok()
+        .filter(|certificates| !certificates.is_empty())
+        .ok_or_else(|| anyhow!("LAN certificate {:?} is invalid", path))
+}
+
+/// Open a file of the LAN certificate.
+fn open(path: &Path) -> Result<File> {
+    File::open(path).map_err(|err| anyhow!("Opening {:?} failed: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{config::Config, lan, test};
+    use anyhow::Result;
+    use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+
+    fn config(dir: &Path) -> Result<Config> {
+        Config::from_raw_str(&format!(
+            r#"
+            key_path = {:?}
+
+            [lan]
+            address = "192.168.1.10:52478"
+            "#,
+            dir.join("key")
+        ))
+    }
+
+    #[test]
+    fn certificate() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = config(dir.path())?;
+        assert!(lan::tls_config(&config).is_err());
+
+        lan::ensure_certificate(&config)?;
+        assert!(lan::tls_config(&config).is_ok());
+        assert_eq!(
+            fs::metadata(config.lan_private_key_path())?
+                .permissions()
+                .mode()
+                & 0o777,
+            0o600
+        );
+
+        let fingerprint = lan::certificate_fingerprint(&config)?.unwrap();
+        // 32 bytes separated by colons
+        assert_eq!(fingerprint.len(), 32 * 3 - 1);
+
+        // An existing certificate is kept so the pinned fingerprint stays valid
+        lan::ensure_certificate(&config)?;
+        assert_eq!(lan::certificate_fingerprint(&config)?, Some(fingerprint));
+
+        fs::write(config.lan_certificate_path(), "invalid")?;
+        assert!(lan::certificate_fingerprint(&config).is_err());
+
+        // Without a LAN address there's nothing to pin
+        assert_eq!(lan::certificate_fingerprint(&Config::default())?, None);
+
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn register() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let state = test::app_state_with_config(config(dir.path())?);
+        lan::ensure_certificate(&state.config)?;
+
+        let response = test::register_device(&state, "test_device").await?;
+        assert_eq!(
+            response.lan_certificate_fingerprint,
+            lan::certificate_fingerprint(&state.config)?
+        );
+        assert!(response.lan_certificate_fingerprint.is_some());
+
+        Ok(())
+    }
+}
@@ -1,42 +1,175 @@
 #![forbid(unsafe_code)]
 
+pub mod admin;
 pub mod app;
+pub mod attachment;
+pub mod backup;
 pub mod body;
+pub mod breach;
 pub mod config;
 pub mod device;
+pub mod export;
+pub mod generator;
+pub mod health;
+pub mod import;
+pub mod item;
+pub mod lan;
+pub mod logging;
+pub mod metrics;
 pub mod net;
+pub mod pair;
 pub mod password;
+pub mod report;
 pub mod route;
+pub mod snapshot;
 pub mod store;
 // Due to integration tests not taking `[cfg(test)]` this has to be exposed publicly
 pub mod test;
+pub mod tor;
 
-use actix_web::{middleware::Logger, web::Data, App, HttpServer};
-use anyhow::Result;
+use actix_web::{guard, web, web::Data, App, HttpServer};
+use anyhow::{anyhow, bail, Result};
 use app::AppState;
 use config::Config;
-use std::net::{Ipv4Addr, SocketAddrV4};
+use futures::future;
+use log::{info, warn};
+use net::{rate_limit::RateLimit, ListenAddress, ListenerPolicy, TrustedSources};
 
 /// Run the keybear server.
 pub async fn run(config: Config) -> Result<()> {
     // Setup the application state.
     let state = Data::new(AppState::from_config(&config)?);
 
+    // Check the stored passwords against the breach dataset when configured
+    if config.hibp_path().is_some() {
+        breach::check_stored_passwords(&state).await?;
+    }
+
+    // Only the holder of the bootstrap token can register the first device
+    if config.registration_open() {
+        if let Some(token) = device::bootstrap::ensure_token(&state).await? {
+            warn!(
+                "No devices are registered yet, register the first device with bootstrap token \"{}\"",
+                token
+            );
+        }
+    }
+
+    // Create the onion service through the Tor control port when configured, it's removed again
+    // when the connection is dropped
+    let _tor_connection = tor::provision(&config)?;
+
+    // Periodically take snapshots of the database when configured
+    if config.snapshot_directory().is_some() {
+        actix_web::rt::spawn(snapshot::schedule(state.clone()));
+    }
+
+    // Serve clients on the local network over TLS when configured
+    let lan_server = match config.lan_address() {
+        Some(address) => {
+            lan::ensure_certificate(&config)?;
+            let tls_config = lan::tls_config(&config)?;
+
+            let state = state.clone();
+            let rate_limit = RateLimit::new(config.rate_limit_per_minute());
+            let server = HttpServer::new(move || {
+                app::fill_app(
+                    App::new().wrap(rate_limit.clone()).wrap(net::logger()),
+                    &state,
+                    ListenerPolicy::Lan,
+                )
+            })
+            .bind_rustls(address, tls_config)
+            .map_err(|err| anyhow!("Listening on {} failed: {}", address, err))?
+            .run();
+
+            info!("Listening on {} for the local network", address);
+
+            Some(server)
+        }
+        None => None,
+    };
+
+    // Expose the metrics on a separate port so they're never reachable through Tor or the LAN
+    let metrics_server = match config.metrics_address() {
+        Some(address) => {
+            if !address.ip().is_loopback() {
+                bail!("Metrics address {} must be a loopback address", address);
+            }
+
+            let state = state.clone();
+            let server = HttpServer::new(move || {
+                App::new().app_data(state.clone()).service(
+                    web::resource(route::METRICS)
+                        .route(web::get().to(metrics::metrics))
+                        .guard(guard::fn_guard(|req| {
+                            req.peer_addr
+                                .map(|addr| addr.ip().is_loopback())
+                                .unwrap_or(false)
+                        })),
+                )
+            })
+            .bind(address)
+            .map_err(|err| anyhow!("Listening on {} failed: {}", address, err))?
+            .run();
+
+            info!("Exposing metrics on {}", address);
+
+            Some(server)
+        }
+        None => None,
+    };
+
     // Start the Tor server
-    Ok(HttpServer::new(move || {
+    let policy = ListenerPolicy::tor(&config);
+    let rate_limit = RateLimit::new(config.rate_limit_per_minute());
+    let factory = move || {
         app::fill_app(
             App::new()
-                // Use the default logging service
-                .wrap(Logger::default()),
+                // Limit the requests of every client, or every Tor circuit when known
+                .wrap(rate_limit.clone())
+                // Log the requests together with the Tor circuit
+                .wrap(net::logger()),
             &state,
+            policy.clone(),
         )
-    })
-    // Disable TCP keep alive
-    .keep_alive(None)
-    // Bind to the Tor service using the port from the config
-    .bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, config.server_port()))?
-    .run()
-    .await?)
+    };
+
+    // Bind to all addresses the Tor service can connect to
+    let listen_addresses = config.listen_addresses();
+    let server = if config.proxy_protocol() {
+        net::proxy::server(
+            &listen_addresses,
+            TrustedSources::from_config(&config),
+            factory,
+        )?
+    } else {
+        let mut server = HttpServer::new(factory)
+            // Disable TCP keep alive
+            .keep_alive(None);
+        for address in &listen_addresses {
+            server = match address {
+                ListenAddress::Tcp(addr) => server.bind(addr),
+                ListenAddress::Unix(path) => server.bind_uds(path),
+            }
+            .map_err(|err| anyhow!("Listening on {} failed: {}", address, err))?;
+        }
+
+        server.run()
+    };
+    for address in &listen_addresses {
+        info!("Listening on {}", address);
+    }
+
+    // Run until all servers are stopped
+    future::try_join_all(
+        std::iter::once(server)
+            .chain(lan_server)
+            .chain(metrics_server),
+    )
+    .await?;
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -0,0 +1,483 @@
+use crate::config::Config;
+use anyhow::{anyhow, bail, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    fmt::{self, Display, Formatter},
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    os::unix::net::UnixDatagram,
+    path::PathBuf,
+    str::FromStr,
+    sync::Mutex,
+};
+use syslog::{Facility, Formatter3164, LoggerBackend};
+
+/// Socket of the native journald protocol.
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+/// Name the log messages are tagged with.
+const IDENTIFIER: &str = "keybear";
+/// What sensitive values are replaced with in the logs.
+const REDACTED: &str = "[redacted]";
+/// Minimum length of a hexadecimal run that's considered an ID or a nonce.
+///
+/// Device IDs are 32 hexadecimal characters, nonces are 12 bytes.
+const MIN_REDACTED_LENGTH: usize = 24;
+
+/// Where the log messages are written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogTarget {
+    /// The local syslog daemon.
+    Syslog,
+    /// The systemd journal with all fields.
+    Journald,
+    /// The standard error output.
+    Stderr,
+    /// A file, appended to.
+    File,
+}
+
+impl LogTarget {
+    /// Names of all targets, as used in the configuration and command line.
+    pub const NAMES: &'static [&'static str] = &["syslog", "journald", "stderr", "file"];
+}
+
+impl FromStr for LogTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "syslog" => Ok(Self::Syslog),
+            "journald" => Ok(Self::Journald),
+            "stderr" => Ok(Self::Stderr),
+            "file" => Ok(Self::File),
+            _ => bail!("Unknown log target \"{}\"", s),
+        }
+    }
+}
+
+impl Display for LogTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Syslog => "syslog",
+            Self::Journald => "journald",
+            Self::Stderr => "stderr",
+            Self::File => "file",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// How the logger is set up, taken from the configuration and overridden by the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// Where the messages are written to.
+    pub target: LogTarget,
+    /// The most verbose level that's written.
+    pub level: LevelFilter,
+    /// The file written to with the file target.
+    pub path: PathBuf,
+}
+
+impl Settings {
+    /// Take the settings from the configuration.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            target: config.log_target(),
+            level: config.log_level(),
+            path: config.log_path().to_path_buf(),
+        }
+    }
+}
+
+/// The output of the logger.
+enum Output {
+    /// Sent to syslog.
+    Syslog(Mutex<syslog::Logger<LoggerBackend, Formatter3164>>),
+    /// Sent to the journal.
+    Journald(UnixDatagram),
+    /// Written to the standard error output.
+    Stderr,
+    /// Appended to a file.
+    File(Mutex<File>),
+}
+
+/// Logger writing every message as structured fields.
+pub struct Logger {
+    /// The most verbose level that's written.
+    level: LevelFilter,
+    /// Where the messages are written to.
+    output: Output,
+}
+
+impl Logger {
+    /// Open the output of the settings.
+    pub fn new(settings: &Settings) -> Result<Self> {
+        let output = match settings.target {
+            LogTarget::Syslog => {
+                let formatter = Formatter3164 {
+                    facility: Facility::LOG_USER,
+                    hostname: None,
+                    process: IDENTIFIER.to_string(),
+                    pid: std::process::id() as i32,
+                };
+                let logger = syslog::unix(formatter)
+                    .map_err(|err| anyhow!("Connecting to syslog failed: {}", err))?;
+
+                Output::Syslog(Mutex::new(logger))
+            }
+            LogTarget::Journald => {
+                let socket = UnixDatagram::unbound()?;
+                socket
+                    .connect(JOURNALD_SOCKET)
+                    .map_err(|err| anyhow!("Connecting to journald failed: {}", err))?;
+
+                Output::Journald(socket)
+            }
+            LogTarget::Stderr => Output::Stderr,
+            LogTarget::File => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&settings.path)
+                    .map_err(|err| {
+                        anyhow!("Opening log file {:?} failed: {}", settings.path, err)
+                    })?;
+
+                Output::File(Mutex::new(file))
+            }
+        };
+
+        Ok(Self {
+            level: settings.level,
+            output,
+        })
+    }
+
+    /// Write a record to the output.
+    fn write(&self, record: &Record) -> io::Result<()> {
+        let fields = fields(record);
+
+        match &self.output {
+            Output::Syslog(logger) => {
+                // Syslog adds the time and level itself
+                let message = logfmt(&fields[2..]);
+                let mut logger = logger.lock().unwrap();
+                match record.level() {
+                    Level::Error => logger.err(message),
+                    Level::Warn => logger.warning(message),
+                    Level::Info => logger.info(message),
+                    Level::Debug | Level::Trace => logger.debug(message),
+                }
+                .map_err(|err| io::Error::other(err.to_string()))
+            }
+            Output::Journald(socket) => {
+                socket.send(&journald_entry(record.level(), &fields))?;
+
+                Ok(())
+            }
+            Output::Stderr => writeln!(io::stderr(), "{}", logfmt(&fields)),
+            Output::File(file) => writeln!(file.lock().unwrap(), "{}", logfmt(&fields)),
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            // There's nowhere left to report the error to
+            let _ = self.write(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Output::File(file) = &self.output {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+/// Set up the global logger.
+///
+/// Falls back to the standard error output when the target can't be used, so the server keeps
+/// running without syslog or journald.
+pub fn init(settings: &Settings) -> Result<()> {
+    let (logger, fallback_reason) = match Logger::new(settings) {
+        Ok(logger) => (logger, None),
+        Err(err) => {
+            let fallback = Settings {
+                target: LogTarget::Stderr,
+                ..settings.clone()
+            };
+
+            (Logger::new(&fallback)?, Some(err))
+        }
+    };
+
+    log::set_boxed_logger(Box::new(logger))
+        .map_err(|err| anyhow!("Setting up logging failed: {}", err))?;
+    log::set_max_level(settings.level);
+
+    if let Some(err) = fallback_reason {
+        log::warn!(
+            "Logging to {} is unavailable, logging to stderr instead: {}",
+            settings.target,
+            err
+        );
+    }
+
+    Ok(())
+}
+
+/// The fields of a record, starting with the time and the level.
+fn fields(record: &Record) -> Vec<(&'static str, String)> {
+    let message = record.args().to_string();
+    // Identifiers are only allowed in the debug logs
+    let message = if record.level() <= Level::Info {
+        redact(&message).into_owned()
+    } else {
+        message
+    };
+
+    let mut fields = vec![
+        ("time", chrono::Utc::now().to_rfc3339()),
+        ("level", record.level().to_string().to_lowercase()),
+        ("target", record.target().to_string()),
+    ];
+    if let Some(file) = record.file() {
+        fields.push(("file", file.to_string()));
+    }
+    if let Some(line) = record.line() {
+        fields.push(("line", line.to_string()));
+    }
+    fields.push(("message", message));
+
+    fields
+}
+
+/// Format the fields as `key=value` pairs, quoting values where needed.
+fn logfmt(fields: &[(&str, String)]) -> String {
+    fields
+        .iter()
+        .map(|(key, value)| {
+            if value.is_empty()
+                || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=')
+            {
+                format!("{}={:?}", key, value)
+            } else {
+                format!("{}={}", key, value)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Encode the fields as an entry of the native journald protocol.
+fn journald_entry(level: Level, fields: &[(&str, String)]) -> Vec<u8> {
+    let priority = match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    };
+
+    let mut entry = Vec::new();
+    let mut push = |key: &str, value: &str| {
+        entry.extend_from_slice(key.as_bytes());
+        if value.contains('\n') {
+            // Values with newlines are prefixed with their length instead
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    };
+
+    push("PRIORITY", &priority.to_string());
+    push("SYSLOG_IDENTIFIER", IDENTIFIER);
+    // The journal keeps its own time and level is in the priority
+    for (key, value) in fields.iter().skip(2) {
+        let key = match *key {
+            "file" => "CODE_FILE".to_string(),
+            "line" => "CODE_LINE".to_string(),
+            key => key.to_uppercase(),
+        };
+        push(&key, value);
+    }
+
+    entry
+}
+
+/// Replace everything that looks like a device ID or a nonce.
+pub fn redact(message: &str) -> Cow<'_, str> {
+    let mut redacted = String::new();
+    let mut last = 0;
+    let mut start = None;
+
+    // Chain a non hexadecimal character so a run at the end is also handled
+    for (index, c) in message
+        .char_indices()
+        .chain(std::iter::once((message.len(), ' ')))
+    {
+        match (c.is_ascii_hexdigit(), start) {
+            (true, None) => start = Some(index),
+            (false, Some(run_start)) => {
+                if index - run_start >= MIN_REDACTED_LENGTH {
+                    redacted.push_str(&message[last..run_start]);
+                    redacted.push_str(REDACTED);
+                    last = index;
+                }
+                start = None;
+            }
+            _ => (),
+        }
+    }
+
+    if last == 0 {
+        Cow::Borrowed(message)
+    } else {
+        redacted.push_str(&message[last..]);
+
+        Cow::Owned(redacted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::Config,
+        logging::{self, LogTarget, Logger, Settings},
+    };
+    use anyhow::Result;
+    use log::{Level, LevelFilter, Log, Record};
+    use std::fs;
+
+    #[test]
+    fn target() -> Result<()> {
+        for name in LogTarget::NAMES {
+            assert_eq!(name.parse::<LogTarget>()?.to_string(), *name);
+        }
+        assert!("console".parse::<LogTarget>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn redact() {
+        let id = "0123456789abcdef0123456789abcdef";
+        assert_eq!(
+            logging::redact(&format!("Device \"{}\" registered", id)),
+            "Device \"[redacted]\" registered"
+        );
+        assert_eq!(logging::redact(id), "[redacted]");
+        // Short hexadecimal values like ports and sizes are kept
+        assert_eq!(
+            logging::redact("Listening on 127.0.0.1:52477"),
+            "Listening on 127.0.0.1:52477"
+        );
+        assert_eq!(
+            logging::redact("Wrote breach index with 1234 hashes"),
+            "Wrote breach index with 1234 hashes"
+        );
+    }
+
+    #[test]
+    fn file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("keybear.log");
+        let logger = Logger::new(&Settings {
+            target: LogTarget::File,
+            level: LevelFilter::Info,
+            path: path.clone(),
+        })?;
+
+        let id = "0123456789abcdef0123456789abcdef";
+        for level in &[Level::Info, Level::Debug] {
+            logger.log(
+                &Record::builder()
+                    .level(*level)
+                    .target("lib::device")
+                    .args(format_args!("Generating nonce for device \"{}\"", id))
+                    .build(),
+            );
+        }
+        logger.flush();
+
+        let contents = fs::read_to_string(&path)?;
+        let lines = contents.lines().collect::<Vec<_>>();
+        // The debug message is above the level
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("time="));
+        assert!(lines[0].contains(" level=info target=lib::device "));
+        assert!(lines[0].ends_with(" message=\"Generating nonce for device \\\"[redacted]\\\"\""));
+
+        Ok(())
+    }
+
+    #[test]
+    fn debug_not_redacted() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("keybear.log");
+        let logger = Logger::new(&Settings {
+            target: LogTarget::File,
+            level: LevelFilter::Debug,
+            path: path.clone(),
+        })?;
+
+        let id = "0123456789abcdef0123456789abcdef";
+        logger.log(
+            &Record::builder()
+                .level(Level::Debug)
+                .args(format_args!("Generating nonce for device \"{}\"", id))
+                .build(),
+        );
+
+        assert!(fs::read_to_string(&path)?.contains(id));
+
+        Ok(())
+    }
+
+    #[test]
+    fn journald_entry() {
+        let fields = vec![
+            ("time", "now".to_string()),
+            ("level", "warn".to_string()),
+            ("target", "lib".to_string()),
+            ("line", "42".to_string()),
+            ("message", "first\nsecond".to_string()),
+        ];
+        let entry = logging::journald_entry(Level::Warn, &fields);
+
+        let mut expected =
+            b"PRIORITY=4\nSYSLOG_IDENTIFIER=keybear\nTARGET=lib\nCODE_LINE=42\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&12u64.to_le_bytes());
+        expected.extend_from_slice(b"first\nsecond\n");
+        assert_eq!(entry, expected);
+    }
+
+    #[test]
+    fn settings() -> Result<()> {
+        let settings = Settings::from_config(&Config::from_raw_str(
+            r#"
+            [log]
+            target = "file"
+            level = "debug"
+            path = "keybear.log"
+            "#,
+        )?);
+        assert_eq!(settings.target, LogTarget::File);
+        assert_eq!(settings.level, LevelFilter::Debug);
+        assert_eq!(settings.path, std::path::Path::new("keybear.log"));
+
+        Ok(())
+    }
+}
@@ -1,18 +1,32 @@
 #![forbid(unsafe_code)]
 
-use anyhow::Result;
-use clap::clap_app;
-use lib::config::Config;
-use log::{error, LevelFilter};
-use std::fs;
-use syslog::Facility;
+use anyhow::{bail, Result};
+use clap::{clap_app, ArgMatches};
+use keybear_core::crypto::StaticSecretExt;
+use lib::{
+    admin,
+    app::AppState,
+    backup::Backup,
+    breach,
+    config::{self, Config, DEFAULT_CONFIG_FILE_PATH},
+    device::bootstrap,
+    export::ExportFormat,
+    health,
+    import::{self, ImportFormat},
+    logging::{self, LogTarget},
+    pair::PairingUri,
+    tor::client_auth::{self, ClientAuthKey, PAIRING_CLIENT_NAME},
+};
+use log::{error, info, warn};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+use x25519_dalek::{PublicKey, StaticSecret};
 
 #[actix_web::main]
 async fn main() -> Result<()> {
-    // Setup logging to syslog
-    syslog::init(Facility::LOG_USER, LevelFilter::Debug, None)
-        .expect("Setting up system log failed");
-
     // Function to check if a file arg exists
     let file_exists = |path: &str| {
         if fs::metadata(path).is_ok() {
@@ -21,22 +35,519 @@ async fn main() -> Result<()> {
             Err(String::from("File doesn't exist"))
         }
     };
+    // Function to check if an override arg is in the form key=value
+    let is_override = |assignment: &str| {
+        config::parse_override(assignment)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    };
     // Parse the command line arguments
     let matches = clap_app!(keybear =>
         (version: clap::crate_version!())
         (author: clap::crate_authors!())
         (about: clap::crate_description!())
         (@arg CONFIG: -c --config +takes_value {file_exists} "Sets a custom config file")
+        (@arg SET: -s --set +takes_value +multiple_occurrences number_of_values(1) {is_override} "Overrides a setting of the config file, like server.port=52477")
+        (@arg LOG_TARGET: --("log-target") +takes_value possible_values(LogTarget::NAMES) "Where to write the log messages to")
+        (@arg LOG_LEVEL: --("log-level") +takes_value possible_values(&["off", "error", "warn", "info", "debug", "trace"]) "The most verbose log messages to write")
+        (@arg LOG_FILE: --("log-file") +takes_value "The file to write the log messages to, implies the file target")
+        (@subcommand import =>
+            (about: "Imports passwords exported from another password manager")
+            (@arg FORMAT: +required possible_values(ImportFormat::NAMES) "The format of the export")
+            (@arg FILE: +required {file_exists} "The exported file, or the directory for pass")
+            (@arg DRY_RUN: --("dry-run") "Only shows what would be imported")
+        )
+        (@subcommand export =>
+            (about: "Exports all passwords and items for another password manager")
+            (@arg FORMAT: +required possible_values(ExportFormat::NAMES) "The format of the export")
+            (@arg FILE: +required "The file to write the export to")
+        )
+        (@subcommand backup =>
+            (about: "Writes an encrypted backup of all data")
+            (@arg FILE: +required "The file to write the backup to")
+        )
+        (@subcommand restore =>
+            (about: "Replaces all data with an encrypted backup, the server must be stopped")
+            (@arg FILE: +required {file_exists} "The backup file")
+            (@arg WITH_CONFIG: --("with-config") "Also overwrites the configuration file with the one in the backup")
+        )
+        (@subcommand devices =>
+            (about: "Manages the devices that can access the server")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand list =>
+                (about: "Lists the registered devices and the devices awaiting verification")
+            )
+            (@subcommand pending =>
+                (about: "Lists the devices awaiting verification with their verification codes")
+            )
+            (@subcommand approve =>
+                (about: "Registers a device that is awaiting verification")
+                (@arg ID: +required "The identifier of the device")
+                (@arg CODE: "The verification code shown on the device, asks for confirmation when omitted")
+            )
+            (@subcommand reject =>
+                (about: "Removes a device that is awaiting verification without registering it")
+                (@arg ID: +required "The identifier of the device")
+            )
+            (@subcommand revoke =>
+                (about: "Removes a registered device")
+                (@arg ID: +required "The identifier of the device")
+            )
+            (@subcommand rename =>
+                (about: "Changes the name of a registered device")
+                (@arg ID: +required "The identifier of the device")
+                (@arg NAME: +required "The new name")
+            )
+        )
+        (@subcommand passwords =>
+            (about: "Manages the stored passwords")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand list =>
+                (about: "Lists all passwords without showing the actual passwords")
+            )
+            (@subcommand show =>
+                (about: "Shows a single password including the actual password")
+                (@arg ID: +required "The identifier of the password")
+            )
+            (@subcommand delete =>
+                (about: "Removes a password")
+                (@arg ID: +required "The identifier of the password")
+            )
+        )
+        (@subcommand breach =>
+            (about: "Manages the dataset of passwords occurring in known data breaches")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand ("build-index") =>
+                (about: "Builds a compact index from a directory of Have I Been Pwned range files")
+                (@arg RANGES: +required {file_exists} "The directory of range files")
+                (@arg OUTPUT: +required "The file to write the index to, configure it as the dataset")
+            )
+        )
+        (@subcommand ("bootstrap-token") =>
+            (about: "Shows the token the first device must register with")
+        )
+        (@subcommand pair =>
+            (about: "Shows the address and key of the server as a QR code to pair a new device")
+            (@arg TOKEN: --token +takes_value "The bootstrap token to include, read from the database when omitted")
+        )
+        (@subcommand ("rotate-key") =>
+            (about: "Generates a new secret key, all devices have to register again")
+        )
+        (@subcommand config =>
+            (about: "Inspects the configuration")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand show =>
+                (about: "Shows the configuration with all defaults and overrides applied")
+            )
+        )
+        (@subcommand ("check-config") =>
+            (about: "Checks whether the configuration is valid")
+        )
+        (@subcommand healthcheck =>
+            (about: "Checks whether the running server is healthy")
+        )
     )
     .get_matches();
 
-    // Load the config TOML file
-    let config = match matches.value_of("CONFIG") {
-        // If a file is passed as an argument use that
-        Some(config_path) => Config::from_file(config_path),
-        // Otherwise try to get the default file location
-        None => Config::from_default_file_or_empty(),
-    }?;
+    // Checking the configuration reports the unknown keys together with the other problems
+    let loaded = load_config(&matches, matches.subcommand_name() == Some("check-config"))?;
+    let config = loaded.config.clone();
+
+    // Setup logging, the command line arguments take precedence over the config
+    let mut log_settings = logging::Settings::from_config(&config);
+    if let Some(target) = matches.value_of("LOG_TARGET") {
+        log_settings.target = target.parse()?;
+    }
+    if let Some(level) = matches.value_of("LOG_LEVEL") {
+        log_settings.level = level.parse()?;
+    }
+    if let Some(path) = matches.value_of("LOG_FILE") {
+        log_settings.target = LogTarget::File;
+        log_settings.path = path.into();
+    }
+    logging::init(&log_settings)?;
+    loaded.log();
+
+    match matches.subcommand() {
+        Some(("import", matches)) => {
+            // Both arguments are required so they can't be empty
+            let format: ImportFormat = matches.value_of("FORMAT").unwrap().parse()?;
+            let path = matches.value_of("FILE").unwrap();
+
+            let master_password = if format.needs_master_password() {
+                Some(rpassword::prompt_password("Master password: ")?)
+            } else {
+                None
+            };
+
+            let state = AppState::from_config(&config)?;
+            let report = import::import(
+                &state,
+                format.parse_path(path, master_password.as_deref())?,
+                matches.is_present("DRY_RUN"),
+            )
+            .await?;
+            print!("{}", report);
+
+            return Ok(());
+        }
+        Some(("export", matches)) => {
+            // Both arguments are required so they can't be empty
+            let format: ExportFormat = matches.value_of("FORMAT").unwrap().parse()?;
+            let file = matches.value_of("FILE").unwrap();
+
+            let master_password = if format.needs_master_password() {
+                let master_password = rpassword::prompt_password("Master password: ")?;
+                if master_password != rpassword::prompt_password("Repeat master password: ")? {
+                    bail!("Master passwords don't match");
+                }
+
+                Some(master_password)
+            } else {
+                eprintln!("Warning: the passwords are exported without encryption");
+
+                None
+            };
+
+            let state = AppState::from_config(&config)?;
+            let passwords = state.passwords().await?;
+            let items = state.items().await?;
+            fs::write(
+                file,
+                format.export(&passwords, &items, master_password.as_deref())?,
+            )?;
+
+            if format.supports_items() {
+                println!(
+                    "Exported {} passwords and {} items to {}",
+                    passwords.iter().count(),
+                    items.iter().count(),
+                    file
+                );
+            } else {
+                println!(
+                    "Exported {} passwords to {}",
+                    passwords.iter().count(),
+                    file
+                );
+                if items.iter().next().is_some() {
+                    eprintln!(
+                        "Warning: {} items aren't exported, this is only supported by kdbx",
+                        items.iter().count()
+                    );
+                }
+            }
+
+            return Ok(());
+        }
+        Some(("backup", matches)) => {
+            // The argument is required so it can't be empty
+            let file = matches.value_of("FILE").unwrap();
+
+            let passphrase = rpassword::prompt_password("Backup passphrase: ")?;
+            if passphrase != rpassword::prompt_password("Repeat backup passphrase: ")? {
+                bail!("Passphrases don't match");
+            }
+
+            let state = AppState::from_config(&config)?;
+            fs::write(file, Backup::collect(&state).await?.encrypt(&passphrase)?)?;
+            println!("Wrote backup to {}", file);
+
+            return Ok(());
+        }
+        Some(("restore", restore_matches)) => {
+            // The argument is required so it can't be empty
+            let file = restore_matches.value_of("FILE").unwrap();
+
+            let passphrase = rpassword::prompt_password("Backup passphrase: ")?;
+            let backup = Backup::decrypt(&fs::read(file)?, &passphrase)?;
+            backup.validate()?;
+
+            let config = if restore_matches.is_present("WITH_CONFIG") {
+                let config_path = matches
+                    .value_of("CONFIG")
+                    .unwrap_or(DEFAULT_CONFIG_FILE_PATH);
+                fs::write(config_path, toml::to_string(backup.settings())?)?;
+                println!("Restored configuration to {}", config_path);
+                if !backup.contains_secrets() {
+                    eprintln!("Warning: the backup doesn't contain the Tor control password, add it to the configuration when it's used");
+                }
+
+                // Restore the data to the paths of the restored configuration
+                let loaded = load_config(&matches, false)?;
+                loaded.log();
+
+                loaded.config
+            } else {
+                config
+            };
+            if !backup.contains_secrets() {
+                eprintln!(
+                    "Warning: the backup doesn't contain the secret key, the current key is kept"
+                );
+            }
+
+            let state = AppState::from_config(&config)?;
+            let created = backup.created;
+            backup.restore(&state).await?;
+            println!("Restored backup created at UNIX time {}", created);
+
+            return Ok(());
+        }
+        Some(("devices", matches)) => {
+            let state = AppState::from_config(&config)?;
+
+            match matches.subcommand() {
+                Some(("list", _)) => {
+                    for device in state.devices().await?.iter() {
+                        println!("{}\t{}", device.id(), device.name());
+                    }
+                    for device in state
+                        .verification_devices()
+                        .await?
+                        .to_needs_verification_vec()
+                    {
+                        println!(
+                            "{}\t{}\t(awaiting verification)",
+                            device.id(),
+                            device.name()
+                        );
+                    }
+                }
+                Some(("pending", _)) => {
+                    for device in admin::pending_devices(&state).await? {
+                        println!(
+                            "{}\t{}\t{}",
+                            device.id(),
+                            device.name(),
+                            device.verification_code()
+                        );
+                    }
+                }
+                Some(("approve", matches)) => {
+                    // The argument is required so it can't be empty
+                    let id = matches.value_of("ID").unwrap();
+                    let verification_code = matches.value_of("CODE");
+
+                    // Let the user compare the codes when none is passed
+                    if verification_code.is_none() {
+                        let pending = admin::pending_devices(&state).await?;
+                        let device = match pending.iter().find(|device| device.id() == id) {
+                            Some(device) => device,
+                            None => bail!("Device with ID \"{}\" is not awaiting verification", id),
+                        };
+
+                        println!(
+                            "Device \"{}\" has verification code \"{}\"",
+                            device.name(),
+                            device.verification_code()
+                        );
+                        print!("Does the device show the same code? [y/N] ");
+                        io::stdout().flush()?;
+
+                        let mut answer = String::new();
+                        io::stdin().read_line(&mut answer)?;
+                        if !answer.trim().eq_ignore_ascii_case("y") {
+                            bail!("Device is not approved");
+                        }
+                    }
+
+                    let device = admin::approve_device(&state, id, verification_code).await?;
+                    println!("Registered device \"{}\"", device.name());
+                }
+                Some(("reject", matches)) => {
+                    let device =
+                        admin::reject_device(&state, matches.value_of("ID").unwrap()).await?;
+                    println!("Rejected device \"{}\"", device.name());
+                }
+                Some(("revoke", matches)) => {
+                    let device =
+                        admin::revoke_device(&state, matches.value_of("ID").unwrap()).await?;
+                    println!("Removed device \"{}\"", device.name());
+                }
+                Some(("rename", matches)) => {
+                    let device = admin::rename_device(
+                        &state,
+                        matches.value_of("ID").unwrap(),
+                        matches.value_of("NAME").unwrap(),
+                    )
+                    .await?;
+                    println!("Renamed device to \"{}\"", device.name());
+                }
+                // A subcommand is required
+                _ => unreachable!(),
+            }
+
+            return Ok(());
+        }
+        Some(("passwords", matches)) => {
+            let state = AppState::from_config(&config)?;
+
+            match matches.subcommand() {
+                Some(("list", _)) => {
+                    for password in state.passwords().await?.iter() {
+                        println!(
+                            "{}\t{}\t{}\t{}",
+                            password.id,
+                            password.name,
+                            password.login().unwrap_or(""),
+                            password.website.as_deref().unwrap_or("")
+                        );
+                    }
+                }
+                Some(("show", matches)) => {
+                    // The argument is required so it can't be empty
+                    let id = matches.value_of("ID").unwrap();
+                    let passwords = state.passwords().await?;
+                    let password = match passwords.by_id(id) {
+                        Some(password) => password,
+                        None => bail!("Password with ID \"{}\" does not exist", id),
+                    };
+
+                    println!("Name: {}", password.name);
+                    println!("Password: {}", password.password);
+                    for (field, value) in &[
+                        ("Username", &password.username),
+                        ("E-mail", &password.email),
+                        ("Website", &password.website),
+                        ("Folder", &password.folder),
+                        ("TOTP secret", &password.totp),
+                        ("Notes", &password.notes),
+                    ] {
+                        if let Some(value) = value {
+                            println!("{}: {}", field, value);
+                        }
+                    }
+                }
+                Some(("delete", matches)) => {
+                    let password =
+                        admin::delete_password(&state, matches.value_of("ID").unwrap()).await?;
+                    println!("Removed password \"{}\"", password.name);
+                }
+                // A subcommand is required
+                _ => unreachable!(),
+            }
+
+            return Ok(());
+        }
+        Some(("breach", matches)) => {
+            match matches.subcommand() {
+                Some(("build-index", matches)) => {
+                    // Both arguments are required so they can't be empty
+                    let count = breach::build_index(
+                        matches.value_of("RANGES").unwrap(),
+                        matches.value_of("OUTPUT").unwrap(),
+                    )?;
+                    println!("Wrote breach index with {} hashes", count);
+                }
+                // A subcommand is required
+                _ => unreachable!(),
+            }
+
+            return Ok(());
+        }
+        Some(("bootstrap-token", _)) => {
+            if !config.registration_open() {
+                bail!("Registration of new devices is closed in the configuration");
+            }
+
+            let state = AppState::from_config(&config)?;
+            match bootstrap::ensure_token(&state).await? {
+                Some(token) => println!("{}", token),
+                None => bail!("A device is already registered, new devices must be verified"),
+            }
+
+            return Ok(());
+        }
+        Some(("pair", matches)) => {
+            let public_key = PublicKey::from(&StaticSecret::from_file(config.key_path())?);
+
+            // Only the first device needs the token
+            let bootstrap_token = match matches.value_of("TOKEN") {
+                Some(token) => Some(token.to_string()),
+                None if config.registration_open() => match AppState::from_config(&config) {
+                    Ok(state) => bootstrap::ensure_token(&state).await?,
+                    Err(err) => {
+                        eprintln!(
+                            "Warning: can't read the bootstrap token while the server is running, pass it with --token: {}",
+                            err
+                        );
+
+                        None
+                    }
+                },
+                None => None,
+            };
+
+            let mut uri = PairingUri::from_config(&config, &public_key, bootstrap_token)?;
+
+            // Allow the new device to connect to the onion service until it has its own key
+            if config.tor_client_authorization() {
+                let key = ClientAuthKey::generate();
+                client_auth::authorize(&config, PAIRING_CLIENT_NAME, &key)?;
+                uri.client_auth_key = Some(key.private_key());
+
+                eprintln!("The pairing key is only used by Tor after it's reloaded, or after keybear is restarted when using the control port");
+            }
+
+            println!("{}", uri.to_qr_code()?);
+            println!("{}", uri);
+
+            return Ok(());
+        }
+        Some(("rotate-key", _)) => {
+            let state = AppState::from_config(&config)?;
+            let removed = admin::rotate_key(&state).await?;
+            println!(
+                "Generated a new secret key, {} devices have to register again",
+                removed
+            );
+
+            return Ok(());
+        }
+        Some(("config", matches)) => {
+            match matches.subcommand() {
+                Some(("show", _)) => print!("{}", toml::to_string(&config.effective())?),
+                // A subcommand is required
+                _ => unreachable!(),
+            }
+
+            return Ok(());
+        }
+        Some(("check-config", _)) => {
+            let problems = loaded
+                .unknown_keys
+                .iter()
+                .map(|key| format!("Unknown configuration key {}", key))
+                .chain(admin::check_config(&config))
+                .collect::<Vec<_>>();
+            if !problems.is_empty() {
+                for problem in &problems {
+                    eprintln!("{}", problem);
+                }
+                bail!("Configuration has {} problems", problems.len());
+            }
+            println!("Configuration is valid");
+
+            return Ok(());
+        }
+        Some(("healthcheck", _)) => {
+            let health = health::request(&config)?;
+            if !health.storage {
+                eprintln!("Database can't be read");
+            }
+            if !health.key {
+                eprintln!("Secret key differs from the key on disk, restart the server");
+            }
+            if !health.is_healthy() {
+                bail!("Server is unhealthy");
+            }
+            println!("Server is healthy");
+
+            return Ok(());
+        }
+        _ => (),
+    }
 
     // Run the application
     lib::run(config).await.map_err(|err| {
@@ -45,3 +556,70 @@ async fn main() -> Result<()> {
         err
     })
 }
+
+/// The configuration with what's found while loading it.
+///
+/// Nothing can be logged while loading since the configuration sets up the logging, so it's logged
+/// afterwards.
+struct LoadedConfig {
+    /// The configuration with the overrides applied.
+    config: Config,
+    /// The configuration file read, `None` when the defaults are used.
+    file: Option<PathBuf>,
+    /// The keys in the configuration file that don't match a setting.
+    unknown_keys: Vec<String>,
+    /// The environment variables with the prefix that don't match a setting.
+    ignored_env_vars: Vec<String>,
+}
+
+impl LoadedConfig {
+    /// Log what's found while loading the configuration.
+    fn log(&self) {
+        if let Some(file) = &self.file {
+            info!("Loaded configuration file {:?}", file);
+        }
+        for name in &self.ignored_env_vars {
+            warn!(
+                "Ignoring environment variable {}, it doesn't match a setting",
+                name
+            );
+        }
+    }
+}
+
+/// Load the configuration file with the overrides applied.
+///
+/// Unknown keys in the file are rejected unless they are allowed, then they are returned.
+fn load_config(matches: &ArgMatches, allow_unknown_keys: bool) -> Result<LoadedConfig> {
+    let file = match matches.value_of("CONFIG") {
+        // If a file is passed as an argument use that
+        Some(config_path) => Some(PathBuf::from(config_path)),
+        // Otherwise try to get the default file location
+        None => Some(PathBuf::from(DEFAULT_CONFIG_FILE_PATH)).filter(|path| path.exists()),
+    };
+
+    // Load the config TOML file
+    let (config, unknown_keys) = match &file {
+        Some(file) if allow_unknown_keys => Config::from_file_with_unknown_keys(file)?,
+        Some(file) => (Config::from_file(file)?, Vec::new()),
+        None => (Config::default(), Vec::new()),
+    };
+
+    // The environment variables override the file and the command line overrides both
+    let (config, ignored_env_vars) = config.with_env_overrides(env::vars())?;
+    let config = config.with_overrides(
+        matches
+            .values_of("SET")
+            .into_iter()
+            .flatten()
+            .map(config::parse_override)
+            .collect::<Result<Vec<_>>>()?,
+    )?;
+
+    Ok(LoadedConfig {
+        config,
+        file,
+        unknown_keys,
+        ignored_env_vars,
+    })
+}
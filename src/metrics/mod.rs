@@ -0,0 +1,388 @@
+use crate::app::AppState;
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    web::Data,
+    Error, HttpResponse,
+};
+use futures::future::{self, FutureExt, LocalBoxFuture, Ready};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// Upper bounds in seconds of the request duration histogram buckets.
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Route label of requests that didn't match any route.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Request durations of a single route.
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Amount of requests in every bucket of `DURATION_BUCKETS`, not cumulative.
+    buckets: [u64; DURATION_BUCKETS.len()],
+    /// Total amount of requests.
+    count: u64,
+    /// Total duration of all requests in seconds.
+    sum: f64,
+}
+
+impl Histogram {
+    /// Add the duration of a request.
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(index) = DURATION_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[index] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// Counters of everything happening in the server.
+///
+/// Only route patterns are recorded, never IDs or other values from the requests.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Amount of requests by method, route pattern and status code.
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    /// Request durations by method and route pattern.
+    durations: Mutex<BTreeMap<(String, String), Histogram>>,
+    /// Amount of encrypted requests that couldn't be decrypted.
+    decrypt_failures: AtomicU64,
+    /// Amount of nonces handed out to devices.
+    nonces_issued: AtomicU64,
+}
+
+impl Metrics {
+    /// Count a handled request.
+    pub fn record_request(&self, method: &str, route: &str, status: u16, duration: Duration) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((method.to_string(), route.to_string(), status))
+            .or_insert(0) += 1;
+
+        self.durations
+            .lock()
+            .unwrap()
+            .entry((method.to_string(), route.to_string()))
+            .or_default()
+            .observe(duration);
+    }
+
+    /// Count an encrypted request that couldn't be decrypted.
+    pub fn record_decrypt_failure(&self) {
+        self.decrypt_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a nonce handed out to a device.
+    pub fn record_nonce_issued(&self) {
+        self.nonces_issued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Amount of encrypted requests that couldn't be decrypted.
+    pub fn decrypt_failures(&self) -> u64 {
+        self.decrypt_failures.load(Ordering::Relaxed)
+    }
+
+    /// Amount of nonces handed out to devices.
+    pub fn nonces_issued(&self) -> u64 {
+        self.nonces_issued.load(Ordering::Relaxed)
+    }
+
+    /// Render the counters in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+
+        header(
+            &mut output,
+            "keybear_http_requests_total",
+            "counter",
+            "Handled HTTP requests.",
+        );
+        for ((method, route, status), count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                output,
+                "keybear_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                escape(method),
+                escape(route),
+                status,
+                count
+            );
+        }
+
+        header(
+            &mut output,
+            "keybear_http_request_duration_seconds",
+            "histogram",
+            "Time it took to handle HTTP requests.",
+        );
+        for ((method, route), histogram) in self.durations.lock().unwrap().iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", escape(method), escape(route));
+
+            let mut cumulative = 0;
+            for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative += count;
+                let _ = writeln!(
+                    output,
+                    "keybear_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                output,
+                "keybear_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            );
+            let _ = writeln!(
+                output,
+                "keybear_http_request_duration_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            );
+            let _ = writeln!(
+                output,
+                "keybear_http_request_duration_seconds_count{{{}}} {}",
+                labels, histogram.count
+            );
+        }
+
+        sample(
+            &mut output,
+            "keybear_decrypt_failures_total",
+            "counter",
+            "Encrypted requests that couldn't be decrypted.",
+            self.decrypt_failures(),
+        );
+        sample(
+            &mut output,
+            "keybear_nonces_issued_total",
+            "counter",
+            "Nonces handed out to devices.",
+            self.nonces_issued(),
+        );
+
+        output
+    }
+}
+
+/// Render all metrics of the server, including the current size of the stored data.
+pub async fn gather(state: &AppState) -> String {
+    let mut output = state.metrics.render();
+
+    if let Ok(devices) = state.devices().await {
+        sample(
+            &mut output,
+            "keybear_devices",
+            "gauge",
+            "Registered devices.",
+            devices.iter().count() as u64,
+        );
+    }
+    if let Ok(verification_devices) = state.verification_devices().await {
+        sample(
+            &mut output,
+            "keybear_pending_verifications",
+            "gauge",
+            "Devices awaiting verification.",
+            verification_devices.to_needs_verification_vec().len() as u64,
+        );
+    }
+    if let Some(Ok(size)) = state
+        .database
+        .as_ref()
+        .map(|database| database.size_on_disk())
+    {
+        sample(
+            &mut output,
+            "keybear_storage_size_bytes",
+            "gauge",
+            "Size of the database on disk.",
+            size,
+        );
+    }
+
+    output
+}
+
+/// The metrics endpoint.
+pub async fn metrics(state: Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(gather(&state).await)
+}
+
+/// Write the help and type lines of a metric.
+fn header(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+}
+
+/// Write a metric with a single value.
+fn sample(output: &mut String, name: &str, kind: &str, help: &str, value: u64) {
+    header(output, name, kind, help);
+    let _ = writeln!(output, "{} {}", name, value);
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Actix middleware counting the requests and their durations per route.
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S> for RequestMetrics
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestMetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(RequestMetricsMiddleware { service })
+    }
+}
+
+/// The service created by the [`RequestMetrics`] middleware.
+pub struct RequestMetricsMiddleware<S> {
+    /// The wrapped service.
+    service: S,
+}
+
+impl<S, B> Service for RequestMetricsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().to_string();
+        // Use the pattern so no IDs end up in the metrics
+        let route = req
+            .match_pattern()
+            // The scope of the routes adds an extra slash in front of the pattern
+            .map(|pattern| pattern.replacen("//", "/", 1))
+            .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+        let state = req.app_data::<Data<AppState>>().cloned();
+
+        self.service
+            .call(req)
+            .map(move |result| {
+                if let Some(state) = state {
+                    let status = match &result {
+                        Ok(response) => response.status(),
+                        Err(err) => err.as_response_error().status_code(),
+                    };
+                    state
+                        .metrics
+                        .record_request(&method, &route, status.as_u16(), start.elapsed());
+                }
+
+                result
+            })
+            .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{app, metrics::Metrics, net::ListenerPolicy, route::v1, test};
+    use actix_web::{test::TestRequest, App};
+    use std::time::Duration;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::default();
+        metrics.record_request("GET", "/v1/passwords/{id}", 200, Duration::from_millis(20));
+        metrics.record_request("GET", "/v1/passwords/{id}", 404, Duration::from_secs(20));
+        metrics.record_decrypt_failure();
+        metrics.record_nonce_issued();
+        metrics.record_nonce_issued();
+
+        let output = metrics.render();
+        assert!(output.contains(
+            "keybear_http_requests_total{method=\"GET\",route=\"/v1/passwords/{id}\",status=\"200\"} 1"
+        ));
+        assert!(output.contains(
+            "keybear_http_request_duration_seconds_bucket{method=\"GET\",route=\"/v1/passwords/{id}\",le=\"0.01\"} 0"
+        ));
+        assert!(output.contains(
+            "keybear_http_request_duration_seconds_bucket{method=\"GET\",route=\"/v1/passwords/{id}\",le=\"0.025\"} 1"
+        ));
+        // Requests slower than the last bucket are only counted in the infinite one
+        assert!(output.contains(
+            "keybear_http_request_duration_seconds_bucket{method=\"GET\",route=\"/v1/passwords/{id}\",le=\"10\"} 1"
+        ));
+        assert!(output.contains(
+            "keybear_http_request_duration_seconds_bucket{method=\"GET\",route=\"/v1/passwords/{id}\",le=\"+Inf\"} 2"
+        ));
+        assert!(output.contains("keybear_decrypt_failures_total 1"));
+        assert!(output.contains("keybear_nonces_issued_total 2"));
+    }
+
+    #[actix_rt::test]
+    async fn gather() {
+        let state = test::app_state();
+        let output = super::gather(&state).await;
+
+        assert!(output.contains("keybear_devices 0"));
+        assert!(output.contains("keybear_pending_verifications 0"));
+        // The test state is kept in memory
+        assert!(!output.contains("keybear_storage_size_bytes"));
+    }
+
+    #[actix_rt::test]
+    async fn middleware() {
+        let state = test::app_state();
+        let mut app = actix_web::test::init_service(app::fill_app(
+            App::new(),
+            &state,
+            ListenerPolicy::tor(&state.config),
+        ))
+        .await;
+
+        let request = TestRequest::get()
+            .uri(&format!("{}/secret-id", v1::PASSWORD))
+            .peer_addr("127.0.0.1:1234".parse().unwrap())
+            .to_request();
+        actix_web::test::call_service(&mut app, request).await;
+
+        let output = state.metrics.render();
+        assert!(output.contains(&format!(
+            "keybear_http_requests_total{{method=\"GET\",route=\"{}/{{id}}\",status=\"404\"}} 1",
+            v1::PASSWORD
+        )));
+        // The ID of the password isn't leaked
+        assert!(!output.contains("secret-id"));
+    }
+}
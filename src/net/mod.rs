@@ -1,52 +1,267 @@
-use actix_web::{dev::RequestHead, guard::Guard};
-use std::net::{IpAddr, Ipv4Addr};
+pub mod proxy;
+pub mod rate_limit;
 
-/// Actix guard to ensure that the only requests we receive are Tor requests.
-pub struct TorGuard;
+use crate::config::Config;
+use actix_web::{dev::RequestHead, guard::Guard, middleware::Logger};
+use anyhow::{anyhow, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::TryFrom,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+};
 
-impl Guard for TorGuard {
+/// Prefix of a listen address for a unix domain socket, the same as Tor uses.
+pub const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+/// An address the server listens on for connections of the Tor hidden service.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ListenAddress {
+    /// A TCP socket, like `127.0.0.1:52477` or `[::1]:52477`.
+    Tcp(SocketAddr),
+    /// A unix domain socket, like `unix:/run/keybear/keybear.sock`.
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = Error;
+
+    fn from_str(address: &str) -> Result<Self> {
+        if let Some(path) = address.strip_prefix(UNIX_SOCKET_PREFIX) {
+            if path.is_empty() {
+                return Err(anyhow!("Unix socket listen address is missing a path"));
+            }
+
+            Ok(Self::Unix(PathBuf::from(path)))
+        } else {
+            address
+                .parse()
+                .map(Self::Tcp)
+                .map_err(|err| anyhow!("Listen address \"{}\" is invalid: {}", address, err))
+        }
+    }
+}
+
+impl TryFrom<String> for ListenAddress {
+    type Error = Error;
+
+    fn try_from(address: String) -> Result<Self> {
+        address.parse()
+    }
+}
+
+impl From<ListenAddress> for String {
+    fn from(address: ListenAddress) -> Self {
+        address.to_string()
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "{}{}", UNIX_SOCKET_PREFIX, path.display()),
+        }
+    }
+}
+
+/// The sources the Tor hidden service is trusted to connect from.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TrustedSources {
+    /// IP addresses Tor connects from.
+    ips: Vec<IpAddr>,
+    /// Whether Tor connects through a unix domain socket.
+    unix_socket: bool,
+}
+
+impl TrustedSources {
+    /// Trust the IP addresses and optionally the unix domain socket listeners.
+    pub fn new(ips: Vec<IpAddr>, unix_socket: bool) -> Self {
+        Self { ips, unix_socket }
+    }
+
+    /// Trust the sources from the config.
+    ///
+    /// When no sources are configured, Tor is trusted to connect from the same loopback address as
+    /// one of the addresses the server listens on.
+    pub fn from_config(config: &Config) -> Self {
+        let listen_addresses = config.listen_addresses();
+
+        let ips = match config.trusted_sources() {
+            Some(ips) => ips.to_vec(),
+            None => listen_addresses
+                .iter()
+                .filter_map(|address| match address {
+                    ListenAddress::Tcp(addr) if addr.ip().is_loopback() => Some(addr.ip()),
+                    _ => None,
+                })
+                .collect(),
+        };
+        let unix_socket = listen_addresses
+            .iter()
+            .any(|address| matches!(address, ListenAddress::Unix(_)));
+
+        Self::new(ips, unix_socket)
+    }
+
+    /// Check if the client trying to connect is trusted.
+    ///
+    /// Connections over a unix domain socket don't have a peer address.
+    pub fn is_trusted(&self, peer_addr: Option<SocketAddr>) -> bool {
+        match peer_addr {
+            Some(addr) => self.ips.contains(&addr.ip()),
+            None => self.unix_socket,
+        }
+    }
+}
+
+/// Actix guard deciding which clients are allowed on a listener.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ListenerPolicy {
+    /// Only the Tor hidden service connecting from one of the trusted sources.
+    Tor(TrustedSources),
+    /// Only Tor circuits from the PROXY protocol header.
+    ///
+    /// The trusted sources are already checked when accepting the connection.
+    TorCircuit,
+    /// Clients on the local network, the listener is secured with TLS.
+    Lan,
+}
+
+impl ListenerPolicy {
+    /// Only trust the Tor hidden service as the server is configured.
+    pub fn tor(config: &Config) -> Self {
+        if config.proxy_protocol() {
+            Self::TorCircuit
+        } else {
+            Self::Tor(TrustedSources::from_config(config))
+        }
+    }
+}
+
+impl Guard for ListenerPolicy {
     fn check(&self, req: &RequestHead) -> bool {
-        match req.peer_addr {
-            Some(addr) => is_valid_client_ip(addr.ip()),
-            None => false,
+        match self {
+            Self::Tor(trusted_sources) => trusted_sources.is_trusted(req.peer_addr),
+            Self::TorCircuit => req
+                .peer_addr
+                .map(|addr| proxy::circuit_id(&addr).is_some())
+                .unwrap_or(false),
+            Self::Lan => req
+                .peer_addr
+                .map(|addr| is_local_network_ip(addr.ip()))
+                .unwrap_or(false),
         }
     }
 }
 
-/// Check if the client trying to connect is valid.
-///
-/// The client is only allowed to be the Tor hidden service.
-pub fn is_valid_client_ip(ip: IpAddr) -> bool {
-    if let IpAddr::V4(ipv4) = ip {
-        ipv4 == Ipv4Addr::LOCALHOST
-    } else {
-        false
+/// The request logger, including the Tor circuit the request came through when it's known.
+pub fn logger() -> Logger {
+    Logger::new(r#"%a %{circuit}xi "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#)
+        .custom_request_replace("circuit", |req| {
+            req.peer_addr()
+                .and_then(|addr| proxy::circuit_id(&addr))
+                .map(|circuit_id| format!("circuit={}", circuit_id))
+                .unwrap_or_else(|| "-".to_string())
+        })
+}
+
+/// Check if the client is on the local network.
+pub fn is_local_network_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ipv4) => ipv4.is_private() || ipv4.is_link_local() || ipv4.is_loopback(),
+        IpAddr::V6(ipv6) => {
+            let first_segment = ipv6.segments()[0];
+
+            ipv6.is_loopback()
+                // Unique local addresses in fc00::/7
+                || first_segment & 0xfe00 == 0xfc00
+                // Link local addresses in fe80::/10
+                || first_segment & 0xffc0 == 0xfe80
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::net::{self, TorGuard};
+    use crate::{
+        config::Config,
+        net::{proxy, ListenAddress, ListenerPolicy, TrustedSources},
+    };
     use actix_web::{guard::Guard, test::TestRequest};
-    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use anyhow::Result;
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+        path::PathBuf,
+    };
 
     #[test]
-    fn valid_client_ips() {
+    fn trusted_sources() -> Result<()> {
+        let trusted_sources = TrustedSources::from_config(&Config::default());
+
         // Valid
-        assert!(net::is_valid_client_ip(IpAddr::V4(Ipv4Addr::LOCALHOST)));
-        assert!(net::is_valid_client_ip("127.0.0.1".parse().unwrap()));
+        assert!(trusted_sources.is_trusted(Some(SocketAddr::from((Ipv4Addr::LOCALHOST, 1234)))));
+        assert!(trusted_sources.is_trusted(Some("127.0.0.1:1234".parse()?)));
 
         // Invalid
-        assert!(!net::is_valid_client_ip(IpAddr::V6(Ipv6Addr::LOCALHOST)));
-        assert!(!net::is_valid_client_ip("::1".parse().unwrap()));
-        assert!(!net::is_valid_client_ip("192.168.1.1".parse().unwrap()));
-        assert!(!net::is_valid_client_ip("127.1.0.1".parse().unwrap()));
-        assert!(!net::is_valid_client_ip("127.0.0.0".parse().unwrap()));
+        assert!(!trusted_sources.is_trusted(Some(SocketAddr::from((Ipv6Addr::LOCALHOST, 1234)))));
+        assert!(!trusted_sources.is_trusted(Some("[::1]:1234".parse()?)));
+        assert!(!trusted_sources.is_trusted(Some("192.168.1.1:1234".parse()?)));
+        assert!(!trusted_sources.is_trusted(Some("127.1.0.1:1234".parse()?)));
+        assert!(!trusted_sources.is_trusted(Some("127.0.0.0:1234".parse()?)));
+        assert!(!trusted_sources.is_trusted(None));
+
+        // IPv6 is allowed when listening on it
+        let trusted_sources = TrustedSources::from_config(&Config::from_raw_str(
+            "[server]\nlisten = [\"[::1]:52477\"]",
+        )?);
+        assert!(trusted_sources.is_trusted(Some("[::1]:1234".parse()?)));
+        assert!(!trusted_sources.is_trusted(Some("127.0.0.1:1234".parse()?)));
+
+        // Non-loopback addresses are never allowed by default, even when listening on them
+        let trusted_sources = TrustedSources::from_config(&Config::from_raw_str(
+            "[server]\nlisten = [\"192.168.1.1:52477\"]",
+        )?);
+        assert!(!trusted_sources.is_trusted(Some("192.168.1.1:1234".parse()?)));
+
+        // Only the configured sources are trusted
+        let trusted_sources = TrustedSources::from_config(&Config::from_raw_str(
+            "[server]\ntrusted_sources = [\"127.0.0.2\"]",
+        )?);
+        assert!(trusted_sources.is_trusted(Some("127.0.0.2:1234".parse()?)));
+        assert!(!trusted_sources.is_trusted(Some("127.0.0.1:1234".parse()?)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn listen_address() {
+        assert_eq!(
+            "127.0.0.1:52477".parse::<ListenAddress>().unwrap(),
+            ListenAddress::Tcp("127.0.0.1:52477".parse().unwrap())
+        );
+        assert_eq!(
+            "unix:/run/keybear.sock".parse::<ListenAddress>().unwrap(),
+            ListenAddress::Unix(PathBuf::from("/run/keybear.sock"))
+        );
+        assert!("127.0.0.1".parse::<ListenAddress>().is_err());
+        assert!("unix:".parse::<ListenAddress>().is_err());
+
+        // Formatted the same way as Tor expects it
+        for address in &["[::1]:52477", "unix:/run/keybear.sock"] {
+            assert_eq!(
+                address.parse::<ListenAddress>().unwrap().to_string(),
+                *address
+            );
+        }
     }
 
     #[actix_rt::test]
     async fn tor_guard_ip() {
-        let guard = TorGuard {};
+        let guard = ListenerPolicy::tor(&Config::default());
 
         let req = TestRequest::default()
             // This is an IP the Tor service might have
@@ -65,5 +280,77 @@ mod tests {
             .peer_addr("[::1]:52477".parse().unwrap())
             .to_http_request();
         assert!(!guard.check(req.head()));
+
+        // Requests without a peer address can only come from a unix domain socket
+        let req = TestRequest::default().to_http_request();
+        assert!(!guard.check(req.head()));
+    }
+
+    #[actix_rt::test]
+    async fn tor_guard_unix_socket() {
+        let guard = ListenerPolicy::Tor(TrustedSources::new(vec!["::1".parse().unwrap()], true));
+
+        let req = TestRequest::default().to_http_request();
+        assert!(guard.check(req.head()));
+
+        let req = TestRequest::default()
+            .peer_addr("[::1]:52477".parse().unwrap())
+            .to_http_request();
+        assert!(guard.check(req.head()));
+
+        let req = TestRequest::default()
+            .peer_addr("127.0.0.1:52477".parse().unwrap())
+            .to_http_request();
+        assert!(!guard.check(req.head()));
+    }
+
+    #[actix_rt::test]
+    async fn lan_policy() {
+        let guard = ListenerPolicy::Lan;
+
+        for ip in &[
+            "192.168.1.2",
+            "10.0.0.2",
+            "169.254.1.2",
+            "fd00::2",
+            "fe80::2",
+        ] {
+            let req = TestRequest::default()
+                .peer_addr(SocketAddr::new(ip.parse().unwrap(), 52478))
+                .to_http_request();
+            assert!(guard.check(req.head()), "{}", ip);
+        }
+
+        for ip in &["8.8.8.8", "2001:db8::2"] {
+            let req = TestRequest::default()
+                .peer_addr(SocketAddr::new(ip.parse().unwrap(), 52478))
+                .to_http_request();
+            assert!(!guard.check(req.head()), "{}", ip);
+        }
+
+        // Unix domain sockets are never used for the local network
+        let req = TestRequest::default().to_http_request();
+        assert!(!guard.check(req.head()));
+    }
+
+    #[actix_rt::test]
+    async fn tor_circuit_policy() -> Result<()> {
+        let guard = ListenerPolicy::tor(&Config::from_raw_str("[server]\nproxy_protocol = true")?);
+        assert_eq!(guard, ListenerPolicy::TorCircuit);
+
+        let req = TestRequest::default()
+            .peer_addr(proxy::circuit_address(42))
+            .to_http_request();
+        assert!(guard.check(req.head()));
+
+        // Everything not coming from a circuit is refused
+        let req = TestRequest::default()
+            .peer_addr("127.0.0.1:52477".parse()?)
+            .to_http_request();
+        assert!(!guard.check(req.head()));
+        let req = TestRequest::default().to_http_request();
+        assert!(!guard.check(req.head()));
+
+        Ok(())
     }
 }
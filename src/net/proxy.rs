@@ -0,0 +1,271 @@
+use crate::net::{ListenAddress, TrustedSources};
+use actix_http::{error::DispatchError, HttpService, Protocol, Request, Response};
+use actix_server::Server;
+use actix_service::{fn_service, map_config, pipeline_factory, IntoServiceFactory, ServiceFactory};
+use actix_web::{
+    body::MessageBody,
+    dev::AppConfig,
+    rt::{
+        self,
+        net::{TcpStream, UnixStream},
+    },
+    Error,
+};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    fmt,
+    io::{self, ErrorKind},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The maximum length of a version 1 PROXY protocol header, including the line ending.
+const MAX_HEADER_LENGTH: usize = 107;
+/// How long a client gets to send the PROXY protocol header.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+/// The first 64 bits of the source address Tor uses to export the circuit ID.
+///
+/// See `HiddenServiceExportCircuitID` in the Tor manual.
+const CIRCUIT_PREFIX: [u16; 4] = [0xfc00, 0xdead, 0xbeef, 0x4dad];
+
+/// Parse a version 1 PROXY protocol header line, returning the source address of the client.
+///
+/// The source is `None` when the proxy doesn't know it.
+pub fn parse_header(line: &str) -> Result<Option<SocketAddr>> {
+    let line = line
+        .strip_suffix("\r\n")
+        .ok_or_else(|| anyhow!("PROXY protocol header doesn't end with CRLF"))?;
+    let mut words = line.split(' ');
+    if words.next() != Some("PROXY") {
+        bail!("PROXY protocol header is missing");
+    }
+
+    let protocol = words.next();
+    if protocol == Some("UNKNOWN") {
+        return Ok(None);
+    }
+
+    let words = words.collect::<Vec<_>>();
+    let (source, port) = match (protocol, words.as_slice()) {
+        (Some("TCP4"), [source, _, port, _]) | (Some("TCP6"), [source, _, port, _]) => {
+            (source, port)
+        }
+        _ => bail!("PROXY protocol header \"{}\" is invalid", line),
+    };
+    let source = source
+        .parse::<IpAddr>()
+        .map_err(|err| anyhow!("PROXY protocol source address is invalid: {}", err))?;
+    let port = port
+        .parse::<u16>()
+        .map_err(|err| anyhow!("PROXY protocol source port is invalid: {}", err))?;
+
+    Ok(Some(SocketAddr::new(source, port)))
+}
+
+/// The ID of the Tor circuit the client is connecting through.
+///
+/// Tor encodes it in the source address of the PROXY protocol header.
+pub fn circuit_id(addr: &SocketAddr) -> Option<u32> {
+    match addr.ip() {
+        IpAddr::V6(ip) if ip.segments()[..4] == CIRCUIT_PREFIX => {
+            Some((u128::from(ip) & u128::from(u32::MAX)) as u32)
+        }
+        _ => None,
+    }
+}
+
+/// The source address Tor sends for a circuit.
+pub fn circuit_address(circuit_id: u32) -> SocketAddr {
+    let [a, b, c, d] = CIRCUIT_PREFIX;
+    let ip = Ipv6Addr::new(
+        a,
+        b,
+        c,
+        d,
+        0,
+        0,
+        (circuit_id >> 16) as u16,
+        circuit_id as u16,
+    );
+
+    SocketAddr::new(IpAddr::V6(ip), u16::MAX)
+}
+
+/// Read the PROXY protocol header from the start of a connection.
+///
+/// It's read byte by byte so nothing of the HTTP request after it is consumed.
+pub async fn read_header<T>(io: &mut T) -> io::Result<Option<SocketAddr>>
+where
+    T: AsyncRead + Unpin,
+{
+    let mut header = Vec::new();
+    while !header.ends_with(b"\r\n") {
+        if header.len() >= MAX_HEADER_LENGTH {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "PROXY protocol header is too long",
+            ));
+        }
+
+        header.push(io.read_u8().await?);
+    }
+
+    let line =
+        String::from_utf8(header).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+
+    parse_header(&line).map_err(|err| io::Error::new(ErrorKind::InvalidData, err.to_string()))
+}
+
+/// Read the PROXY protocol header within the timeout.
+async fn accept<T>(mut io: T) -> Result<(T, Protocol, Option<SocketAddr>), DispatchError>
+where
+    T: AsyncRead + Unpin,
+{
+    let source = rt::time::timeout(HEADER_TIMEOUT, read_header(&mut io))
+        .await
+        .map_err(|_| {
+            DispatchError::Io(io::Error::new(
+                ErrorKind::TimedOut,
+                "PROXY protocol header timed out",
+            ))
+        })?
+        .map_err(DispatchError::Io)?;
+
+    Ok((io, Protocol::Http1, source))
+}
+
+/// Start a server expecting a PROXY protocol header on every connection.
+///
+/// Only connections from the trusted sources are accepted, the address from the header is used as
+/// the peer address of the requests.
+pub fn server<F, I, S, B>(
+    listen_addresses: &[ListenAddress],
+    trusted_sources: TrustedSources,
+    factory: F,
+) -> Result<Server>
+where
+    F: Fn() -> I + Send + Clone + 'static,
+    I: IntoServiceFactory<S>,
+    S: ServiceFactory<Config = AppConfig, Request = Request>,
+    S::Error: Into<Error> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as actix_service::Service>::Future: 'static,
+    B: MessageBody + 'static,
+{
+    let mut builder = Server::build();
+
+    for address in listen_addresses {
+        let name = format!("keybear-proxy-{}", address);
+        let factory = factory.clone();
+        let trusted_sources = trusted_sources.clone();
+
+        builder = match address {
+            ListenAddress::Tcp(addr) => builder.bind(name, addr, move || {
+                let trusted_sources = trusted_sources.clone();
+
+                pipeline_factory(fn_service(move |io: TcpStream| {
+                    let trusted = trusted_sources.is_trusted(io.peer_addr().ok());
+
+                    async move {
+                        if !trusted {
+                            return Err(DispatchError::Io(io::Error::new(
+                                ErrorKind::PermissionDenied,
+                                "Connection from untrusted source",
+                            )));
+                        }
+
+                        accept(io).await
+                    }
+                }))
+                .and_then(
+                    HttpService::build()
+                        // Disable TCP keep alive
+                        .keep_alive(None)
+                        .finish(map_config(factory(), |_| AppConfig::default())),
+                )
+            }),
+            ListenAddress::Unix(path) => builder.bind_uds(name, path, move || {
+                let trusted = trusted_sources.is_trusted(None);
+
+                pipeline_factory(fn_service(move |io: UnixStream| async move {
+                    if !trusted {
+                        return Err(DispatchError::Io(io::Error::new(
+                            ErrorKind::PermissionDenied,
+                            "Connection from untrusted source",
+                        )));
+                    }
+
+                    accept(io).await
+                }))
+                .and_then(
+                    HttpService::build()
+                        .keep_alive(None)
+                        .finish(map_config(factory(), |_| AppConfig::default())),
+                )
+            }),
+        }
+        .map_err(|err| anyhow!("Listening on {} failed: {}", address, err))?;
+    }
+
+    Ok(builder.run())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::net::proxy;
+    use anyhow::Result;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn parse_header() -> Result<()> {
+        // The header Tor sends with `HiddenServiceExportCircuitID haproxy`
+        let source = proxy::parse_header("PROXY TCP6 fc00:dead:beef:4dad::1:2 ::1 65535 5219\r\n")?;
+        assert_eq!(
+            source,
+            Some("[fc00:dead:beef:4dad::1:2]:65535".parse::<SocketAddr>()?)
+        );
+        assert_eq!(proxy::circuit_id(&source.unwrap()), Some(0x0001_0002));
+
+        assert_eq!(
+            proxy::parse_header("PROXY TCP4 192.168.1.2 127.0.0.1 1234 80\r\n")?,
+            Some("192.168.1.2:1234".parse()?)
+        );
+        assert_eq!(proxy::parse_header("PROXY UNKNOWN\r\n")?, None);
+
+        assert!(proxy::parse_header("PROXY TCP4 192.168.1.2 127.0.0.1 1234 80").is_err());
+        assert!(proxy::parse_header("GET / HTTP/1.1\r\n").is_err());
+        assert!(proxy::parse_header("PROXY TCP4 invalid 127.0.0.1 1234 80\r\n").is_err());
+        assert!(proxy::parse_header("PROXY TCP4 192.168.1.2 127.0.0.1\r\n").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn circuit_id() -> Result<()> {
+        assert_eq!(proxy::circuit_id(&proxy::circuit_address(42)), Some(42));
+        assert_eq!(
+            proxy::circuit_id(&proxy::circuit_address(u32::MAX)),
+            Some(u32::MAX)
+        );
+        assert_eq!(proxy::circuit_id(&"[::1]:1234".parse()?), None);
+        assert_eq!(proxy::circuit_id(&"127.0.0.1:1234".parse()?), None);
+
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn read_header() -> Result<()> {
+        let mut stream: &[u8] = b"PROXY TCP6 fc00:dead:beef:4dad::2a ::1 65535 5219\r\nGET /";
+        let source = proxy::read_header(&mut stream).await?;
+        assert_eq!(source, Some(proxy::circuit_address(42)));
+        // The request after the header is untouched
+        assert_eq!(stream, b"GET /");
+
+        let mut stream: &[u8] = &[b'P'; 200];
+        assert!(proxy::read_header(&mut stream).await.is_err());
+
+        Ok(())
+    }
+}
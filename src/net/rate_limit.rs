@@ -0,0 +1,223 @@
+use crate::net::proxy;
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    error::ErrorTooManyRequests,
+    Error,
+};
+use futures::future::{self, Either, Ready};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// The period in which the requests of a client are counted.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Limit the amount of requests per minute of every client.
+pub struct RateLimiter {
+    /// Maximum amount of requests in a minute.
+    limit: u32,
+    /// Start of the current window and the amount of requests in it for every client.
+    clients: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    /// Allow a client to do `limit` requests every minute.
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a request of the client, returning whether it's allowed.
+    pub fn check(&self, client: &str) -> bool {
+        self.check_at(client, Instant::now())
+    }
+
+    /// Count a request of the client at a specific moment.
+    fn check_at(&self, client: &str, now: Instant) -> bool {
+        let mut clients = self.clients.lock().unwrap();
+
+        // Forget the clients from previous windows so the map doesn't keep growing
+        clients.retain(|_, (start, _)| now.duration_since(*start) < WINDOW);
+
+        let (_, requests) = clients.entry(client.to_string()).or_insert((now, 0));
+        *requests += 1;
+
+        *requests <= self.limit
+    }
+}
+
+/// Actix middleware refusing requests of clients exceeding the rate limit.
+#[derive(Clone)]
+pub struct RateLimit {
+    /// The shared limiter, nothing is limited without one.
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl RateLimit {
+    /// Allow every client `limit` requests per minute, unlimited when `None`.
+    pub fn new(limit: Option<u32>) -> Self {
+        Self {
+            limiter: limit.map(|limit| Arc::new(RateLimiter::new(limit))),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for RateLimit
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(RateLimitMiddleware {
+            service,
+            limiter: self.limiter.clone(),
+        })
+    }
+}
+
+/// The service created by the [`RateLimit`] middleware.
+pub struct RateLimitMiddleware<S> {
+    /// The wrapped service.
+    service: S,
+    /// The shared limiter.
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl<S, B> Service for RateLimitMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let allowed = self
+            .limiter
+            .as_ref()
+            .map(|limiter| limiter.check(&client_key(req.peer_addr())))
+            .unwrap_or(true);
+
+        if allowed {
+            Either::Left(self.service.call(req))
+        } else {
+            Either::Right(future::ok(
+                req.error_response(ErrorTooManyRequests("Rate limit exceeded")),
+            ))
+        }
+    }
+}
+
+/// Identify the client of a request.
+///
+/// Everything through Tor comes from the same address, so when the Tor circuit is known that's
+/// used instead.
+pub fn client_key(peer_addr: Option<SocketAddr>) -> String {
+    match peer_addr {
+        Some(addr) => match proxy::circuit_id(&addr) {
+            Some(circuit_id) => format!("circuit-{}", circuit_id),
+            None => addr.ip().to_string(),
+        },
+        None => "unix".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::net::{
+        proxy,
+        rate_limit::{self, RateLimit, RateLimiter},
+    };
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn check() {
+        let limiter = RateLimiter::new(2);
+        let now = Instant::now();
+
+        assert!(limiter.check_at("first", now));
+        assert!(limiter.check_at("first", now));
+        assert!(!limiter.check_at("first", now));
+        // Other clients have their own limit
+        assert!(limiter.check_at("second", now));
+
+        // The limit is reset after a minute
+        assert!(limiter.check_at("first", now + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn client_key() {
+        assert_eq!(
+            rate_limit::client_key(Some(proxy::circuit_address(42))),
+            "circuit-42"
+        );
+        assert_eq!(
+            rate_limit::client_key(Some("127.0.0.1:1234".parse().unwrap())),
+            "127.0.0.1"
+        );
+        assert_eq!(rate_limit::client_key(None), "unix");
+    }
+
+    #[actix_rt::test]
+    async fn middleware() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RateLimit::new(Some(1)))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let request = || {
+            test::TestRequest::default()
+                .peer_addr(proxy::circuit_address(1))
+                .to_request()
+        };
+        let response = test::call_service(&mut app, request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(&mut app, request()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // Another circuit isn't limited
+        let response = test::call_service(
+            &mut app,
+            test::TestRequest::default()
+                .peer_addr(proxy::circuit_address(2))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Without a limit everything is allowed
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RateLimit::new(None))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        for _ in 0..10 {
+            let response = test::call_service(&mut app, request()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{config::Config, net::ListenAddress};
+use anyhow::{anyhow, Result};
+use qrcode::{render::unicode::Dense1x2, QrCode};
+use std::{
+    fmt, fs,
+    net::{Ipv4Addr, SocketAddr},
+    path::Path,
+};
+use x25519_dalek::PublicKey;
+
+/// Scheme of the pairing URI.
+pub const PAIRING_SCHEME: &str = "keybear";
+
+/// Everything a client needs to know to register with the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairingUri {
+    /// The onion hostname of the hidden service.
+    pub host: String,
+    /// The port of the hidden service.
+    pub port: u16,
+    /// The public key of the server.
+    pub public_key: [u8; 32],
+    /// The token the first device must register with.
+    pub bootstrap_token: Option<String>,
+    /// Base32 encoded private key to connect to the onion service with client authorization.
+    pub client_auth_key: Option<String>,
+}
+
+impl PairingUri {
+    /// Get the address of the hidden service from the Tor files in the config.
+    ///
+    /// When the onion service is created through the Tor control port the address saved by
+    /// keybear is used instead.
+    pub fn from_config(
+        config: &Config,
+        public_key: &PublicKey,
+        bootstrap_token: Option<String>,
+    ) -> Result<Self> {
+        let (hidden_service_dir, port) = if config.tor_control_address().is_some() {
+            (config.tor_onion_dir(), config.tor_onion_port())
+        } else {
+            let torrc_path = config.torrc_path();
+            let torrc = fs::read_to_string(torrc_path).map_err(|err| {
+                anyhow!("Reading Tor configuration {:?} failed: {}", torrc_path, err)
+            })?;
+            let listen_addresses = config.listen_addresses();
+            let port = hidden_service_port(&torrc, &listen_addresses).ok_or_else(|| {
+                anyhow!(
+                    "No hidden service port forwarding to {} found in {:?}",
+                    listen_addresses
+                        .iter()
+                        .map(|address| address.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" or "),
+                    torrc_path
+                )
+            })?;
+
+            (config.tor_hidden_service_dir().to_path_buf(), port)
+        };
+
+        Ok(Self {
+            host: onion_hostname(&hidden_service_dir)?,
+            port,
+            public_key: public_key.to_bytes(),
+            bootstrap_token,
+            client_auth_key: None,
+        })
+    }
+
+    /// Render the URI as a QR code that can be shown in a terminal.
+    pub fn to_qr_code(&self) -> Result<String> {
+        let code = QrCode::new(self.to_string())
+            .map_err(|err| anyhow!("Creating QR code failed: {}", err))?;
+
+        Ok(code
+            .render::<Dense1x2>()
+            // Terminals are mostly light text on a dark background
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .build())
+    }
+}
+
+impl fmt::Display for PairingUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}://{}:{}?key={}",
+            PAIRING_SCHEME,
+            self.host,
+            self.port,
+            base64::encode_config(self.public_key, base64::URL_SAFE_NO_PAD)
+        )?;
+
+        // The token only consists of alphanumeric characters so it doesn't need to be escaped
+        if let Some(token) = &self.bootstrap_token {
+            write!(f, "&token={}", token)?;
+        }
+        // Base32 doesn't need to be escaped either
+        if let Some(client_auth_key) = &self.client_auth_key {
+            write!(f, "&auth={}", client_auth_key)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Read the onion hostname Tor generated for the hidden service.
+pub fn onion_hostname(hidden_service_dir: &Path) -> Result<String> {
+    let path = hidden_service_dir.join("hostname");
+
+    let hostname = fs::read_to_string(&path)
+        .map_err(|err| anyhow!("Reading onion hostname {:?} failed: {}", path, err))?
+        .trim()
+        .to_string();
+    if !hostname.ends_with(".onion") {
+        return Err(anyhow!("File {:?} doesn't contain an onion hostname", path));
+    }
+
+    Ok(hostname)
+}
+
+/// Find the virtual port of the hidden service that forwards to one of the listen addresses in a
+/// torrc file.
+pub fn hidden_service_port(torrc: &str, listen_addresses: &[ListenAddress]) -> Option<u16> {
+    torrc
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            if words.next()? != "HiddenServicePort" {
+                return None;
+            }
+
+            let virtual_port = words.next()?.parse::<u16>().ok()?;
+            let target = match words.next() {
+                // Only a port means the port on localhost
+                Some(target) => match target.parse::<u16>() {
+                    Ok(port) => localhost(port),
+                    Err(_) => match target.strip_prefix("localhost:") {
+                        Some(port) => localhost(port.parse().ok()?),
+                        None => target.parse().ok()?,
+                    },
+                },
+                // Without a target the same port is used on localhost
+                None => localhost(virtual_port),
+            };
+
+            Some((virtual_port, target))
+        })
+        .find(|(_, target)| listen_addresses.contains(target))
+        .map(|(virtual_port, _)| virtual_port)
+}
+
+/// The address Tor connects to when only a port is specified.
+fn localhost(port: u16) -> ListenAddress {
+    ListenAddress::Tcp(SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::Config,
+        pair::{self, PairingUri},
+    };
+    use anyhow::Result;
+    use keybear_core::crypto::StaticSecretExt;
+    use std::fs;
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    #[test]
+    fn hidden_service_port() {
+        let torrc = r#"
+            # Some comment
+            HiddenServiceDir /var/lib/tor/keybear
+            HiddenServicePort 80 127.0.0.1:8080
+            HiddenServicePort 5219 127.0.0.1:52477
+            HiddenServicePort 1234
+            HiddenServicePort 4321 4322
+            HiddenServicePort 443 [::1]:52477
+            HiddenServicePort 8443 unix:/run/keybear/keybear.sock
+        "#;
+        let port = |address: &str| pair::hidden_service_port(torrc, &[address.parse().unwrap()]);
+
+        assert_eq!(port("127.0.0.1:52477"), Some(5219));
+        assert_eq!(port("127.0.0.1:1234"), Some(1234));
+        assert_eq!(port("127.0.0.1:4322"), Some(4321));
+        assert_eq!(port("[::1]:52477"), Some(443));
+        assert_eq!(port("unix:/run/keybear/keybear.sock"), Some(8443));
+        assert_eq!(port("127.0.0.1:80"), None);
+        assert_eq!(port("[::1]:1234"), None);
+    }
+
+    #[test]
+    fn from_config() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("hostname"), "abcdef.onion\n")?;
+        fs::write(
+            dir.path().join("torrc"),
+            include_str!("../../debian/tor-service.conf"),
+        )?;
+        let config = Config::from_raw_str(&format!(
+            r#"
+            [tor]
+            hidden_service_dir = {:?}
+            torrc_path = {:?}
+            "#,
+            dir.path(),
+            dir.path().join("torrc")
+        ))?;
+
+        let public_key = PublicKey::from(&StaticSecret::new_with_os_rand());
+        let uri = PairingUri::from_config(&config, &public_key, Some("token".to_string()))?;
+        assert_eq!(uri.host, "abcdef.onion");
+        assert_eq!(uri.port, 5219);
+        assert_eq!(
+            uri.to_string(),
+            format!(
+                "keybear://abcdef.onion:5219?key={}&token=token",
+                base64::encode_config(public_key.as_bytes(), base64::URL_SAFE_NO_PAD)
+            )
+        );
+        assert!(!uri.to_qr_code()?.is_empty());
+
+        // Without a hostname the server can't be reached
+        fs::remove_file(dir.path().join("hostname"))?;
+        assert!(PairingUri::from_config(&config, &public_key, None).is_err());
+
+        Ok(())
+    }
+}
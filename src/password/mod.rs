@@ -1,15 +1,20 @@
-use crate::{app::AppState, body::EncryptedBody};
+use crate::{app::AppState, body::EncryptedBody, breach::BreachDatabase};
 use actix_web::{
-    error::ErrorNotFound,
+    error::{ErrorInternalServerError, ErrorNotFound},
     web::{Data, Path},
     Result,
 };
 use keybear_core::types::{PasswordResponse, PublicPassword, RegisterPasswordRequest};
+use log::warn;
 use serde::{Deserialize, Serialize};
+use std::{
+    ops::Deref,
+    time::{SystemTime, UNIX_EPOCH},
+};
 use uuid::Uuid;
 
-/// Allow converting an incoming message to a device.
-trait ToPassword {
+/// Allow converting an incoming message to a password.
+pub(crate) trait ToPassword {
     fn to_password(&self) -> Password;
 }
 
@@ -27,7 +32,7 @@ impl Passwords {
     }
 
     /// Get a vector of passwords as allowed to be shown to the clients.
-    pub fn to_public_vec(&self) -> Vec<PublicPassword> {
+    pub fn to_public_vec(&self) -> Vec<PasswordSummary> {
         self.passwords.iter().map(|pass| pass.to_public()).collect()
     }
 
@@ -35,20 +40,35 @@ impl Passwords {
     pub fn by_id(&self, id: &str) -> Option<&Password> {
         self.passwords.iter().find(|password| password.id == id)
     }
+
+    /// Remove a password by ID, returning it when it existed.
+    pub fn remove(&mut self, id: &str) -> Option<Password> {
+        let index = self
+            .passwords
+            .iter()
+            .position(|password| password.id == id)?;
+
+        Some(self.passwords.remove(index))
+    }
+
+    /// Iterate over all passwords.
+    pub fn iter(&self) -> impl Iterator<Item = &Password> {
+        self.passwords.iter()
+    }
+
+    /// Iterate mutably over all passwords.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Password> {
+        self.passwords.iter_mut()
+    }
 }
 
 impl ToPassword for RegisterPasswordRequest {
     /// Convert this into a password struct that can be added to the database.
     fn to_password(&self) -> Password {
-        // Generate a new unique identifier
-        let id = Uuid::new_v4().to_simple().to_string();
-
         Password {
-            id,
-            name: self.name().to_string(),
-            password: self.password().to_string(),
             email: self.email().map(|s| s.to_string()),
             website: self.website().map(|s| s.to_string()),
+            ..Password::new(self.name(), self.password())
         }
     }
 }
@@ -66,35 +86,183 @@ pub struct Password {
     pub email: Option<String>,
     /// The website associated.
     pub website: Option<String>,
+    /// The base32 encoded secret of the time-based one time password.
+    #[serde(default)]
+    pub totp: Option<String>,
+    /// When the password was last changed as a UNIX timestamp, unknown for older entries.
+    #[serde(default)]
+    pub modified: Option<u64>,
+    /// Whether the password occurs in a known data breach.
+    #[serde(default)]
+    pub compromised: bool,
+    /// Free form notes.
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// Name of the folder the password is organized in.
+    #[serde(default)]
+    pub folder: Option<String>,
+    /// The username, for logins that don't use the e-mail.
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Whether a time-based one time password could be stored with it, this isn't the case when
+    /// it's imported from a format without one.
+    #[serde(default = "totp_supported")]
+    pub totp_supported: bool,
 }
 
 impl Password {
+    /// Construct a new password entry with a generated unique identifier.
+    pub fn new<S1, S2>(name: S1, password: S2) -> Self
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        Self {
+            id: Uuid::new_v4().to_simple().to_string(),
+            name: name.into(),
+            password: password.into(),
+            email: None,
+            website: None,
+            totp: None,
+            modified: Some(current_timestamp()),
+            compromised: false,
+            notes: None,
+            folder: None,
+            username: None,
+            totp_supported: true,
+        }
+    }
+
+    /// Set the username, it's also used as the e-mail when it's an e-mail address.
+    pub fn set_username(&mut self, username: Option<String>) {
+        self.username = username.filter(|username| !username.is_empty());
+
+        if self.email.is_none() {
+            self.email = self
+                .username
+                .clone()
+                .filter(|username| username.contains('@'));
+        }
+    }
+
+    /// The name used to log in, the username or otherwise the e-mail.
+    pub fn login(&self) -> Option<&str> {
+        self.username.as_deref().or(self.email.as_deref())
+    }
+
     /// Convert it to a message response.
-    pub fn to_response(&self) -> PasswordResponse {
-        PasswordResponse::new(&self.password)
+    pub fn to_response(&self) -> PasswordDetails {
+        PasswordDetails {
+            password: PasswordResponse::new(&self.password),
+            totp: self.totp.clone(),
+            notes: self.notes.clone(),
+        }
     }
 
     /// Convert it to a public password, without the actual password.
-    pub fn to_public(&self) -> PublicPassword {
-        PublicPassword::new(
-            &self.id,
-            &self.name,
-            self.email.as_ref(),
-            self.website.as_ref(),
-        )
+    pub fn to_public(&self) -> PasswordSummary {
+        PasswordSummary {
+            password: PublicPassword::new(
+                &self.id,
+                &self.name,
+                self.email.as_ref(),
+                self.website.as_ref(),
+            ),
+            username: self.username.clone(),
+            folder: self.folder.clone(),
+        }
     }
 }
 
+/// Password information without the actual password.
+///
+/// Extends the public password of `keybear_core` with the information only known by this server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasswordSummary {
+    /// The public password every client understands.
+    #[serde(flatten)]
+    pub password: PublicPassword,
+    /// The username, for logins that don't use the e-mail.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Name of the folder the password is organized in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+}
+
+impl Deref for PasswordSummary {
+    type Target = PublicPassword;
+
+    fn deref(&self) -> &Self::Target {
+        &self.password
+    }
+}
+
+/// The secret parts of a password.
+///
+/// Extends the password response of `keybear_core` with the information only known by this
+/// server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasswordDetails {
+    /// The password response every client understands.
+    #[serde(flatten)]
+    pub password: PasswordResponse,
+    /// The base32 encoded secret of the time-based one time password.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp: Option<String>,
+    /// Free form notes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+impl Deref for PasswordDetails {
+    type Target = PasswordResponse;
+
+    fn deref(&self) -> &Self::Target {
+        &self.password
+    }
+}
+
+/// Passwords stored before this was tracked could always have a one time password.
+fn totp_supported() -> bool {
+    true
+}
+
+/// The current time as a UNIX timestamp in seconds.
+pub fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Flag the password when it occurs in a known data breach.
+pub(crate) fn flag_compromised(state: &AppState, password: &mut Password) -> Result<()> {
+    if let Some(database) =
+        BreachDatabase::from_config(&state.config).map_err(ErrorInternalServerError)?
+    {
+        password.compromised = database
+            .is_compromised(&password.password)
+            .map_err(ErrorInternalServerError)?;
+
+        if password.compromised {
+            warn!("Registered password occurs in a known data breach");
+        }
+    }
+
+    Ok(())
+}
+
 /// Get a single password.
 pub async fn get_password(
     Path((id,)): Path<(String,)>,
     state: Data<AppState>,
-) -> Result<EncryptedBody<PasswordResponse>> {
+) -> Result<EncryptedBody<PasswordDetails>> {
     // Get the passwords from the database or use the default
     let passwords = state
         .storage
         .lock()
-        .unwrap()
+        .await
         .get::<_, Passwords>("passwords")
         .await?
         .unwrap_or_else(Passwords::default);
@@ -110,12 +278,12 @@ pub async fn get_password(
 }
 
 /// Get a list of all passwords.
-pub async fn get_passwords(state: Data<AppState>) -> Result<EncryptedBody<Vec<PublicPassword>>> {
+pub async fn get_passwords(state: Data<AppState>) -> Result<EncryptedBody<Vec<PasswordSummary>>> {
     // Get the passwords from the database or use the default
     let passwords = state
         .storage
         .lock()
-        .unwrap()
+        .await
         .get::<_, Passwords>("passwords")
         .await?
         .unwrap_or_else(Passwords::default);
@@ -127,9 +295,19 @@ pub async fn get_passwords(state: Data<AppState>) -> Result<EncryptedBody<Vec<Pu
 pub async fn post_passwords(
     password: EncryptedBody<RegisterPasswordRequest>,
     state: Data<AppState>,
-) -> Result<EncryptedBody<PublicPassword>> {
-    // Get a mutex lock on the storage
-    let storage = state.storage.lock().unwrap();
+) -> Result<EncryptedBody<PasswordSummary>> {
+    // Convert the register password to an internal password used for storage
+    let password = register(&state, password.to_password()).await?;
+
+    Ok(EncryptedBody::new(password.to_public()))
+}
+
+/// Flag the password when it occurs in a known data breach and store it.
+///
+/// Returns the password as it's stored.
+pub(crate) async fn register(state: &AppState, mut password: Password) -> Result<Password> {
+    // Get a mutex lock on the storage for the whole update
+    let storage = state.storage.lock().await;
 
     // Get the passwords from the database or use the default
     let mut passwords = storage
@@ -137,8 +315,7 @@ pub async fn post_passwords(
         .await?
         .unwrap_or_else(Passwords::default);
 
-    // Convert the register password to an internal password used for storage
-    let password = password.to_password();
+    flag_compromised(state, &mut password)?;
 
     // Register the passed password
     passwords.register(password.clone());
@@ -146,5 +323,5 @@ pub async fn post_passwords(
     // Persist the passwords in the storage
     storage.set("passwords", &passwords).await?;
 
-    Ok(EncryptedBody::new(password.to_public()))
+    Ok(password)
 }
@@ -0,0 +1,544 @@
+use crate::{
+    app::AppState,
+    body::EncryptedBody,
+    item::{kind::ItemKind, Item, Items},
+    password::{self, Password, Passwords},
+};
+use actix_web::{error::ErrorInternalServerError, web::Data, Result as WebResult};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// After how many days a password is considered old when not specified.
+pub const DEFAULT_MAX_AGE_DAYS: u64 = 365;
+
+/// Seconds in a day.
+const DAY: u64 = 24 * 60 * 60;
+
+/// Passwords that are so common they are always guessed first.
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456",
+    "123456789",
+    "12345678",
+    "12345",
+    "1234567",
+    "1234567890",
+    "111111",
+    "000000",
+    "123123",
+    "654321",
+    "666666",
+    "password",
+    "passw0rd",
+    "qwerty",
+    "qwertyuiop",
+    "azerty",
+    "abc123",
+    "iloveyou",
+    "admin",
+    "welcome",
+    "letmein",
+    "monkey",
+    "dragon",
+    "football",
+    "baseball",
+    "sunshine",
+    "princess",
+    "master",
+    "login",
+    "starwars",
+    "trustno1",
+    "secret",
+    "changeme",
+    "default",
+    "test",
+];
+
+/// Websites that are known to support two-factor authentication with one time passwords.
+const TOTP_WEBSITES: &[&str] = &[
+    "amazon.com",
+    "apple.com",
+    "binance.com",
+    "bitbucket.org",
+    "cloudflare.com",
+    "coinbase.com",
+    "crates.io",
+    "digitalocean.com",
+    "discord.com",
+    "dropbox.com",
+    "facebook.com",
+    "github.com",
+    "gitlab.com",
+    "google.com",
+    "icloud.com",
+    "instagram.com",
+    "linkedin.com",
+    "live.com",
+    "microsoft.com",
+    "npmjs.com",
+    "outlook.com",
+    "paypal.com",
+    "proton.me",
+    "protonmail.com",
+    "reddit.com",
+    "slack.com",
+    "twitch.tv",
+    "twitter.com",
+    "x.com",
+];
+
+/// The parameters of the report.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReportRequest {
+    /// After how many days without changes a password is reported as old.
+    pub max_age_days: Option<u64>,
+}
+
+/// An estimation of how hard a password is to guess.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strength {
+    VeryWeak,
+    Weak,
+    Reasonable,
+    Strong,
+    VeryStrong,
+}
+
+impl Strength {
+    /// Get the strength from the amount of entropy bits.
+    pub fn from_entropy(entropy: f64) -> Self {
+        match entropy {
+            e if e < 28.0 => Strength::VeryWeak,
+            e if e < 36.0 => Strength::Weak,
+            e if e < 60.0 => Strength::Reasonable,
+            e if e < 128.0 => Strength::Strong,
+            _ => Strength::VeryStrong,
+        }
+    }
+}
+
+/// A single problem found with a password.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Finding {
+    /// The password is easy to guess.
+    Weak,
+    /// The same password is used by other entries.
+    Reused {
+        /// Identifiers of the other entries.
+        ids: Vec<String>,
+    },
+    /// The password hasn't been changed for a long time.
+    Old {
+        /// Days since the last change.
+        days: u64,
+    },
+    /// The website supports one time passwords but none is configured.
+    MissingTotp,
+    /// The password occurs in a known data breach.
+    Compromised,
+}
+
+/// The findings of a single password.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntryReport {
+    /// Identifier of the password or item.
+    pub id: String,
+    /// Name of the password or item.
+    pub name: String,
+    /// Estimated entropy in bits.
+    pub entropy: f64,
+    /// Estimated strength.
+    pub strength: Strength,
+    /// All problems found.
+    pub findings: Vec<Finding>,
+}
+
+/// The health report of all passwords.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VaultReport {
+    /// The reports of every password.
+    pub entries: Vec<EntryReport>,
+    /// Amount of weak passwords.
+    pub weak: usize,
+    /// Amount of passwords that are used more than once.
+    pub reused: usize,
+    /// Amount of old passwords.
+    pub old: usize,
+    /// Amount of passwords without a one time password where it's supported.
+    pub missing_totp: usize,
+    /// Amount of passwords occurring in a known data breach.
+    pub compromised: usize,
+}
+
+impl VaultReport {
+    /// Analyse all passwords and the items containing a password.
+    ///
+    /// `now` is the current UNIX timestamp used to determine the age of the passwords.
+    pub fn analyse(passwords: &Passwords, items: &Items, max_age_days: u64, now: u64) -> Self {
+        let credentials = passwords
+            .iter()
+            .map(Credential::from_password)
+            .chain(items.iter().filter_map(Credential::from_item))
+            .collect::<Vec<_>>();
+
+        // Group the entries by their password to find the duplicates
+        let mut by_password: HashMap<&str, Vec<&str>> = HashMap::new();
+        credentials.iter().for_each(|credential| {
+            by_password
+                .entry(credential.password)
+                .or_default()
+                .push(credential.id)
+        });
+
+        let mut report = Self::default();
+        for credential in credentials.iter() {
+            let entropy = estimate_entropy(credential.password);
+            let strength = Strength::from_entropy(entropy);
+
+            let mut findings = Vec::new();
+            if strength <= Strength::Weak {
+                findings.push(Finding::Weak);
+                report.weak += 1;
+            }
+
+            let others = by_password[credential.password]
+                .iter()
+                .filter(|id| **id != credential.id)
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>();
+            if !others.is_empty() {
+                findings.push(Finding::Reused { ids: others });
+                report.reused += 1;
+            }
+
+            if let Some(modified) = credential.modified {
+                let days = now.saturating_sub(modified) / DAY;
+                if days >= max_age_days {
+                    findings.push(Finding::Old { days });
+                    report.old += 1;
+                }
+            }
+
+            if credential.missing_totp {
+                findings.push(Finding::MissingTotp);
+                report.missing_totp += 1;
+            }
+
+            if credential.compromised {
+                findings.push(Finding::Compromised);
+                report.compromised += 1;
+            }
+
+            report.entries.push(EntryReport {
+                id: credential.id.to_string(),
+                name: credential.name.to_string(),
+                entropy,
+                strength,
+                findings,
+            });
+        }
+
+        report
+    }
+}
+
+/// The parts of a password or item the report looks at.
+struct Credential<'a> {
+    /// Identifier of the password or item.
+    id: &'a str,
+    /// Name of the password or item.
+    name: &'a str,
+    /// The secret that must be hard to guess.
+    password: &'a str,
+    /// When the password was last changed as a UNIX timestamp, if known.
+    modified: Option<u64>,
+    /// Whether the website supports a one time password that could be stored but isn't.
+    missing_totp: bool,
+    /// Whether the password occurs in a known data breach.
+    compromised: bool,
+}
+
+impl<'a> Credential<'a> {
+    /// Look at a password.
+    fn from_password(password: &'a Password) -> Self {
+        Self {
+            id: &password.id,
+            name: &password.name,
+            password: &password.password,
+            modified: password.modified,
+            missing_totp: password.totp.is_none()
+                && password.totp_supported
+                && supports_totp(password.website.as_deref()),
+            compromised: password.compromised,
+        }
+    }
+
+    /// Look at an item, `None` when the type doesn't contain a password.
+    fn from_item(item: &'a Item) -> Option<Self> {
+        let (password, missing_totp) = match &item.kind {
+            ItemKind::Login(login) => (
+                login.password.as_str(),
+                login.totp.is_none() && supports_totp(login.website.as_deref()),
+            ),
+            ItemKind::WifiNetwork(wifi) => (wifi.password.as_deref()?, false),
+            ItemKind::Database(database) => (database.password.as_str(), false),
+            ItemKind::Card(_) | ItemKind::Identity(_) | ItemKind::ApiKey(_) | ItemKind::Note(_) => {
+                return None
+            }
+        };
+
+        Some(Self {
+            id: &item.id,
+            name: &item.name,
+            password,
+            modified: None,
+            missing_totp,
+            compromised: false,
+        })
+    }
+}
+
+/// Estimate the entropy of a password in bits.
+///
+/// The estimation assumes every character is picked at random from the classes used, characters
+/// that repeat or continue a sequence don't add to the entropy.
+pub fn estimate_entropy(password: &str) -> f64 {
+    // Well-known passwords are guessed immediately, also when followed by some digits or symbols
+    let stripped = password
+        .trim_end_matches(|c: char| !c.is_alphabetic())
+        .to_lowercase();
+    if COMMON_PASSWORDS.contains(&password.to_lowercase().as_str())
+        || COMMON_PASSWORDS.contains(&stripped.as_str())
+    {
+        return 0.0;
+    }
+
+    let chars = password.chars().collect::<Vec<_>>();
+
+    // Determine the size of the pool the characters are picked from
+    let mut pool = 0;
+    if chars.iter().any(|c| c.is_ascii_lowercase()) {
+        pool += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) {
+        pool += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_digit()) {
+        pool += 10;
+    }
+    if chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' ') {
+        pool += 33;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        pool += 100;
+    }
+    if pool == 0 {
+        return 0.0;
+    }
+
+    // Count the characters that aren't predictable from the previous one
+    let effective_length = 1 + chars
+        .windows(2)
+        .filter(|pair| {
+            let (previous, current) = (pair[0] as i64, pair[1] as i64);
+
+            (current - previous).abs() > 1
+        })
+        .count();
+
+    effective_length as f64 * (pool as f64).log2()
+}
+
+/// Whether the website is known to support one time passwords.
+fn supports_totp(website: Option<&str>) -> bool {
+    let host = match website.and_then(website_host) {
+        Some(host) => host,
+        None => return false,
+    };
+
+    TOTP_WEBSITES
+        .iter()
+        .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+}
+
+/// Get the lowercase host from a website URL.
+pub(crate) fn website_host(website: &str) -> Option<String> {
+    let website = website.trim();
+    // Remove the scheme
+    let without_scheme = website
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(website);
+    // Remove the path, the port and the credentials
+    let host = without_scheme
+        .split(['/', '?', '#'])
+        .next()?
+        .rsplit('@')
+        .next()?
+        .split(':')
+        .next()?
+        .to_lowercase();
+
+    if host.is_empty() {
+        None
+    } else {
+        Some(host)
+    }
+}
+
+/// Analyse all passwords and report the problems found.
+pub async fn report(
+    request: EncryptedBody<ReportRequest>,
+    state: Data<AppState>,
+) -> WebResult<EncryptedBody<VaultReport>> {
+    let passwords = state.passwords().await.map_err(ErrorInternalServerError)?;
+    let items = state.items().await.map_err(ErrorInternalServerError)?;
+
+    Ok(EncryptedBody::new(VaultReport::analyse(
+        &passwords,
+        &items,
+        request.max_age_days.unwrap_or(DEFAULT_MAX_AGE_DAYS),
+        password::current_timestamp(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        item::{
+            kind::{ItemKind, Login, WifiNetwork, WifiSecurity},
+            Item, Items,
+        },
+        password::{Password, Passwords},
+        report::{self, Finding, Strength, VaultReport, DAY},
+    };
+
+    fn password(id: &str, password: &str, website: Option<&str>, modified: u64) -> Password {
+        Password {
+            id: id.to_string(),
+            website: website.map(|website| website.to_string()),
+            modified: Some(modified),
+            ..Password::new(id, password)
+        }
+    }
+
+    #[test]
+    fn entropy() {
+        assert_eq!(report::estimate_entropy(""), 0.0);
+        assert_eq!(report::estimate_entropy("password"), 0.0);
+        assert_eq!(report::estimate_entropy("Password123!"), 0.0);
+
+        // Sequences and repetitions are weak
+        assert_eq!(
+            Strength::from_entropy(report::estimate_entropy("abcdefghijklmnop")),
+            Strength::VeryWeak
+        );
+        assert_eq!(
+            Strength::from_entropy(report::estimate_entropy("zzzzzzzzzzzzzzzzzzzz")),
+            Strength::VeryWeak
+        );
+
+        // Random passwords are strong
+        assert!(
+            Strength::from_entropy(report::estimate_entropy("x7$Kq!v9Lm#2Wz")) >= Strength::Strong
+        );
+    }
+
+    #[test]
+    fn website_host() {
+        assert_eq!(
+            report::website_host("https://www.github.com/login").as_deref(),
+            Some("www.github.com")
+        );
+        assert_eq!(
+            report::website_host("user@example.com:8080").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(report::website_host("").as_deref(), None);
+    }
+
+    #[test]
+    fn analyse() {
+        let now = 1000 * DAY;
+
+        let mut passwords = Passwords::default();
+        passwords.register(password("a", "x7$Kq!v9Lm#2Wz", None, now));
+        passwords.register(password("b", "x7$Kq!v9Lm#2Wz", None, now));
+        passwords.register(password("c", "123456", None, now - 400 * DAY));
+        passwords.register(password(
+            "d",
+            "Tr0ub4dor&3horse!",
+            Some("https://github.com"),
+            now,
+        ));
+        // A one time password can't be stored with it so it's not reported missing
+        passwords.register(Password {
+            totp_supported: false,
+            ..password(
+                "e",
+                "Sp1ral-Staircase&Lamp",
+                Some("https://gitlab.com"),
+                now,
+            )
+        });
+
+        let mut items = Items::default();
+        items.register(Item {
+            id: "f".to_string(),
+            name: "f".to_string(),
+            kind: ItemKind::WifiNetwork(WifiNetwork {
+                ssid: "home".to_string(),
+                security: WifiSecurity::Wpa2,
+                password: Some("123456".to_string()),
+            }),
+        });
+        items.register(Item {
+            id: "g".to_string(),
+            name: "g".to_string(),
+            kind: ItemKind::Login(Login {
+                username: None,
+                email: None,
+                website: Some("https://twitter.com".to_string()),
+                password: "Gl4cier#Orbit&Pencil".to_string(),
+                totp: None,
+            }),
+        });
+
+        let report = VaultReport::analyse(&passwords, &items, 365, now);
+        assert_eq!(report.entries.len(), 7);
+        assert_eq!(report.weak, 2);
+        assert_eq!(report.reused, 4);
+        assert_eq!(report.old, 1);
+        assert_eq!(report.missing_totp, 2);
+
+        assert_eq!(
+            report.entries[0].findings,
+            vec![Finding::Reused {
+                ids: vec!["b".to_string()]
+            }]
+        );
+        assert_eq!(
+            report.entries[2].findings,
+            vec![
+                Finding::Weak,
+                Finding::Reused {
+                    ids: vec!["f".to_string()]
+                },
+                Finding::Old { days: 400 }
+            ]
+        );
+        assert_eq!(report.entries[3].findings, vec![Finding::MissingTotp]);
+        assert!(report.entries[4].findings.is_empty());
+        assert_eq!(
+            report.entries[5].findings,
+            vec![
+                Finding::Weak,
+                Finding::Reused {
+                    ids: vec!["c".to_string()]
+                }
+            ]
+        );
+        assert_eq!(report.entries[6].findings, vec![Finding::MissingTotp]);
+    }
+}
@@ -1,35 +1,109 @@
 use crate::{
+    attachment, backup, breach,
     device::{self, nonce, register},
-    net::TorGuard,
-    password,
+    export, generator, health, import, item,
+    metrics::RequestMetrics,
+    net::ListenerPolicy,
+    password, report,
+    tor::client_auth,
 };
 use actix_web::web::{self, ServiceConfig};
-use keybear_core::route::v1;
+
+/// Route URL paths of the first version of the API.
+///
+/// Contains all routes from `keybear_core` together with the routes only known by this server.
+pub mod v1 {
+    pub use keybear_core::route::v1::*;
+
+    /// All vault items, regardless of their type.
+    pub const ITEM: &str = "/v1/items";
+    /// Files attached to passwords and items.
+    pub const ATTACHMENT: &str = "/v1/attachments";
+    /// Generating new passwords.
+    pub const GENERATE: &str = "/v1/generate";
+    /// The health report of all passwords.
+    pub const REPORT: &str = "/v1/report";
+    /// Stored passwords occurring in known data breaches.
+    pub const BREACHES: &str = "/v1/breaches";
+    /// Importing passwords exported from other password managers.
+    pub const IMPORT: &str = "/v1/import";
+    /// Exporting the passwords for other password managers.
+    pub const EXPORT: &str = "/v1/export";
+    /// Encrypted backups of all data.
+    pub const BACKUP: &str = "/v1/backup";
+    /// The key of the device to connect to the onion service with client authorization.
+    pub const CLIENT_AUTH: &str = "/v1/client-auth";
+}
+
+/// Health of the server for monitoring.
+pub const HEALTH: &str = "/health";
+/// Version of the server.
+pub const VERSION: &str = "/version";
+/// Prometheus metrics, only served on the metrics address.
+pub const METRICS: &str = "/metrics";
 
 /// Create the actix app with all routes and services.
-pub fn router(cfg: &mut ServiceConfig) {
-    cfg.service(
-        web::scope("/")
-            // Unencrypted calls
-            .service(web::resource(v1::REGISTER).route(web::post().to(register::register)))
-            .service(web::resource(v1::NONCE).route(web::get().to(nonce::nonce)))
-            // Encrypted calls
-            .service(web::resource(v1::VERIFY).route(web::post().to(register::verify)))
-            .service(
-                web::resource(v1::VERIFICATION_DEVICES)
-                    .route(web::get().to(register::verification_devices)),
-            )
-            .service(web::resource(v1::DEVICES).route(web::get().to(device::devices)))
-            .service(
-                web::resource(v1::PASSWORD)
-                    .route(web::get().to(password::get_passwords))
-                    .route(web::post().to(password::post_passwords)),
-            )
-            .service(
-                web::resource(format!("{}/{{id}}", v1::PASSWORD))
-                    .route(web::get().to(password::get_password)),
-            )
-            // Ensure that the communication is only going through the Tor service
-            .guard(TorGuard),
-    );
+///
+/// Only requests from clients allowed by the policy of the listener are handled, except for the
+/// monitoring endpoints.
+pub fn router(cfg: &mut ServiceConfig, policy: ListenerPolicy) {
+    cfg
+        // Monitoring calls, they don't reveal anything about the vault
+        .service(web::resource(HEALTH).route(web::get().to(health::health)))
+        .service(web::resource(VERSION).route(web::get().to(health::version)))
+        .service(
+            web::scope("/")
+                // Unencrypted calls
+                .service(web::resource(v1::REGISTER).route(web::post().to(register::register)))
+                .service(web::resource(v1::NONCE).route(web::get().to(nonce::nonce)))
+                // Encrypted calls
+                .service(web::resource(v1::VERIFY).route(web::post().to(register::verify)))
+                .service(
+                    web::resource(v1::VERIFICATION_DEVICES)
+                        .route(web::get().to(register::verification_devices)),
+                )
+                .service(web::resource(v1::DEVICES).route(web::get().to(device::devices)))
+                .service(
+                    web::resource(v1::PASSWORD)
+                        .route(web::get().to(password::get_passwords))
+                        .route(web::post().to(password::post_passwords)),
+                )
+                .service(
+                    web::resource(format!("{}/{{id}}", v1::PASSWORD))
+                        .route(web::get().to(password::get_password)),
+                )
+                .service(
+                    web::resource(v1::ITEM)
+                        .route(web::get().to(item::get_items))
+                        .route(web::post().to(item::post_items)),
+                )
+                .service(
+                    web::resource(format!("{}/{{id}}", v1::ITEM))
+                        .route(web::get().to(item::get_item))
+                        .route(web::delete().to(item::delete_item)),
+                )
+                .service(
+                    web::resource(v1::ATTACHMENT)
+                        .route(web::get().to(attachment::get_attachments))
+                        .route(web::post().to(attachment::post_attachment)),
+                )
+                .service(
+                    web::resource(format!("{}/{{id}}", v1::ATTACHMENT))
+                        .route(web::get().to(attachment::get_attachment))
+                        .route(web::delete().to(attachment::delete_attachment)),
+                )
+                .service(web::resource(v1::GENERATE).route(web::post().to(generator::generate)))
+                .service(web::resource(v1::REPORT).route(web::post().to(report::report)))
+                .service(web::resource(v1::BREACHES).route(web::get().to(breach::breaches)))
+                .service(web::resource(v1::IMPORT).route(web::post().to(import::post_import)))
+                .service(web::resource(v1::EXPORT).route(web::post().to(export::post_export)))
+                .service(web::resource(v1::BACKUP).route(web::post().to(backup::post_backup)))
+                .service(
+                    web::resource(v1::CLIENT_AUTH).route(web::get().to(client_auth::client_auth)),
+                )
+                // Ensure that the communication is only going through the Tor service or the LAN
+                .guard(policy)
+                // Count the requests per route
+                .wrap(RequestMetrics),
+        );
 }
@@ -0,0 +1,395 @@
+use crate::{
+    app::{self, AppState},
+    config::Config,
+};
+use actix_web::{
+    error::BlockingError,
+    rt::time,
+    web::{self, Data},
+};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use log::{error, info};
+use std::{
+    collections::BTreeSet,
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Format of the directory names of the snapshots, the time is in UTC.
+const SNAPSHOT_NAME_FORMAT: &str = "keybear-%Y%m%dT%H%M%SZ";
+/// Name of the file in the snapshot directory every snapshot is logged to.
+pub const AUDIT_LOG_NAME: &str = "snapshots.log";
+
+/// Which snapshots to keep when removing old ones.
+///
+/// For every period the most recent snapshot of the last periods is kept, the newest snapshot is
+/// always kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    /// Amount of hours to keep a snapshot of.
+    pub hourly: usize,
+    /// Amount of days to keep a snapshot of.
+    pub daily: usize,
+    /// Amount of weeks to keep a snapshot of.
+    pub weekly: usize,
+}
+
+impl Retention {
+    /// Get the retention rules from the config.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            hourly: config.snapshot_keep_hourly(),
+            daily: config.snapshot_keep_daily(),
+            weekly: config.snapshot_keep_weekly(),
+        }
+    }
+
+    /// Select the snapshots that should be kept.
+    pub fn retain(&self, snapshots: &[DateTime<Utc>]) -> BTreeSet<DateTime<Utc>> {
+        // Walk from the newest to the oldest snapshot
+        let mut sorted = snapshots.to_vec();
+        sorted.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut keep = BTreeSet::new();
+        if let Some(newest) = sorted.first() {
+            keep.insert(*newest);
+        }
+
+        // The formats are used to determine in which period a snapshot falls
+        for (amount, period_format) in &[
+            (self.hourly, "%Y-%m-%d %H"),
+            (self.daily, "%Y-%m-%d"),
+            (self.weekly, "%G-W%V"),
+        ] {
+            let mut previous_period = None;
+            let mut kept = 0;
+            for snapshot in &sorted {
+                if kept >= *amount {
+                    break;
+                }
+
+                // Keep the first, so most recent, snapshot of every period
+                let period = snapshot.format(period_format).to_string();
+                if previous_period.as_ref() != Some(&period) {
+                    keep.insert(*snapshot);
+                    kept += 1;
+                    previous_period = Some(period);
+                }
+            }
+        }
+
+        keep
+    }
+}
+
+/// Path of the snapshot taken at a specific time.
+pub fn snapshot_path(directory: &Path, time: DateTime<Utc>) -> PathBuf {
+    directory.join(time.format(SNAPSHOT_NAME_FORMAT).to_string())
+}
+
+/// List all snapshots in the directory with the time they were taken.
+///
+/// Files that aren't named like a snapshot are ignored.
+pub fn list(directory: &Path) -> Result<Vec<(DateTime<Utc>, PathBuf)>> {
+    if !directory.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut snapshots = fs::read_dir(directory)?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let time =
+                NaiveDateTime::parse_from_str(path.file_name()?.to_str()?, SNAPSHOT_NAME_FORMAT)
+                    .ok()?;
+
+            Some((Utc.from_utc_datetime(&time), path))
+        })
+        .collect::<Vec<_>>();
+    snapshots.sort();
+
+    Ok(snapshots)
+}
+
+/// Write a snapshot of the database to the directory.
+///
+/// The storage isn't locked while the database is copied, the responses take the same lock and
+/// would block the threads handling requests. Pending writes are flushed first and sled exports
+/// every tree while it can still be written to. Only the owner can access the directory and the
+/// snapshots, since they contain all secrets.
+pub async fn take(state: &AppState, directory: &Path) -> Result<PathBuf> {
+    let database = match &state.database {
+        Some(database) => database.clone(),
+        None => bail!("Snapshots can only be taken of a database on disk"),
+    };
+
+    app::create_private_dir(directory)?;
+
+    let path = snapshot_path(directory, Utc::now());
+    if path.exists() {
+        bail!("Snapshot {:?} already exists", path);
+    }
+    // Write to a hidden directory first so an interrupted snapshot is never mistaken for a
+    // complete one
+    let partial_path = directory.join(format!(
+        ".{}.partial",
+        path.file_name().unwrap().to_string_lossy()
+    ));
+    if partial_path.exists() {
+        fs::remove_dir_all(&partial_path)?;
+    }
+
+    app::create_private_dir(&partial_path)?;
+
+    // Copying the whole database blocks, so don't do it on the thread handling requests
+    let snapshot_path = partial_path.clone();
+    web::block(move || -> Result<()> {
+        database.flush()?;
+
+        let snapshot = sled::Config::default().path(&snapshot_path).open()?;
+        snapshot.import(database.export());
+        snapshot.flush()?;
+
+        Ok(())
+    })
+    .await
+    .map_err(|err| match err {
+        BlockingError::Error(err) => err,
+        BlockingError::Canceled => anyhow!("Writing snapshot {:?} was canceled", partial_path),
+    })?;
+
+    fs::rename(&partial_path, &path)?;
+
+    Ok(path)
+}
+
+/// Remove the snapshots that aren't kept by the retention rules.
+///
+/// Returns the paths of the removed snapshots.
+pub fn prune(directory: &Path, retention: &Retention) -> Result<Vec<PathBuf>> {
+    let snapshots = list(directory)?;
+    let keep = retention.retain(&snapshots.iter().map(|(time, _)| *time).collect::<Vec<_>>());
+
+    let mut removed = Vec::new();
+    for (time, path) in snapshots {
+        if !keep.contains(&time) {
+            fs::remove_dir_all(&path)?;
+            removed.push(path);
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Take a snapshot and remove the old ones, every step is logged in the audit log.
+pub async fn take_and_prune(
+    state: &AppState,
+    directory: &Path,
+    retention: &Retention,
+) -> Result<()> {
+    let path = take(state, directory).await?;
+    info!("Wrote database snapshot {:?}", path);
+    audit(directory, &format!("created {}", display_name(&path)))?;
+
+    for path in prune(directory, retention)? {
+        info!("Removed database snapshot {:?}", path);
+        audit(directory, &format!("removed {}", display_name(&path)))?;
+    }
+
+    Ok(())
+}
+
+/// Periodically take snapshots as configured, doesn't return when snapshots are enabled.
+pub async fn schedule(state: Data<AppState>) {
+    let directory = match state.config.snapshot_directory() {
+        Some(directory) => directory.to_path_buf(),
+        None => return,
+    };
+    let retention = Retention::from_config(&state.config);
+
+    // The first snapshot is taken immediately
+    let mut interval = time::interval(Duration::from_secs(
+        state.config.snapshot_interval_minutes().max(1) * 60,
+    ));
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = take_and_prune(&state, &directory, &retention).await {
+            error!("Taking database snapshot failed: {}", err);
+
+            if let Err(err) = audit(&directory, &format!("failed {}", err)) {
+                error!("Writing snapshot audit log failed: {}", err);
+            }
+        }
+    }
+}
+
+/// Append a line with the current time to the audit log in the snapshot directory.
+fn audit(directory: &Path, message: &str) -> Result<()> {
+    let path = directory.join(AUDIT_LOG_NAME);
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(&path)
+        .map_err(|err| anyhow!("Opening snapshot audit log {:?} failed: {}", path, err))?;
+    writeln!(file, "{} {}", Utc::now().to_rfc3339(), message)?;
+
+    Ok(())
+}
+
+/// The name of a snapshot as shown in the audit log.
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        app::AppState,
+        config::Config,
+        password::Password,
+        snapshot::{self, Retention, AUDIT_LOG_NAME},
+        store::StorageBuilder,
+        test,
+    };
+    use anyhow::Result;
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use futures::lock::Mutex;
+    use keybear_core::crypto::StaticSecretExt;
+    use std::{fs, os::unix::fs::PermissionsExt};
+    use x25519_dalek::StaticSecret;
+
+    fn hours_ago(now: DateTime<Utc>, hours: i64) -> DateTime<Utc> {
+        now - Duration::hours(hours)
+    }
+
+    #[test]
+    fn retain() {
+        // A Sunday at noon
+        let now = Utc.with_ymd_and_hms(2021, 2, 14, 12, 0, 0).unwrap();
+        // A snapshot every half hour for the last five weeks
+        let snapshots = (0..5 * 7 * 24 * 2)
+            .map(|half_hours| now - Duration::minutes(30 * half_hours))
+            .collect::<Vec<_>>();
+
+        let keep = Retention {
+            hourly: 3,
+            daily: 2,
+            weekly: 2,
+        }
+        .retain(&snapshots);
+        assert_eq!(
+            keep.into_iter().rev().collect::<Vec<_>>(),
+            vec![
+                now,
+                // The last snapshot of the previous hours
+                hours_ago(now, 1) + Duration::minutes(30),
+                hours_ago(now, 2) + Duration::minutes(30),
+                // The last snapshot of the previous day
+                hours_ago(now, 12) - Duration::minutes(30),
+                // The last snapshot of the previous week
+                hours_ago(now, 7 * 24 - 12) - Duration::minutes(30),
+            ]
+        );
+
+        // The newest snapshot is always kept
+        let keep = Retention {
+            hourly: 0,
+            daily: 0,
+            weekly: 0,
+        }
+        .retain(&snapshots);
+        assert_eq!(keep.into_iter().collect::<Vec<_>>(), vec![now]);
+
+        assert!(Retention {
+            hourly: 1,
+            daily: 1,
+            weekly: 1
+        }
+        .retain(&[])
+        .is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn take_and_prune() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let snapshots_dir = dir.path().join("snapshots");
+
+        let (storage, database) =
+            StorageBuilder::new(dir.path().join("db")).build_with_database()?;
+        let state = AppState {
+            secret_key: StaticSecret::new_with_os_rand(),
+            storage: Mutex::new(storage),
+            database: Some(database),
+            metrics: Default::default(),
+            reserved_attachment_size: Default::default(),
+            config: Config::default(),
+        };
+        let mut passwords = state.passwords().await?;
+        passwords.register(Password::new("test", "test_password"));
+        state.set_passwords(&passwords).await?;
+
+        // Create some old snapshots that should be removed
+        let old = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        for hours in 0..3 {
+            fs::create_dir_all(snapshot::snapshot_path(
+                &snapshots_dir,
+                hours_ago(old, hours),
+            ))?;
+        }
+
+        let retention = Retention {
+            hourly: 2,
+            daily: 0,
+            weekly: 0,
+        };
+        {
+            // Taking a snapshot must not wait for the lock on the storage
+            let _storage = state.storage.lock().await;
+            snapshot::take_and_prune(&state, &snapshots_dir, &retention).await?;
+        }
+
+        let snapshots = snapshot::list(&snapshots_dir)?;
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].0, old);
+
+        // The snapshot must contain the same data as the database
+        let restored = AppState {
+            storage: Mutex::new(StorageBuilder::new(&snapshots[1].1).build()?),
+            database: None,
+            metrics: Default::default(),
+            reserved_attachment_size: Default::default(),
+            secret_key: StaticSecret::new_with_os_rand(),
+            config: Config::default(),
+        };
+        assert_eq!(restored.passwords().await?, passwords);
+
+        // Only the owner can access the new snapshot
+        assert_eq!(
+            fs::metadata(&snapshots[1].1)?.permissions().mode() & 0o777,
+            0o700
+        );
+
+        let audit_log = fs::read_to_string(snapshots_dir.join(AUDIT_LOG_NAME))?;
+        assert_eq!(audit_log.lines().count(), 3);
+        assert!(audit_log
+            .lines()
+            .next()
+            .unwrap()
+            .contains(" created keybear-"));
+
+        // Snapshots can't be taken of storage in memory
+        assert!(snapshot::take(&test::app_state(), &snapshots_dir)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+}
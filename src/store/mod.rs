@@ -1,6 +1,8 @@
+use crate::app;
 use actix_storage::{Format, Storage};
 use actix_storage_sled::{SledConfig, SledStore};
 use anyhow::Result;
+use sled::Db;
 use std::path::PathBuf;
 
 /// Structure to setup the [`Storage`](./struct.Storage.html) struct for encoding & decoding messages.
@@ -22,12 +24,24 @@ impl StorageBuilder {
 
     /// Construct the storage struct.
     pub fn build(self) -> Result<Storage> {
-        Ok(Storage::build()
-            .store(SledStore::from_db(
-                SledConfig::default().path(self.database_path).open()?,
-            ))
+        Ok(self.build_with_database()?.0)
+    }
+
+    /// Construct the storage struct, also returning a handle to the underlying database.
+    ///
+    /// The handle can be used for operations the storage doesn't expose, like taking snapshots.
+    /// A new database directory is created so only the owner can access it.
+    pub fn build_with_database(self) -> Result<(Storage, Db)> {
+        app::create_private_dir(&self.database_path)?;
+
+        let database = SledConfig::default().path(self.database_path).open()?;
+
+        let storage = Storage::build()
+            .store(SledStore::from_db(database.clone()))
             .format(Format::Json)
-            .finish())
+            .finish();
+
+        Ok((storage, database))
     }
 }
 
@@ -35,6 +49,7 @@ impl StorageBuilder {
 mod tests {
     use crate::store::StorageBuilder;
     use anyhow::Result;
+    use std::{fs, os::unix::fs::PermissionsExt};
 
     #[test]
     fn new_database() -> Result<()> {
@@ -44,6 +59,15 @@ mod tests {
         // Construct the storage with a new database.
         let _storage = StorageBuilder::new(dir.path().join("test.db")).build()?;
 
+        // Only the owner can access the database
+        assert_eq!(
+            fs::metadata(dir.path().join("test.db"))?
+                .permissions()
+                .mode()
+                & 0o777,
+            0o700
+        );
+
         // Close the directory
         dir.close()?;
 
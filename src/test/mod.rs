@@ -1,7 +1,12 @@
 use crate::{
     app::{self, AppState},
-    body::EncryptedBody,
-    device::nonce::SerializableNonce,
+    body::{ChunkCipher, EncryptedBody},
+    config::Config,
+    device::{
+        nonce::SerializableNonce,
+        register::{self, RegisterResponse},
+    },
+    net::ListenerPolicy,
 };
 use actix_http::Request;
 use actix_service::ServiceFactory;
@@ -10,12 +15,13 @@ use actix_storage_hashmap::HashMapStore;
 use actix_web::{
     body::{Body, MessageBody},
     dev::{Service, ServiceRequest, ServiceResponse},
-    http::Method,
+    http::{Method, StatusCode},
     test::{self, TestRequest},
-    web::Data,
-    App, Error,
+    web::{BytesMut, Data, Json},
+    App, Error, Result as WebResult,
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use futures::lock::Mutex;
 use keybear_core::{
     crypto::{self, PublicKey, SharedSecret, StaticSecret, StaticSecretExt},
     route::v1,
@@ -23,7 +29,7 @@ use keybear_core::{
     CLIENT_ID_HEADER,
 };
 use serde::{de::DeserializeOwned, Serialize};
-use std::{fmt::Debug, sync::Mutex};
+use std::fmt::Debug;
 
 /// A client containing the keys to perform test requests.
 pub struct TestClient {
@@ -40,9 +46,19 @@ impl TestClient {
     pub async fn setup() -> (
         impl Service<Request = Request, Response = ServiceResponse<Body>, Error = Error>,
         Self,
+    ) {
+        Self::setup_with_config(Config::default()).await
+    }
+
+    /// Setup a server with a custom configuration and a registered client.
+    pub async fn setup_with_config(
+        config: Config,
+    ) -> (
+        impl Service<Request = Request, Response = ServiceResponse<Body>, Error = Error>,
+        Self,
     ) {
         // Setup the test service
-        let mut app = test::init_service(fill_app(App::new())).await;
+        let mut app = test::init_service(fill_app_with_config(App::new(), config)).await;
 
         // Create a public and a secret key for the device
         let secret_key = StaticSecret::new_with_os_rand();
@@ -106,7 +122,7 @@ impl TestClient {
         let body = test::read_body(resp).await;
 
         // Decrypt it
-        crypto::decrypt(&self.to_shared_secret(), &nonce.to_nonce(), &body).unwrap()
+        crypto::decrypt(&self.to_shared_secret(), nonce.to_nonce(), &body).unwrap()
     }
 
     /// Perform a request with a body and get the result back.
@@ -130,7 +146,7 @@ impl TestClient {
         // Create an encrypted JSON payload
         let payload =
             EncryptedBody::new_with_key_and_client_id(body, self.to_shared_secret(), &self.id)
-                .into_bytes(&nonce.to_nonce())
+                .into_bytes(nonce.to_nonce())
                 .unwrap();
 
         // Build a request to test our function
@@ -157,7 +173,146 @@ impl TestClient {
         let body = test::read_body(resp).await;
 
         // Decrypt it
-        crypto::decrypt(&self.to_shared_secret(), &nonce.to_nonce(), &body).unwrap()
+        crypto::decrypt(&self.to_shared_secret(), nonce.to_nonce(), &body).unwrap()
+    }
+
+    /// Perform a request with a body encrypted in chunks and get the result back.
+    pub async fn perform_encrypted_chunked_request_with_body<S, B, E, T>(
+        &self,
+        app: &mut S,
+        path: &str,
+        method: Method,
+        chunks: &[&[u8]],
+    ) -> T
+    where
+        S: Service<Request = Request, Response = ServiceResponse<B>, Error = E>,
+        B: MessageBody + Unpin,
+        E: Debug,
+        T: DeserializeOwned,
+    {
+        // Get the nonce
+        let nonce = self.perform_nonce_request(app).await.unwrap();
+
+        // Perform the request and get the response
+        let resp = app
+            .call(self.chunked_request(path, method, &nonce, chunks))
+            .await
+            .unwrap();
+
+        // Ensure that the path is accessed correctly
+        assert!(
+            resp.status().is_success(),
+            "Incorrect response status \"{}\" with body: {:?}",
+            resp.status().canonical_reason().unwrap(),
+            test::read_body(resp).await,
+        );
+
+        // Extract the encrypted body
+        let body = test::read_body(resp).await;
+
+        // Decrypt it
+        crypto::decrypt(&self.to_shared_secret(), nonce.to_nonce(), &body).unwrap()
+    }
+
+    /// Perform a request with a body encrypted in chunks and only get the status code back.
+    pub async fn perform_encrypted_chunked_request_with_body_status<S, B, E>(
+        &self,
+        app: &mut S,
+        path: &str,
+        method: Method,
+        chunks: &[&[u8]],
+    ) -> StatusCode
+    where
+        S: Service<Request = Request, Response = ServiceResponse<B>, Error = E>,
+        B: MessageBody + Unpin,
+        E: Debug,
+    {
+        // Get the nonce
+        let nonce = self.perform_nonce_request(app).await.unwrap();
+
+        // Perform the request and get the response
+        app.call(self.chunked_request(path, method, &nonce, chunks))
+            .await
+            .unwrap()
+            .status()
+    }
+
+    /// Build a request with a body encrypted in chunks.
+    fn chunked_request(
+        &self,
+        path: &str,
+        method: Method,
+        nonce: &SerializableNonce,
+        chunks: &[&[u8]],
+    ) -> Request {
+        // Encrypt every chunk into a frame
+        let mut cipher = ChunkCipher::new(&self.to_shared_secret(), nonce.to_nonce());
+        let payload = chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                cipher
+                    .encrypt_chunk(chunk, index + 1 == chunks.len())
+                    .unwrap()
+            })
+            .collect::<Vec<_>>()
+            .concat();
+
+        TestRequest::with_uri(path)
+            .method(method)
+            .header(CLIENT_ID_HEADER, self.id.as_str())
+            .set_payload(payload)
+            // The peer address must be localhost otherwise the Tor guard triggers
+            .peer_addr("127.0.0.1:1234".parse().unwrap())
+            .to_request()
+    }
+
+    /// Perform a request without a body and get the chunked result back.
+    pub async fn perform_encrypted_chunked_request<S, B, E>(
+        &self,
+        app: &mut S,
+        path: &str,
+        method: Method,
+    ) -> Vec<u8>
+    where
+        S: Service<Request = Request, Response = ServiceResponse<B>, Error = E>,
+        B: MessageBody + Unpin,
+        E: Debug,
+    {
+        // Get the nonce
+        let nonce = self.perform_nonce_request(app).await.unwrap();
+
+        // Build a request to test our function
+        let req = TestRequest::with_uri(path)
+            .header(CLIENT_ID_HEADER, self.id.as_str())
+            .method(method)
+            // The peer address must be localhost otherwise the Tor guard triggers
+            .peer_addr("127.0.0.1:1234".parse().unwrap())
+            .to_request();
+
+        // Perform the request and get the response
+        let resp = app.call(req).await.unwrap();
+
+        // Ensure that the path is accessed correctly
+        assert!(resp.status().is_success());
+
+        // Extract the encrypted body
+        let mut body = BytesMut::from(&test::read_body(resp).await[..]);
+
+        // Decrypt all the chunks
+        let mut cipher = ChunkCipher::new(&self.to_shared_secret(), nonce.to_nonce());
+        let mut data = Vec::new();
+        while let Some((chunk, last)) = cipher.decrypt_frame(&mut body).unwrap() {
+            data.extend_from_slice(&chunk);
+
+            if last {
+                assert!(body.is_empty(), "Received chunks after the last chunk");
+
+                return data;
+            }
+        }
+
+        panic!("Response ended before the last chunk");
     }
 
     /// Generate a shared secret key from the server and client keys.
@@ -299,14 +454,64 @@ where
         InitError = (),
     >,
 {
-    app::fill_app(app, &app_state())
+    fill_app_with_config(app, Config::default())
+}
+
+/// Generate an app with all routes and a custom configuration.
+pub fn fill_app_with_config<T, B>(app: App<T, B>, config: Config) -> App<T, B>
+where
+    B: MessageBody,
+    T: ServiceFactory<
+        Config = (),
+        Request = ServiceRequest,
+        Response = ServiceResponse<B>,
+        Error = Error,
+        InitError = (),
+    >,
+{
+    let app_state = app_state_with_config(config);
+    let policy = ListenerPolicy::tor(&app_state.config);
+
+    app::fill_app(app, &app_state, policy)
 }
 
 /// Generate a default application state.
 pub fn app_state() -> Data<AppState> {
+    app_state_with_config(Config::default())
+}
+
+/// Generate an application state with a custom configuration.
+pub fn app_state_with_config(config: Config) -> Data<AppState> {
     Data::new(AppState {
         secret_key: StaticSecret::new_with_os_rand(),
         // Use a simple in-memory hashmap storage
         storage: Mutex::new(Storage::build().store(HashMapStore::default()).finish()),
+        database: None,
+        metrics: Default::default(),
+        reserved_attachment_size: Default::default(),
+        config,
     })
 }
+
+/// Register a device with a new key directly through the handler.
+///
+/// It's awaiting verification when it's not the first device.
+pub async fn register_device(state: &Data<AppState>, name: &str) -> Result<RegisterResponse> {
+    register_device_with_request(state, TestRequest::default(), name)
+        .await
+        .map_err(|err| anyhow!("{}", err))
+}
+
+/// Register a device with a new key directly through the handler with a custom request.
+pub async fn register_device_with_request(
+    state: &Data<AppState>,
+    request: TestRequest,
+    name: &str,
+) -> WebResult<RegisterResponse> {
+    let device =
+        RegisterDeviceRequest::new(name, &PublicKey::from(&StaticSecret::new_with_os_rand()));
+
+    register::register(request.to_http_request(), Json(device), state.clone())
+        .await
+        .map(|response| response.into_inner())
+}
@@ -0,0 +1,328 @@
+use crate::{app::AppState, body::EncryptedBody, config::Config, device::Device};
+use actix_web::{
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    web::Data,
+    HttpRequest, Result as WebResult,
+};
+use anyhow::{anyhow, bail, Result};
+use data_encoding::BASE32_NOPAD;
+use keybear_core::{crypto::StaticSecretExt, CLIENT_ID_HEADER};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::{convert::TryInto, fs, path::PathBuf};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Name of the directory with the public keys of the authorized clients.
+pub const AUTHORIZED_CLIENTS_DIR: &str = "authorized_clients";
+/// Name of the client authorized by the pairing URI.
+pub const PAIRING_CLIENT_NAME: &str = "pairing";
+
+/// The private key a device needs to connect to the onion service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientAuthResponse {
+    /// The base32 encoded x25519 private key.
+    pub private_key: String,
+}
+
+/// An x25519 key pair for onion service client authorization.
+pub struct ClientAuthKey {
+    /// The private key.
+    secret: StaticSecret,
+}
+
+impl ClientAuthKey {
+    /// Generate a new random key.
+    pub fn generate() -> Self {
+        Self {
+            secret: StaticSecret::new_with_os_rand(),
+        }
+    }
+
+    /// Load the key from a base32 encoded private key.
+    pub fn from_private_key(private_key: &str) -> Result<Self> {
+        let bytes: [u8; 32] = BASE32_NOPAD
+            .decode(private_key.as_bytes())
+            .map_err(|err| anyhow!("Client authorization key is invalid: {}", err))?
+            .try_into()
+            .map_err(|_| anyhow!("Client authorization key has an invalid length"))?;
+
+        Ok(Self {
+            secret: StaticSecret::from(bytes),
+        })
+    }
+
+    /// The base32 encoded private key.
+    pub fn private_key(&self) -> String {
+        BASE32_NOPAD.encode(&self.secret.to_bytes())
+    }
+
+    /// The base32 encoded public key.
+    pub fn public_key(&self) -> String {
+        BASE32_NOPAD.encode(PublicKey::from(&self.secret).as_bytes())
+    }
+
+    /// Contents of the `.auth` file in the authorized clients directory of the onion service.
+    pub fn auth_file(&self) -> String {
+        format!("descriptor:x25519:{}\n", self.public_key())
+    }
+
+    /// Contents of the `.auth_private` file a Tor client needs to connect to the onion service.
+    pub fn auth_private_file(&self, onion_hostname: &str) -> String {
+        format!(
+            "{}:descriptor:x25519:{}\n",
+            onion_hostname.trim_end_matches(".onion"),
+            self.private_key()
+        )
+    }
+}
+
+/// Directory with the public keys of the authorized clients.
+///
+/// When the onion service is created through the Tor control port it's kept in the onion directory
+/// of keybear, otherwise it's in the hidden service directory of Tor.
+pub fn authorized_clients_dir(config: &Config) -> PathBuf {
+    if config.tor_control_address().is_some() {
+        config.tor_onion_dir().join(AUTHORIZED_CLIENTS_DIR)
+    } else {
+        config.tor_hidden_service_dir().join(AUTHORIZED_CLIENTS_DIR)
+    }
+}
+
+/// Allow a client to connect to the onion service.
+///
+/// Tor only picks up the change when it's reloaded.
+pub fn authorize(config: &Config, name: &str, key: &ClientAuthKey) -> Result<()> {
+    let dir = authorized_clients_dir(config);
+    fs::create_dir_all(&dir).map_err(|err| {
+        anyhow!(
+            "Creating authorized clients directory {:?} failed: {}",
+            dir,
+            err
+        )
+    })?;
+
+    let path = dir.join(format!("{}.auth", name));
+    fs::write(&path, key.auth_file())
+        .map_err(|err| anyhow!("Writing client authorization {:?} failed: {}", path, err))?;
+
+    // The names are device IDs, which are kept out of the regular logs
+    info!("Authorized a client for the onion service");
+    debug!("Authorized client \"{}\" for the onion service", name);
+
+    Ok(())
+}
+
+/// Remove the authorization of a client, nothing happens when it isn't authorized.
+pub fn revoke(config: &Config, name: &str) -> Result<()> {
+    let path = authorized_clients_dir(config).join(format!("{}.auth", name));
+    if path.exists() {
+        fs::remove_file(&path)
+            .map_err(|err| anyhow!("Removing client authorization {:?} failed: {}", path, err))?;
+
+        info!("Revoked a client for the onion service");
+        debug!("Revoked client \"{}\" for the onion service", name);
+    }
+
+    Ok(())
+}
+
+/// The base32 encoded public keys of all authorized clients.
+pub fn authorized_public_keys(config: &Config) -> Result<Vec<String>> {
+    let dir = authorized_clients_dir(config);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut keys = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("auth") {
+            continue;
+        }
+
+        let contents = fs::read_to_string(&path)?;
+        match contents.trim().strip_prefix("descriptor:x25519:") {
+            Some(key) => keys.push(key.to_string()),
+            None => bail!("Client authorization {:?} is invalid", path),
+        }
+    }
+    keys.sort();
+
+    Ok(keys)
+}
+
+/// Authorize a registered device when client authorization is enabled.
+///
+/// The device connects with its own key from now on, so the key of the pairing URI is revoked.
+pub fn authorize_device(config: &Config, device: &Device) -> Result<()> {
+    match device.client_auth_key() {
+        Some(private_key) if config.tor_client_authorization() => {
+            authorize(
+                config,
+                device.id(),
+                &ClientAuthKey::from_private_key(private_key)?,
+            )?;
+
+            revoke(config, PAIRING_CLIENT_NAME)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Get the client authorization key of the requesting device.
+pub async fn client_auth(
+    request: HttpRequest,
+    state: Data<AppState>,
+) -> WebResult<EncryptedBody<ClientAuthResponse>> {
+    let id = request
+        .headers()
+        .get(CLIENT_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .ok_or_else(|| ErrorBadRequest(format!("\"{}\" header is missing", CLIENT_ID_HEADER)))?;
+
+    // The response is encrypted for the device in the header so only that device can read it
+    let device = state.device(id).await.map_err(ErrorInternalServerError)?;
+    match device.client_auth_key() {
+        Some(private_key) => Ok(EncryptedBody::new(ClientAuthResponse {
+            private_key: private_key.to_string(),
+        })),
+        None => Err(ErrorNotFound(
+            "Client authorization is disabled for this device",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        admin,
+        config::Config,
+        test,
+        tor::client_auth::{self, ClientAuthKey, PAIRING_CLIENT_NAME},
+    };
+    use actix_web::test::TestRequest;
+    use anyhow::{anyhow, Result};
+    use data_encoding::BASE32_NOPAD;
+    use keybear_core::CLIENT_ID_HEADER;
+    use std::{fs, path::Path};
+
+    fn config(dir: &Path) -> Result<Config> {
+        Config::from_raw_str(&format!(
+            r#"
+            [tor]
+            hidden_service_dir = {:?}
+            client_authorization = true
+            "#,
+            dir
+        ))
+    }
+
+    #[test]
+    fn key() -> Result<()> {
+        let key = ClientAuthKey::generate();
+        // Tor expects 52 characters for the 32 bytes
+        assert_eq!(key.public_key().len(), 52);
+        assert_eq!(BASE32_NOPAD.decode(key.public_key().as_bytes())?.len(), 32);
+
+        let loaded = ClientAuthKey::from_private_key(&key.private_key())?;
+        assert_eq!(loaded.public_key(), key.public_key());
+        assert!(ClientAuthKey::from_private_key("invalid").is_err());
+
+        assert_eq!(
+            key.auth_file(),
+            format!("descriptor:x25519:{}\n", key.public_key())
+        );
+        assert_eq!(
+            key.auth_private_file("abcdef.onion"),
+            format!("abcdef:descriptor:x25519:{}\n", key.private_key())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn authorize_and_revoke() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = config(dir.path())?;
+        assert!(client_auth::authorized_public_keys(&config)?.is_empty());
+
+        let first = ClientAuthKey::generate();
+        let second = ClientAuthKey::generate();
+        client_auth::authorize(&config, "first", &first)?;
+        client_auth::authorize(&config, "second", &second)?;
+        assert_eq!(
+            fs::read_to_string(dir.path().join("authorized_clients/first.auth"))?,
+            first.auth_file()
+        );
+
+        let mut expected = vec![first.public_key(), second.public_key()];
+        expected.sort();
+        assert_eq!(client_auth::authorized_public_keys(&config)?, expected);
+
+        client_auth::revoke(&config, "first")?;
+        // Revoking twice isn't a problem
+        client_auth::revoke(&config, "first")?;
+        assert_eq!(
+            client_auth::authorized_public_keys(&config)?,
+            vec![second.public_key()]
+        );
+
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn devices() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let state = test::app_state_with_config(config(dir.path())?);
+        let auth_file = |id: &str| dir.path().join(format!("authorized_clients/{}.auth", id));
+
+        // The first device is authorized immediately, replacing the key of the pairing URI
+        client_auth::authorize(
+            &state.config,
+            PAIRING_CLIENT_NAME,
+            &ClientAuthKey::generate(),
+        )?;
+        let first = test::register_device(&state, "first")
+            .await?
+            .id()
+            .to_string();
+        assert!(auth_file(&first).exists());
+        assert!(!auth_file(PAIRING_CLIENT_NAME).exists());
+
+        // Other devices only after they are approved
+        client_auth::authorize(
+            &state.config,
+            PAIRING_CLIENT_NAME,
+            &ClientAuthKey::generate(),
+        )?;
+        let second = test::register_device(&state, "second")
+            .await?
+            .id()
+            .to_string();
+        assert!(!auth_file(&second).exists());
+        assert!(auth_file(PAIRING_CLIENT_NAME).exists());
+        admin::approve_device(&state, &second, None).await?;
+        assert!(auth_file(&second).exists());
+        assert!(!auth_file(PAIRING_CLIENT_NAME).exists());
+
+        // The device can get its own key
+        let request = TestRequest::default()
+            .header(CLIENT_ID_HEADER, second.as_str())
+            .to_http_request();
+        let response = client_auth::client_auth(request, state.clone())
+            .await
+            .map_err(|err| anyhow!("{}", err))?
+            .into_inner();
+        assert_eq!(
+            ClientAuthKey::from_private_key(&response.private_key)?.auth_file(),
+            fs::read_to_string(auth_file(&second))?
+        );
+
+        // Revoked devices can't connect anymore
+        admin::revoke_device(&state, &second).await?;
+        assert!(!auth_file(&second).exists());
+        assert_eq!(client_auth::authorized_public_keys(&state.config)?.len(), 1);
+
+        Ok(())
+    }
+}
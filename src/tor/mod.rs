@@ -0,0 +1,462 @@
+pub mod client_auth;
+
+use crate::{app, config::Config};
+use anyhow::{anyhow, bail, Result};
+use log::{debug, info, warn};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    path::Path,
+};
+
+/// Name of the file in the onion directory containing the private key of the onion service.
+pub const ONION_PRIVATE_KEY_FILE: &str = "private_key";
+/// Name of the file in the onion directory containing the hostname of the onion service.
+pub const ONION_HOSTNAME_FILE: &str = "hostname";
+
+/// A connection with the control port of Tor.
+///
+/// Onion services added through the connection are removed by Tor when it's closed.
+pub struct ControlConnection {
+    /// Buffered reader of the replies.
+    reader: BufReader<TcpStream>,
+    /// Stream to write the commands to.
+    writer: TcpStream,
+}
+
+impl ControlConnection {
+    /// Connect to the control port.
+    pub fn connect(address: &str) -> Result<Self> {
+        let stream = TcpStream::connect(address)
+            .map_err(|err| anyhow!("Connecting to Tor control port {} failed: {}", address, err))?;
+
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    /// Authenticate with a password, or otherwise the cookie file or no authentication at all.
+    pub fn authenticate(&mut self, password: Option<&str>) -> Result<()> {
+        if let Some(password) = password {
+            return self
+                .command(&format!("AUTHENTICATE {}", quote(password)))
+                .map(|_| ());
+        }
+
+        // Find out which authentication methods are supported
+        let info = self.command("PROTOCOLINFO 1")?;
+        let auth = info
+            .iter()
+            .find(|line| line.starts_with("AUTH "))
+            .ok_or_else(|| anyhow!("Tor didn't report the authentication methods"))?;
+        let methods = reply_value(auth, "METHODS").unwrap_or_default();
+        let methods = methods.split(',').collect::<Vec<_>>();
+
+        if methods.contains(&"NULL") {
+            self.command("AUTHENTICATE").map(|_| ())
+        } else if methods.contains(&"COOKIE") {
+            let cookie_file = reply_value(auth, "COOKIEFILE")
+                .ok_or_else(|| anyhow!("Tor didn't report the authentication cookie file"))?;
+            let cookie = fs::read(&cookie_file).map_err(|err| {
+                anyhow!(
+                    "Reading Tor authentication cookie {:?} failed: {}",
+                    cookie_file,
+                    err
+                )
+            })?;
+
+            self.command(&format!("AUTHENTICATE {}", to_hex(&cookie)))
+                .map(|_| ())
+        } else {
+            bail!(
+                "Tor control port requires unsupported authentication methods \"{}\", configure a control password",
+                methods.join(",")
+            )
+        }
+    }
+
+    /// Add an onion service forwarding the virtual port to the target address.
+    ///
+    /// A new key is generated when none is passed. When client authorization keys are passed only
+    /// those clients can connect. Returns the service ID and the private key.
+    pub fn add_onion(
+        &mut self,
+        private_key: Option<&str>,
+        virtual_port: u16,
+        target: &str,
+        client_auth_keys: &[String],
+    ) -> Result<(String, String)> {
+        let key = private_key.unwrap_or("NEW:ED25519-V3");
+        let mut command = format!("ADD_ONION {}", key);
+        if !client_auth_keys.is_empty() {
+            command.push_str(" Flags=V3Auth");
+        }
+        command.push_str(&format!(" Port={},{}", virtual_port, target));
+        for client_auth_key in client_auth_keys {
+            command.push_str(&format!(" ClientAuthV3={}", client_auth_key));
+        }
+        let reply = self.command(&command)?;
+
+        let service_id = reply
+            .iter()
+            .find_map(|line| line.strip_prefix("ServiceID="))
+            .ok_or_else(|| anyhow!("Tor didn't return the onion service ID"))?
+            .to_string();
+        // The private key is only returned for new keys
+        let private_key = match reply
+            .iter()
+            .find_map(|line| line.strip_prefix("PrivateKey="))
+        {
+            Some(private_key) => private_key.to_string(),
+            None => key.to_string(),
+        };
+
+        Ok((service_id, private_key))
+    }
+
+    /// Send a command and read the reply lines, an error is returned when it's not successful.
+    fn command(&mut self, command: &str) -> Result<Vec<String>> {
+        // Don't log secrets
+        debug!(
+            "Sending Tor control command \"{}\"",
+            command.split(' ').next().unwrap_or_default()
+        );
+
+        self.writer
+            .write_all(format!("{}\r\n", command).as_bytes())?;
+        self.writer.flush()?;
+
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                bail!("Tor closed the control connection");
+            }
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+            if line.len() < 4 {
+                bail!("Invalid reply from Tor control port: \"{}\"", line);
+            }
+
+            let (status, separator, text) = (&line[..3], &line[3..4], &line[4..]);
+            if status != "250" {
+                bail!("Tor control command failed: {} {}", status, text);
+            }
+            lines.push(text.to_string());
+
+            // A space marks the last line of the reply
+            if separator == " " {
+                return Ok(lines);
+            }
+        }
+    }
+}
+
+/// Create the onion service through the control port as configured.
+///
+/// The private key and the hostname are saved in the onion directory so the same address is used
+/// every time. The returned connection must be kept open for the onion service to stay available.
+pub fn provision(config: &Config) -> Result<Option<ControlConnection>> {
+    let address = match config.tor_control_address() {
+        Some(address) => address,
+        None => return Ok(None),
+    };
+
+    let mut connection = ControlConnection::connect(address)?;
+    connection.authenticate(config.tor_control_password())?;
+
+    let onion_dir = config.tor_onion_dir();
+    let key_path = onion_dir.join(ONION_PRIVATE_KEY_FILE);
+    let private_key = if key_path.exists() {
+        Some(fs::read_to_string(&key_path)?.trim().to_string())
+    } else {
+        None
+    };
+
+    let client_auth_keys = if config.tor_client_authorization() {
+        let keys = client_auth::authorized_public_keys(config)?;
+        if keys.is_empty() {
+            warn!("Client authorization is enabled but no clients are authorized yet, the onion service is reachable by anyone knowing the address");
+        }
+
+        keys
+    } else {
+        Vec::new()
+    };
+
+    let (service_id, private_key) = connection.add_onion(
+        private_key.as_deref(),
+        config.tor_onion_port(),
+        // Tor connects to the first address the server listens on
+        &config.listen_addresses()[0].to_string(),
+        &client_auth_keys,
+    )?;
+    let hostname = format!("{}.onion", service_id);
+
+    save(&onion_dir, &private_key, &hostname)?;
+
+    info!(
+        "Onion service available at {}:{}",
+        hostname,
+        config.tor_onion_port()
+    );
+
+    Ok(Some(connection))
+}
+
+/// Save the private key and the hostname in the onion directory, only the owner can access them.
+fn save(onion_dir: &Path, private_key: &str, hostname: &str) -> Result<()> {
+    app::create_private_dir(onion_dir)?;
+    app::write_private_file(
+        &onion_dir.join(ONION_PRIVATE_KEY_FILE),
+        private_key.as_bytes(),
+    )?;
+    fs::write(
+        onion_dir.join(ONION_HOSTNAME_FILE),
+        format!("{}\n", hostname),
+    )?;
+
+    Ok(())
+}
+
+/// Get the value of a `KEY=value` or `KEY="value"` pair from a reply line.
+fn reply_value(line: &str, key: &str) -> Option<String> {
+    let start = line.find(&format!("{}=", key))? + key.len() + 1;
+    let rest = &line[start..];
+
+    match rest.strip_prefix('"') {
+        Some(quoted) => {
+            // Unescape the quoted string until the closing quote
+            let mut value = String::new();
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => return Some(value),
+                    '\\' => value.push(chars.next()?),
+                    c => value.push(c),
+                }
+            }
+
+            None
+        }
+        None => rest.split(' ').next().map(|value| value.to_string()),
+    }
+}
+
+/// Quote a string for the control protocol.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Encode bytes as uppercase hexadecimal.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::Config,
+        pair::PairingUri,
+        tor::{
+            self,
+            client_auth::{self, ClientAuthKey},
+            ONION_HOSTNAME_FILE, ONION_PRIVATE_KEY_FILE,
+        },
+    };
+    use anyhow::Result;
+    use keybear_core::crypto::StaticSecretExt;
+    use std::{
+        fs,
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        os::unix::fs::PermissionsExt,
+        path::Path,
+        thread::{self, JoinHandle},
+    };
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    /// Start a fake control port answering every expected command with the reply.
+    ///
+    /// Returns the address and a handle that fails when a different command is received.
+    fn fake_control_port(
+        conversation: Vec<(&'static str, String)>,
+    ) -> Result<(String, JoinHandle<()>)> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?.to_string();
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            for (expected, reply) in conversation {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                assert!(
+                    line.starts_with(expected),
+                    "Expected \"{}\" but got \"{}\"",
+                    expected,
+                    line
+                );
+
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+
+        Ok((address, handle))
+    }
+
+    fn config(dir: &Path, control_address: &str, password: Option<&str>) -> Result<Config> {
+        Config::from_raw_str(&format!(
+            r#"
+            key_path = {:?}
+
+            [tor]
+            control_address = {:?}
+            {}
+            "#,
+            dir.join("key"),
+            control_address,
+            password
+                .map(|password| format!("control_password = {:?}", password))
+                .unwrap_or_default()
+        ))
+    }
+
+    #[test]
+    fn provision() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+
+        // The first time a new key is generated
+        let (address, handle) = fake_control_port(vec![
+            (
+                "AUTHENTICATE \"pass\\\"word\"\r\n",
+                "250 OK\r\n".to_string(),
+            ),
+            (
+                "ADD_ONION NEW:ED25519-V3 Port=5219,127.0.0.1:52477\r\n",
+                "250-ServiceID=abcdef\r\n250-PrivateKey=ED25519-V3:secret\r\n250 OK\r\n"
+                    .to_string(),
+            ),
+        ])?;
+        let config = config(dir.path(), &address, Some("pass\"word"))?;
+        let connection = tor::provision(&config)?;
+        assert!(connection.is_some());
+        handle.join().unwrap();
+
+        let onion_dir = config.tor_onion_dir();
+        assert_eq!(
+            fs::read_to_string(onion_dir.join(ONION_PRIVATE_KEY_FILE))?,
+            "ED25519-V3:secret"
+        );
+        // Only the owner can read the private key
+        for (path, mode) in &[
+            (onion_dir.to_path_buf(), 0o700),
+            (onion_dir.join(ONION_PRIVATE_KEY_FILE), 0o600),
+        ] {
+            assert_eq!(fs::metadata(path)?.permissions().mode() & 0o777, *mode);
+        }
+        assert_eq!(
+            fs::read_to_string(onion_dir.join(ONION_HOSTNAME_FILE))?,
+            "abcdef.onion\n"
+        );
+
+        // The pairing URI uses the provisioned onion service
+        let uri = PairingUri::from_config(
+            &config,
+            &PublicKey::from(&StaticSecret::new_with_os_rand()),
+            None,
+        )?;
+        assert_eq!((uri.host.as_str(), uri.port), ("abcdef.onion", 5219));
+
+        // The second time the saved key is used, authenticating with the cookie
+        let cookie_path = dir.path().join("control_auth_cookie");
+        fs::write(&cookie_path, [0x01, 0xAB])?;
+        let protocol_info = format!(
+            "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=COOKIE,SAFECOOKIE COOKIEFILE={:?}\r\n250 OK\r\n",
+            cookie_path
+        );
+        let (address, handle) = fake_control_port(vec![
+            ("PROTOCOLINFO 1\r\n", protocol_info),
+            ("AUTHENTICATE 01AB\r\n", "250 OK\r\n".to_string()),
+            (
+                "ADD_ONION ED25519-V3:secret Port=5219,127.0.0.1:52477\r\n",
+                "250-ServiceID=abcdef\r\n250 OK\r\n".to_string(),
+            ),
+        ])?;
+        let config = self::config(dir.path(), &address, None)?;
+        tor::provision(&config)?;
+        handle.join().unwrap();
+        assert_eq!(
+            fs::read_to_string(onion_dir.join(ONION_PRIVATE_KEY_FILE))?,
+            "ED25519-V3:secret"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn client_authorization() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+
+        let (address, handle) = fake_control_port(vec![
+            ("AUTHENTICATE", "250 OK\r\n".to_string()),
+            (
+                "ADD_ONION NEW:ED25519-V3 Flags=V3Auth Port=5219,127.0.0.1:52477 ClientAuthV3=",
+                "250-ServiceID=abcdef\r\n250-PrivateKey=ED25519-V3:secret\r\n250 OK\r\n"
+                    .to_string(),
+            ),
+        ])?;
+        let config = Config::from_raw_str(&format!(
+            r#"
+            key_path = {:?}
+
+            [tor]
+            control_address = {:?}
+            control_password = "password"
+            client_authorization = true
+            "#,
+            dir.path().join("key"),
+            address
+        ))?;
+        client_auth::authorize(&config, "device", &ClientAuthKey::generate())?;
+
+        tor::provision(&config)?;
+        handle.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn failed_authentication() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+
+        let (address, handle) = fake_control_port(vec![(
+            "AUTHENTICATE",
+            "515 Authentication failed: Password did not match HashedControlPassword value from configuration\r\n".to_string(),
+        )])?;
+        assert!(tor::provision(&config(dir.path(), &address, Some("wrong"))?).is_err());
+        handle.join().unwrap();
+
+        // Nothing should be saved
+        assert!(!config(dir.path(), &address, None)?.tor_onion_dir().exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reply_value() {
+        let line =
+            r#"AUTH METHODS=COOKIE,SAFECOOKIE COOKIEFILE="/run/tor/control \"auth\" cookie""#;
+        assert_eq!(
+            tor::reply_value(line, "METHODS").as_deref(),
+            Some("COOKIE,SAFECOOKIE")
+        );
+        assert_eq!(
+            tor::reply_value(line, "COOKIEFILE").as_deref(),
+            Some("/run/tor/control \"auth\" cookie")
+        );
+        assert_eq!(tor::reply_value(line, "OTHER"), None);
+    }
+}
@@ -0,0 +1,188 @@
+use actix_web::http::{Method, StatusCode};
+use keybear_core::types::{PublicPassword, RegisterPasswordRequest};
+use lib::{
+    attachment::{PublicAttachment, RegisterAttachmentRequest},
+    config::Config,
+    route::v1,
+    test::TestClient,
+};
+
+#[actix_rt::test]
+async fn upload_download() {
+    // Setup the server and register a single client
+    let (mut app, client) = TestClient::setup().await;
+
+    // Create a password to attach the file to
+    let password =
+        RegisterPasswordRequest::new::<_, _, String, String>("test", "test_password", None, None);
+    let created: PublicPassword = client
+        .perform_encrypted_request_with_body(&mut app, v1::PASSWORD, Method::POST, &password)
+        .await;
+
+    // The first chunk describes the file
+    let description = serde_json::to_vec(&RegisterAttachmentRequest {
+        entry_id: created.id().to_string(),
+        name: "recovery-codes.txt".to_string(),
+    })
+    .unwrap();
+
+    // Upload the file in multiple chunks
+    let attachment: PublicAttachment = client
+        .perform_encrypted_chunked_request_with_body(
+            &mut app,
+            v1::ATTACHMENT,
+            Method::POST,
+            &[&description, b"first ", b"second ", b"third"],
+        )
+        .await;
+    assert_eq!(attachment.name, "recovery-codes.txt");
+    assert_eq!(attachment.size, 18);
+
+    // Verify it's in the list of attachments
+    let attachments: Vec<PublicAttachment> = client
+        .perform_encrypted_request(&mut app, v1::ATTACHMENT, Method::GET)
+        .await;
+    assert_eq!(attachments, vec![attachment.clone()]);
+
+    // Download the contents again
+    let contents = client
+        .perform_encrypted_chunked_request(
+            &mut app,
+            &format!("{}/{}", v1::ATTACHMENT, attachment.id),
+            Method::GET,
+        )
+        .await;
+    assert_eq!(contents, b"first second third");
+
+    // Delete the attachment
+    let _: () = client
+        .perform_encrypted_request(
+            &mut app,
+            &format!("{}/{}", v1::ATTACHMENT, attachment.id),
+            Method::DELETE,
+        )
+        .await;
+    let attachments: Vec<PublicAttachment> = client
+        .perform_encrypted_request(&mut app, v1::ATTACHMENT, Method::GET)
+        .await;
+    assert!(attachments.is_empty());
+}
+
+#[actix_rt::test]
+async fn missing_entry() {
+    // Setup the server and register a single client
+    let (mut app, client) = TestClient::setup().await;
+
+    let description = serde_json::to_vec(&RegisterAttachmentRequest {
+        entry_id: "non-existing".to_string(),
+        name: "file.txt".to_string(),
+    })
+    .unwrap();
+
+    // Attaching to an entry that doesn't exist should fail
+    let status = client
+        .perform_encrypted_chunked_request_with_body_status(
+            &mut app,
+            v1::ATTACHMENT,
+            Method::POST,
+            &[&description, b"contents"],
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn quota() {
+    // Setup the server with room for 10 bytes of attachments
+    let config = Config::from_raw_str("[attachments]\nmax_size = 8\nquota = 10").unwrap();
+    let (mut app, client) = TestClient::setup_with_config(config).await;
+
+    let password =
+        RegisterPasswordRequest::new::<_, _, String, String>("test", "test_password", None, None);
+    let created: PublicPassword = client
+        .perform_encrypted_request_with_body(&mut app, v1::PASSWORD, Method::POST, &password)
+        .await;
+    let description = serde_json::to_vec(&RegisterAttachmentRequest {
+        entry_id: created.id().to_string(),
+        name: "file.txt".to_string(),
+    })
+    .unwrap();
+
+    // A single file can't be larger than the maximum size
+    let status = client
+        .perform_encrypted_chunked_request_with_body_status(
+            &mut app,
+            v1::ATTACHMENT,
+            Method::POST,
+            &[&description, b"12345", b"6789"],
+        )
+        .await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    // The rejected file doesn't count towards the quota
+    let _: PublicAttachment = client
+        .perform_encrypted_chunked_request_with_body(
+            &mut app,
+            v1::ATTACHMENT,
+            Method::POST,
+            &[&description, b"12345", b"678"],
+        )
+        .await;
+
+    // But the stored file does
+    let status = client
+        .perform_encrypted_chunked_request_with_body_status(
+            &mut app,
+            v1::ATTACHMENT,
+            Method::POST,
+            &[&description, b"123"],
+        )
+        .await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    let _: PublicAttachment = client
+        .perform_encrypted_chunked_request_with_body(
+            &mut app,
+            v1::ATTACHMENT,
+            Method::POST,
+            &[&description, b"12"],
+        )
+        .await;
+}
+
+#[actix_rt::test]
+async fn delete_entry() {
+    // Setup the server and register a single client
+    let (mut app, client) = TestClient::setup().await;
+
+    let password =
+        RegisterPasswordRequest::new::<_, _, String, String>("test", "test_password", None, None);
+    let created: PublicPassword = client
+        .perform_encrypted_request_with_body(&mut app, v1::PASSWORD, Method::POST, &password)
+        .await;
+    let description = serde_json::to_vec(&RegisterAttachmentRequest {
+        entry_id: created.id().to_string(),
+        name: "file.txt".to_string(),
+    })
+    .unwrap();
+    let _: PublicAttachment = client
+        .perform_encrypted_chunked_request_with_body(
+            &mut app,
+            v1::ATTACHMENT,
+            Method::POST,
+            &[&description, b"contents"],
+        )
+        .await;
+
+    // Deleting the entry deletes its attachments
+    let _: () = client
+        .perform_encrypted_request(
+            &mut app,
+            &format!("{}/{}", v1::ITEM, created.id()),
+            Method::DELETE,
+        )
+        .await;
+    let attachments: Vec<PublicAttachment> = client
+        .perform_encrypted_request(&mut app, v1::ATTACHMENT, Method::GET)
+        .await;
+    assert!(attachments.is_empty());
+}
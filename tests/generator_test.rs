@@ -0,0 +1,67 @@
+use actix_web::http::Method;
+use keybear_core::types::{PasswordResponse, PublicPassword};
+use lib::{
+    generator::{
+        GenerateKind, GeneratePasswordRequest, GeneratePasswordResponse, PassphraseOptions,
+        RandomOptions, StoreGenerated,
+    },
+    route::v1,
+    test::TestClient,
+};
+
+#[actix_rt::test]
+async fn generate() {
+    // Setup the server and register a single client
+    let (mut app, client) = TestClient::setup().await;
+
+    // Generate a random password without storing it
+    let request = GeneratePasswordRequest {
+        kind: GenerateKind::Random(RandomOptions {
+            length: 32,
+            ..RandomOptions::default()
+        }),
+        store: None,
+    };
+    let generated: GeneratePasswordResponse = client
+        .perform_encrypted_request_with_body(&mut app, v1::GENERATE, Method::POST, &request)
+        .await;
+    assert_eq!(generated.password.len(), 32);
+    assert!(generated.stored.is_none());
+
+    // Nothing should be stored
+    let passwords: Vec<PublicPassword> = client
+        .perform_encrypted_request(&mut app, v1::PASSWORD, Method::GET)
+        .await;
+    assert!(passwords.is_empty());
+}
+
+#[actix_rt::test]
+async fn generate_and_store() {
+    // Setup the server and register a single client
+    let (mut app, client) = TestClient::setup().await;
+
+    // Generate a passphrase and store it directly
+    let request = GeneratePasswordRequest {
+        kind: GenerateKind::Passphrase(PassphraseOptions::default()),
+        store: Some(StoreGenerated {
+            name: "test".to_string(),
+            email: None,
+            website: Some("example.com".to_string()),
+        }),
+    };
+    let generated: GeneratePasswordResponse = client
+        .perform_encrypted_request_with_body(&mut app, v1::GENERATE, Method::POST, &request)
+        .await;
+    let stored = generated.stored.unwrap();
+    assert_eq!(stored.name(), "test");
+
+    // The stored password must be the generated one
+    let stored_password: PasswordResponse = client
+        .perform_encrypted_request(
+            &mut app,
+            &format!("{}/{}", v1::PASSWORD, stored.id()),
+            Method::GET,
+        )
+        .await;
+    assert_eq!(stored_password.password(), generated.password);
+}
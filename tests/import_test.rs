@@ -0,0 +1,121 @@
+use actix_web::http::Method;
+use keybear_core::types::PublicPassword;
+use lib::{
+    import::{ImportFormat, ImportReport, ImportRequest},
+    item::{ItemResponse, PublicItem},
+    password::{PasswordDetails, PasswordSummary},
+    route::v1,
+    test::TestClient,
+};
+
+const BITWARDEN_EXPORT: &str = r#"{
+    "encrypted": false,
+    "folders": [{ "id": "f1", "name": "Work" }],
+    "items": [
+        {
+            "type": 1,
+            "name": "GitHub",
+            "notes": "Recovery codes are in the safe",
+            "folderId": "f1",
+            "login": {
+                "uris": [{ "match": null, "uri": "https://github.com" }],
+                "username": "user",
+                "password": "hunter2",
+                "totp": "JBSWY3DPEHPK3PXP"
+            }
+        },
+        {
+            "type": 3,
+            "name": "Visa",
+            "card": { "cardholderName": "Jane Doe", "number": "4111111111111111", "expMonth": "7", "expYear": "2031" }
+        },
+        { "type": 2, "name": "Note", "notes": "Secret", "secureNote": { "type": 0 } }
+    ]
+}"#;
+
+#[actix_rt::test]
+async fn import_bitwarden() {
+    // Setup the server and register a single client
+    let (mut app, client) = TestClient::setup().await;
+
+    let mut request = ImportRequest {
+        format: ImportFormat::Bitwarden,
+        data: BITWARDEN_EXPORT.to_string(),
+        master_password: None,
+        dry_run: true,
+    };
+
+    // A dry run only reports what would happen
+    let report: ImportReport = client
+        .perform_encrypted_request_with_body(&mut app, v1::IMPORT, Method::POST, &request)
+        .await;
+    assert!(report.dry_run);
+    assert_eq!(report.created.len(), 1);
+    assert_eq!(report.created_items.len(), 2);
+    assert!(report.skipped.is_empty());
+
+    let passwords: Vec<PublicPassword> = client
+        .perform_encrypted_request(&mut app, v1::PASSWORD, Method::GET)
+        .await;
+    assert!(passwords.is_empty());
+
+    // Import it for real
+    request.dry_run = false;
+    let report: ImportReport = client
+        .perform_encrypted_request_with_body(&mut app, v1::IMPORT, Method::POST, &request)
+        .await;
+    assert_eq!(report.created.len(), 1);
+    assert_eq!(report.created_items.len(), 2);
+
+    // The information that doesn't fit in the core types is also available
+    let passwords: Vec<PasswordSummary> = client
+        .perform_encrypted_request(&mut app, v1::PASSWORD, Method::GET)
+        .await;
+    assert_eq!(passwords.len(), 1);
+    assert_eq!(passwords[0].name(), "GitHub");
+    assert_eq!(passwords[0].username.as_deref(), Some("user"));
+    assert_eq!(passwords[0].folder.as_deref(), Some("Work"));
+
+    let password: PasswordDetails = client
+        .perform_encrypted_request(
+            &mut app,
+            &format!("{}/{}", v1::PASSWORD, passwords[0].id()),
+            Method::GET,
+        )
+        .await;
+    assert_eq!(password.password(), "hunter2");
+    assert_eq!(password.totp.as_deref(), Some("JBSWY3DPEHPK3PXP"));
+    assert_eq!(
+        password.notes.as_deref(),
+        Some("Recovery codes are in the safe")
+    );
+
+    let items: Vec<PublicItem> = client
+        .perform_encrypted_request(&mut app, &format!("{}?type=card", v1::ITEM), Method::GET)
+        .await;
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].name, "Visa");
+
+    // Secure notes are kept as note items
+    let items: Vec<PublicItem> = client
+        .perform_encrypted_request(&mut app, &format!("{}?type=note", v1::ITEM), Method::GET)
+        .await;
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].name, "Note");
+    let note: ItemResponse = client
+        .perform_encrypted_request(
+            &mut app,
+            &format!("{}/{}", v1::ITEM, items[0].id),
+            Method::GET,
+        )
+        .await;
+    assert_eq!(note.secrets["text"], "Secret");
+
+    // Importing it again skips the existing entry
+    let report: ImportReport = client
+        .perform_encrypted_request_with_body(&mut app, v1::IMPORT, Method::POST, &request)
+        .await;
+    assert!(report.created.is_empty());
+    assert!(report.created_items.is_empty());
+    assert_eq!(report.skipped.len(), 3);
+}
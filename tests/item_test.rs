@@ -0,0 +1,175 @@
+use actix_web::http::Method;
+use keybear_core::types::{PasswordResponse, PublicPassword};
+use lib::{
+    item::{
+        kind::{Card, ItemKind, ItemType, Login, WifiNetwork, WifiSecurity},
+        ItemResponse, PublicItem, RegisterItemRequest,
+    },
+    route::v1,
+    test::TestClient,
+};
+
+#[actix_rt::test]
+async fn save() {
+    // Setup the server and register a single client
+    let (mut app, client) = TestClient::setup().await;
+
+    // Create a card to save
+    let card = RegisterItemRequest {
+        name: "test".to_string(),
+        kind: ItemKind::Card(Card {
+            cardholder: "J. Doe".to_string(),
+            number: "4111 1111 1111 1111".to_string(),
+            expiry_month: 4,
+            expiry_year: 2030,
+            security_code: Some("123".to_string()),
+        }),
+    };
+
+    // Save the card
+    let created: PublicItem = client
+        .perform_encrypted_request_with_body(&mut app, v1::ITEM, Method::POST, &card)
+        .await;
+    assert_eq!(created.name, card.name);
+    assert_eq!(created.item_type, ItemType::Card);
+    // The card number must not be in the public fields
+    assert!(!created.fields.values().any(|value| value.contains("4111")));
+
+    // Save a Wi-Fi network
+    let wifi = RegisterItemRequest {
+        name: "home".to_string(),
+        kind: ItemKind::WifiNetwork(WifiNetwork {
+            ssid: "home".to_string(),
+            security: WifiSecurity::Wpa2,
+            password: Some("secret password".to_string()),
+        }),
+    };
+    let _: PublicItem = client
+        .perform_encrypted_request_with_body(&mut app, v1::ITEM, Method::POST, &wifi)
+        .await;
+
+    // Verify both are in the list of items
+    let items: Vec<PublicItem> = client
+        .perform_encrypted_request(&mut app, v1::ITEM, Method::GET)
+        .await;
+    assert_eq!(items.len(), 2);
+
+    // Filter the items by type
+    let items: Vec<PublicItem> = client
+        .perform_encrypted_request(&mut app, &format!("{}?type=card", v1::ITEM), Method::GET)
+        .await;
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].id, created.id);
+
+    // Get the secret fields by the ID
+    let stored: ItemResponse = client
+        .perform_encrypted_request(
+            &mut app,
+            &format!("{}/{}", v1::ITEM, created.id),
+            Method::GET,
+        )
+        .await;
+    assert_eq!(stored.secrets["number"], "4111111111111111");
+
+    // Delete the card
+    let _: () = client
+        .perform_encrypted_request(
+            &mut app,
+            &format!("{}/{}", v1::ITEM, created.id),
+            Method::DELETE,
+        )
+        .await;
+    let items: Vec<PublicItem> = client
+        .perform_encrypted_request(&mut app, v1::ITEM, Method::GET)
+        .await;
+    assert_eq!(items.len(), 1);
+}
+
+#[actix_rt::test]
+async fn login_is_password() {
+    // Setup the server and register a single client
+    let (mut app, client) = TestClient::setup().await;
+
+    // Save a login as an item
+    let login = RegisterItemRequest {
+        name: "github".to_string(),
+        kind: ItemKind::Login(Login {
+            username: Some("octocat".to_string()),
+            email: None,
+            website: Some("https://github.com".to_string()),
+            password: "test_password".to_string(),
+            totp: None,
+        }),
+    };
+    let created: PublicItem = client
+        .perform_encrypted_request_with_body(&mut app, v1::ITEM, Method::POST, &login)
+        .await;
+    assert_eq!(created.item_type, ItemType::Login);
+
+    // It's the same entry as the password
+    let passwords: Vec<PublicPassword> = client
+        .perform_encrypted_request(&mut app, v1::PASSWORD, Method::GET)
+        .await;
+    assert_eq!(passwords.len(), 1);
+    assert_eq!(passwords[0].id(), created.id);
+    let password: PasswordResponse = client
+        .perform_encrypted_request(
+            &mut app,
+            &format!("{}/{}", v1::PASSWORD, created.id),
+            Method::GET,
+        )
+        .await;
+    assert_eq!(password.password(), "test_password");
+
+    // And the password is shown as a login
+    let items: Vec<PublicItem> = client
+        .perform_encrypted_request(&mut app, &format!("{}?type=login", v1::ITEM), Method::GET)
+        .await;
+    assert_eq!(items, vec![created.clone()]);
+    assert_eq!(items[0].fields["username"], "octocat");
+    let stored: ItemResponse = client
+        .perform_encrypted_request(
+            &mut app,
+            &format!("{}/{}", v1::ITEM, created.id),
+            Method::GET,
+        )
+        .await;
+    assert_eq!(stored.secrets["password"], "test_password");
+
+    // Deleting the item deletes the password
+    let _: () = client
+        .perform_encrypted_request(
+            &mut app,
+            &format!("{}/{}", v1::ITEM, created.id),
+            Method::DELETE,
+        )
+        .await;
+    let passwords: Vec<PublicPassword> = client
+        .perform_encrypted_request(&mut app, v1::PASSWORD, Method::GET)
+        .await;
+    assert!(passwords.is_empty());
+}
+
+#[actix_rt::test]
+#[should_panic]
+async fn invalid() {
+    // Setup the server and register a single client
+    let (mut app, client) = TestClient::setup().await;
+
+    // A card with an invalid checksum
+    let card = RegisterItemRequest {
+        name: "test".to_string(),
+        kind: ItemKind::Card(Card {
+            cardholder: "J. Doe".to_string(),
+            number: "4111 1111 1111 1112".to_string(),
+            expiry_month: 4,
+            expiry_year: 2030,
+            security_code: None,
+        }),
+    };
+
+    // Saving should be rejected
+    let _: PublicItem = client
+        .perform_encrypted_request_with_body(&mut app, v1::ITEM, Method::POST, &card)
+        .await;
+}
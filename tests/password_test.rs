@@ -1,9 +1,10 @@
 use actix_web::http::Method;
-use keybear_core::{
+use keybear_core::types::{PasswordResponse, PublicPassword, RegisterPasswordRequest};
+use lib::{
+    report::{Finding, ReportRequest, VaultReport},
     route::v1,
-    types::{PasswordResponse, PublicPassword, RegisterPasswordRequest},
+    test::TestClient,
 };
-use lib::test::TestClient;
 
 #[actix_rt::test]
 async fn save() {
@@ -37,3 +38,51 @@ async fn save() {
         .await;
     assert_eq!(stored_password.password(), password.password());
 }
+
+#[actix_rt::test]
+async fn report() {
+    // Setup the server and register a single client
+    let (mut app, client) = TestClient::setup().await;
+
+    // Save the same weak password twice
+    for name in &["first", "second"] {
+        let password =
+            RegisterPasswordRequest::new::<_, _, String, String>(*name, "123456", None, None);
+        let _: PublicPassword = client
+            .perform_encrypted_request_with_body(&mut app, v1::PASSWORD, Method::POST, &password)
+            .await;
+    }
+
+    // Save a strong password for a website supporting one time passwords without one
+    let password = RegisterPasswordRequest::new::<_, _, String, _>(
+        "github",
+        "Tr0ub4dor&3horse!",
+        None,
+        Some("https://github.com"),
+    );
+    let github: PublicPassword = client
+        .perform_encrypted_request_with_body(&mut app, v1::PASSWORD, Method::POST, &password)
+        .await;
+
+    // Get the health report
+    let report: VaultReport = client
+        .perform_encrypted_request_with_body(
+            &mut app,
+            v1::REPORT,
+            Method::POST,
+            &ReportRequest::default(),
+        )
+        .await;
+    assert_eq!(report.entries.len(), 3);
+    assert_eq!(report.weak, 2);
+    assert_eq!(report.reused, 2);
+    assert_eq!(report.old, 0);
+    assert_eq!(report.missing_totp, 1);
+
+    let entry = report
+        .entries
+        .iter()
+        .find(|entry| entry.id == github.id())
+        .unwrap();
+    assert_eq!(entry.findings, vec![Finding::MissingTotp]);
+}